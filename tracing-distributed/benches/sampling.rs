@@ -0,0 +1,105 @@
+//! Measures the overhead of tracing a request in sampled and unsampled traces, using a visitor
+//! that formats fields into strings as most backends do.
+//!
+//! Run with `cargo bench -p tracing-distributed`.
+
+// benches are only run by contributors, using recent toolchains
+#![allow(clippy::incompatible_msrv)]
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+use tracing::field::{Field, Visit};
+use tracing_distributed::{
+    register_dist_tracing_root, AlwaysSample, Event, NeverSample, Sampler, Span, Telemetry,
+    TelemetryLayer,
+};
+use tracing_subscriber::layer::Layer;
+use tracing_subscriber::registry;
+
+type TraceId = u64;
+type SpanId = tracing::Id;
+
+#[derive(Default)]
+struct StringVisitor(HashMap<&'static str, String>);
+
+impl Visit for StringVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name(), format!("{:?}", value));
+    }
+}
+
+struct DiscardingTelemetry;
+
+impl Telemetry for DiscardingTelemetry {
+    type Visitor = StringVisitor;
+    type TraceId = TraceId;
+    type SpanId = SpanId;
+
+    fn mk_visitor(&self) -> Self::Visitor {
+        StringVisitor::default()
+    }
+
+    fn report_span(&self, span: Span<Self::Visitor, SpanId, TraceId>) {
+        drop(std::hint::black_box(span));
+    }
+
+    fn report_event(&self, event: Event<Self::Visitor, SpanId, TraceId>) {
+        drop(std::hint::black_box(event));
+    }
+}
+
+#[derive(Debug)]
+struct Request {
+    route: &'static str,
+    user_id: u64,
+    headers: Vec<(&'static str, &'static str)>,
+}
+
+fn handle_request(trace_id: TraceId, request: &Request) {
+    let root = tracing::info_span!("request", route = request.route);
+    let _guard = root.enter();
+    register_dist_tracing_root::<SpanId, TraceId>(trace_id, None).unwrap();
+
+    for step in 0..10 {
+        let span =
+            tracing::info_span!("step", step, request = ?request, result = tracing::field::Empty);
+        let _guard = span.enter();
+        tracing::info!(user_id = request.user_id, headers = ?request.headers, "processing");
+        span.record("result", tracing::field::debug(&request.headers));
+    }
+}
+
+fn bench<S: 'static + Sampler<TraceId>>(name: &str, sampler: S) {
+    let layer =
+        TelemetryLayer::new("bench_svc_name", DiscardingTelemetry, |x| x).with_sampler(sampler);
+    let subscriber = layer.with_subscriber(registry::Registry::default());
+    let request = Request {
+        route: "/users/:id",
+        user_id: 135,
+        headers: vec![("accept", "application/json"), ("user-agent", "bench")],
+    };
+
+    tracing::subscriber::with_default(subscriber, || {
+        // warm up, then run for roughly a second
+        for trace_id in 0..1_000 {
+            handle_request(trace_id, &request);
+        }
+
+        let start = Instant::now();
+        let mut iterations = 0;
+        while start.elapsed() < Duration::from_secs(1) {
+            for _ in 0..100 {
+                handle_request(iterations, &request);
+                iterations += 1;
+            }
+        }
+        let per_iteration = start.elapsed() / iterations as u32;
+        println!("{:<10} {:>8} ns/request", name, per_iteration.as_nanos());
+    });
+}
+
+fn main() {
+    bench("sampled", AlwaysSample);
+    bench("unsampled", NeverSample);
+}
@@ -0,0 +1,355 @@
+use crate::propagation::{Carrier, IdCodec, Propagator, RemoteTraceCtx};
+use rand::Rng;
+use std::marker::PhantomData;
+
+/// Name of the header carrying a single-header B3 value: `{TraceId}-{SpanId}-{SamplingState}-{ParentSpanId}`.
+pub const B3_SINGLE_HEADER: &str = "b3";
+
+/// Name of the multi-header B3 header carrying the trace id.
+pub const B3_TRACE_ID_HEADER: &str = "x-b3-traceid";
+
+/// Name of the multi-header B3 header carrying the span id.
+pub const B3_SPAN_ID_HEADER: &str = "x-b3-spanid";
+
+/// Name of the multi-header B3 header carrying the sampling decision.
+pub const B3_SAMPLED_HEADER: &str = "x-b3-sampled";
+
+/// Name of the multi-header B3 header carrying the debug flag, which implies a positive sampling decision.
+pub const B3_FLAGS_HEADER: &str = "x-b3-flags";
+
+/// Which headers a `B3Propagator` writes when injecting trace context.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub enum B3Encoding {
+    /// The `b3` header only.
+    SingleHeader,
+    /// The `X-B3-TraceId`, `X-B3-SpanId` and `X-B3-Sampled` headers only.
+    MultipleHeader,
+    /// Both the single-header and multi-header formats, for peers that only understand one of them.
+    SingleAndMultipleHeader,
+}
+
+/// `Propagator` using the [B3](https://github.com/openzipkin/b3-propagation) headers used by Zipkin
+/// and Envoy, with ids converted using `C`.
+///
+/// Extracts from the `b3` header if present and valid, falling back to the multi-header format. Both 64-bit
+/// and 128-bit trace ids are supported: trace ids with the upper 64 bits unset are injected as 64-bit ids.
+///
+/// A `b3` header holding only a sampling decision overrides that of the multi-header format. As
+/// required by the B3 spec, a deny-only `b3: 0` header is honored even without any ids, by extracting
+/// an unsampled trace context with random ids.
+pub struct B3Propagator<C> {
+    encoding: B3Encoding,
+    codec: PhantomData<fn() -> C>,
+}
+
+impl<C> B3Propagator<C> {
+    /// Construct a `B3Propagator` that injects trace context using the provided encoding.
+    pub fn new(encoding: B3Encoding) -> Self {
+        B3Propagator {
+            encoding,
+            codec: PhantomData,
+        }
+    }
+}
+
+impl<C> Default for B3Propagator<C> {
+    /// Injects trace context using the multi-header format, which is the most widely supported.
+    fn default() -> Self {
+        B3Propagator::new(B3Encoding::MultipleHeader)
+    }
+}
+
+impl<C> std::fmt::Debug for B3Propagator<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("B3Propagator")
+            .field("encoding", &self.encoding)
+            .finish()
+    }
+}
+
+impl<C: IdCodec> Propagator<C::SpanId, C::TraceId> for B3Propagator<C> {
+    fn inject(&self, ctx: &RemoteTraceCtx<C::SpanId, C::TraceId>, carrier: &mut dyn Carrier) {
+        let trace_id = encode_trace_id(C::encode_trace_id(&ctx.trace_id));
+        let span_id = format!("{:016x}", C::encode_span_id(&ctx.span_id));
+        let sampled = ctx.sampled.map(|sampled| if sampled { "1" } else { "0" });
+
+        if self.encoding != B3Encoding::MultipleHeader {
+            let value = match sampled {
+                Some(sampled) => format!("{}-{}-{}", trace_id, span_id, sampled),
+                None => format!("{}-{}", trace_id, span_id),
+            };
+            carrier.set(B3_SINGLE_HEADER, value);
+        }
+
+        if self.encoding != B3Encoding::SingleHeader {
+            carrier.set(B3_TRACE_ID_HEADER, trace_id);
+            carrier.set(B3_SPAN_ID_HEADER, span_id);
+            if let Some(sampled) = sampled {
+                carrier.set(B3_SAMPLED_HEADER, sampled.to_string());
+            }
+        }
+    }
+
+    fn extract(&self, carrier: &dyn Carrier) -> Option<RemoteTraceCtx<C::SpanId, C::TraceId>> {
+        let single_header = carrier.get(B3_SINGLE_HEADER).map(str::trim);
+        let (trace_id, span_id, sampled) = match single_header.and_then(extract_single_header) {
+            Some(ctx) => ctx,
+            None => match extract_multiple_header(carrier) {
+                Some((trace_id, span_id, sampled)) => {
+                    let single_sampled = single_header.and_then(parse_sampling_state);
+                    (trace_id, span_id, single_sampled.or(sampled))
+                }
+                None if single_header == Some("0") => {
+                    let mut rng = rand::thread_rng();
+                    let trace_id = rng.gen::<u128>().max(1);
+                    let span_id = rng.gen::<u64>().max(1);
+                    (trace_id, span_id, Some(false))
+                }
+                None => return None,
+            },
+        };
+
+        Some(RemoteTraceCtx {
+            sampled,
+            ..RemoteTraceCtx::new(C::decode_trace_id(trace_id), C::decode_span_id(span_id))
+        })
+    }
+}
+
+fn encode_trace_id(trace_id: u128) -> String {
+    if trace_id >> 64 == 0 {
+        format!("{:016x}", trace_id)
+    } else {
+        format!("{:032x}", trace_id)
+    }
+}
+
+// a single header holding only a sampling decision (eg `b3: 0`) can't be used to continue a trace
+fn extract_single_header(value: &str) -> Option<(u128, u64, Option<bool>)> {
+    let mut iter = value.split('-');
+    let trace_id = parse_trace_id(iter.next()?)?;
+    let span_id = parse_span_id(iter.next()?)?;
+    let sampled = match iter.next() {
+        None => None,
+        Some(state) => Some(parse_sampling_state(state)?),
+    };
+
+    Some((trace_id, span_id, sampled))
+}
+
+// the debug flag (`d`) implies a positive sampling decision
+fn parse_sampling_state(s: &str) -> Option<bool> {
+    match s {
+        "1" | "d" => Some(true),
+        "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn extract_multiple_header(carrier: &dyn Carrier) -> Option<(u128, u64, Option<bool>)> {
+    let trace_id = parse_trace_id(carrier.get(B3_TRACE_ID_HEADER)?.trim())?;
+    let span_id = parse_span_id(carrier.get(B3_SPAN_ID_HEADER)?.trim())?;
+    // the debug flag implies a positive sampling decision
+    let sampled = if carrier.get(B3_FLAGS_HEADER).map(str::trim) == Some("1") {
+        Some(true)
+    } else {
+        // older implementations send `true` and `false`
+        match carrier.get(B3_SAMPLED_HEADER).map(str::trim) {
+            Some("1") | Some("true") => Some(true),
+            Some("0") | Some("false") => Some(false),
+            _ => None,
+        }
+    };
+
+    Some((trace_id, span_id, sampled))
+}
+
+fn parse_trace_id(s: &str) -> Option<u128> {
+    if (s.len() == 16 || s.len() == 32) && s.chars().all(|c| c.is_ascii_hexdigit()) {
+        u128::from_str_radix(s, 16).ok().filter(|u| *u != 0)
+    } else {
+        None
+    }
+}
+
+fn parse_span_id(s: &str) -> Option<u64> {
+    if s.len() == 16 && s.chars().all(|c| c.is_ascii_hexdigit()) {
+        u64::from_str_radix(s, 16).ok().filter(|u| *u != 0)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::propagation::extract_dist_tracing_root;
+    use crate::telemetry::test::{SpanId, TestTelemetry, TraceId};
+    use crate::TelemetryLayer;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tracing::instrument;
+    use tracing_subscriber::layer::Layer;
+    use tracing_subscriber::registry;
+
+    type TestB3Propagator = B3Propagator<TestTelemetry>;
+
+    fn carrier(headers: &[(&str, &str)]) -> HashMap<String, String> {
+        headers
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn extract(headers: &[(&str, &str)]) -> Option<RemoteTraceCtx<SpanId, TraceId>> {
+        TestB3Propagator::default().extract(&carrier(headers))
+    }
+
+    #[test]
+    fn extract_single_header() {
+        let ctx = extract(&[(B3_SINGLE_HEADER, "80f198ee56343ba8-e457b5a2e4d86bd1-1")]).unwrap();
+        assert_eq!(ctx.trace_id, 0x80f1_98ee_5634_3ba8);
+        assert_eq!(ctx.span_id.into_u64(), 0xe457_b5a2_e4d8_6bd1);
+        assert_eq!(ctx.sampled, Some(true));
+
+        let ctx = extract(&[(
+            B3_SINGLE_HEADER,
+            "463ac35c9f6413ad48485a3953bb6124-a2fb4a1d1a96d312-0-0020000000000001",
+        )])
+        .unwrap();
+        assert_eq!(ctx.span_id.into_u64(), 0xa2fb_4a1d_1a96_d312);
+        assert_eq!(ctx.sampled, Some(false));
+
+        let ctx = extract(&[(B3_SINGLE_HEADER, "80f198ee56343ba8-e457b5a2e4d86bd1-d")]).unwrap();
+        assert_eq!(ctx.sampled, Some(true));
+
+        let ctx = extract(&[(B3_SINGLE_HEADER, "80f198ee56343ba8-e457b5a2e4d86bd1")]).unwrap();
+        assert_eq!(ctx.sampled, None);
+
+        assert_eq!(extract(&[(B3_SINGLE_HEADER, "1")]), None);
+        assert_eq!(extract(&[(B3_SINGLE_HEADER, "80f198ee56343ba8")]), None);
+    }
+
+    #[test]
+    fn extract_falls_back_to_multiple_header() {
+        let multiple_header = [
+            (B3_TRACE_ID_HEADER, "80f198ee56343ba8"),
+            (B3_SPAN_ID_HEADER, "e457b5a2e4d86bd1"),
+            (B3_SAMPLED_HEADER, "1"),
+        ];
+
+        // an unparseable single header is ignored
+        let mut headers = multiple_header.to_vec();
+        headers.push((B3_SINGLE_HEADER, "not-a-b3-header"));
+        let ctx = extract(&headers).unwrap();
+        assert_eq!(ctx.trace_id, 0x80f1_98ee_5634_3ba8);
+        assert_eq!(ctx.span_id.into_u64(), 0xe457_b5a2_e4d8_6bd1);
+        assert_eq!(ctx.sampled, Some(true));
+
+        // a sampling-only single header overrides the multi-header sampling decision
+        let mut headers = multiple_header.to_vec();
+        headers.push((B3_SINGLE_HEADER, "0"));
+        let ctx = extract(&headers).unwrap();
+        assert_eq!(ctx.trace_id, 0x80f1_98ee_5634_3ba8);
+        assert_eq!(ctx.sampled, Some(false));
+    }
+
+    #[test]
+    fn extract_deny_only_header() {
+        let ctx = extract(&[(B3_SINGLE_HEADER, "0")]).unwrap();
+        assert_eq!(ctx.sampled, Some(false));
+    }
+
+    #[test]
+    fn extract_multiple_header() {
+        let ctx = extract(&[
+            (B3_TRACE_ID_HEADER, "463ac35c9f6413ad48485a3953bb6124"),
+            (B3_SPAN_ID_HEADER, "a2fb4a1d1a96d312"),
+            (B3_SAMPLED_HEADER, "0"),
+        ])
+        .unwrap();
+        assert_eq!(ctx.trace_id, 0x4848_5a39_53bb_6124);
+        assert_eq!(ctx.sampled, Some(false));
+
+        let ctx = extract(&[
+            (B3_TRACE_ID_HEADER, "80f198ee56343ba8"),
+            (B3_SPAN_ID_HEADER, "e457b5a2e4d86bd1"),
+            (B3_FLAGS_HEADER, "1"),
+        ])
+        .unwrap();
+        assert_eq!(ctx.sampled, Some(true));
+
+        assert_eq!(extract(&[(B3_TRACE_ID_HEADER, "80f198ee56343ba8")]), None);
+    }
+
+    #[test]
+    fn inject_encodings() {
+        let ctx = RemoteTraceCtx {
+            sampled: Some(false),
+            ..RemoteTraceCtx::new(135, tracing::Id::from_u64(246))
+        };
+
+        let mut single = HashMap::new();
+        TestB3Propagator::new(B3Encoding::SingleHeader).inject(&ctx, &mut single);
+        assert_eq!(
+            single,
+            carrier(&[(B3_SINGLE_HEADER, "0000000000000087-00000000000000f6-0")])
+        );
+
+        let mut multiple = HashMap::new();
+        TestB3Propagator::new(B3Encoding::MultipleHeader).inject(&ctx, &mut multiple);
+        assert_eq!(
+            multiple,
+            carrier(&[
+                (B3_TRACE_ID_HEADER, "0000000000000087"),
+                (B3_SPAN_ID_HEADER, "00000000000000f6"),
+                (B3_SAMPLED_HEADER, "0"),
+            ])
+        );
+
+        let mut both = HashMap::new();
+        TestB3Propagator::new(B3Encoding::SingleAndMultipleHeader).inject(&ctx, &mut both);
+        assert_eq!(both.len(), 4);
+    }
+
+    #[test]
+    fn unsampled_traces_are_not_reported() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let cap = TestTelemetry::new(spans.clone(), events.clone());
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        #[instrument]
+        fn f(sampled: &'static str) {
+            let incoming = carrier(&[(
+                B3_SINGLE_HEADER,
+                &format!("80f198ee56343ba8-e457b5a2e4d86bd1-{}", sampled),
+            )]);
+            extract_dist_tracing_root(&TestB3Propagator::default(), &incoming).unwrap();
+            g();
+        }
+
+        #[instrument]
+        fn g() {
+            tracing::info!("event");
+        }
+
+        #[instrument]
+        fn denied() {
+            let incoming = carrier(&[(B3_SINGLE_HEADER, "0")]);
+            extract_dist_tracing_root(&TestB3Propagator::default(), &incoming).unwrap();
+            g();
+        }
+
+        tracing::subscriber::with_default(subscriber, || {
+            f("0");
+            f("1");
+            denied();
+        });
+
+        // only the sampled trace is reported
+        assert_eq!(spans.lock().unwrap().len(), 2);
+        assert_eq!(events.lock().unwrap().len(), 1);
+    }
+}
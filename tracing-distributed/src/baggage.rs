@@ -0,0 +1,215 @@
+use crate::trace::{with_current_trace_ctx, TraceCtxError};
+use std::collections::BTreeMap;
+use std::iter::FromIterator;
+use std::sync::Arc;
+
+/// Name of the header carrying a W3C `baggage` value.
+pub const BAGGAGE_HEADER: &str = "baggage";
+
+/// Request-scoped key-value pairs (eg a tenant id or feature flag cohort) carried along with the
+/// context of a distributed trace and propagated across process boundaries.
+///
+/// Cheap to clone: entries are only copied when a clone is modified.
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Default)]
+pub struct Baggage(Arc<BTreeMap<String, String>>);
+
+impl Baggage {
+    /// Construct an empty `Baggage`.
+    pub fn new() -> Self {
+        Baggage::default()
+    }
+
+    /// Get the value associated with `key`, if any.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(|s| s.as_str())
+    }
+
+    /// Set the value associated with `key`, returning the previous value, if any.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        Arc::make_mut(&mut self.0).insert(key.into(), value.into())
+    }
+
+    /// Remove the value associated with `key`, returning it, if any.
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        if self.0.contains_key(key) {
+            Arc::make_mut(&mut self.0).remove(key)
+        } else {
+            None
+        }
+    }
+
+    /// Iterate over all entries, ordered by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns true if there are no entries.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // parses a w3c `baggage` header value, skipping malformed entries and ignoring entry properties
+    pub(crate) fn from_w3c_header(s: &str) -> Self {
+        s.split(',')
+            .filter_map(|entry| {
+                let key_value = entry.split(';').next()?;
+                let mut key_value = key_value.splitn(2, '=');
+                let (key, value) = (key_value.next()?, key_value.next()?);
+                let key = key.trim();
+                if key.is_empty() {
+                    return None;
+                }
+                Some((key.to_string(), percent_decode(value.trim())?))
+            })
+            .collect()
+    }
+
+    // encodes entries as a w3c `baggage` header value
+    pub(crate) fn to_w3c_header(&self) -> String {
+        self.iter()
+            .map(|(k, v)| format!("{}={}", k, percent_encode(v)))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for Baggage {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Baggage(Arc::new(
+            iter.into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+        ))
+    }
+}
+
+// the w3c spec allows any printable ascii other than whitespace, `"`, `,`, `;` and `\` in values
+fn percent_encode(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'!' | b'#'..=b'+' | b'-'..=b':' | b'<'..=b'[' | b']'..=b'~' if b != b'%' => {
+                res.push(b as char)
+            }
+            _ => res.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    res
+}
+
+pub(crate) fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Retrieve the baggage associated with the current span.
+pub fn current_baggage<SpanId, TraceId>() -> Result<Baggage, TraceCtxError>
+where
+    SpanId: 'static + Clone + Send + Sync,
+    TraceId: 'static + Clone + Send + Sync,
+{
+    with_current_trace_ctx::<SpanId, TraceId, _, _>(|trace_ctx| trace_ctx.baggage.clone())
+}
+
+/// Set a baggage entry on the current span. The entry is visible to the current span, to spans
+/// subsequently created within it and to any process the trace context is propagated to from them.
+pub fn set_baggage_item<SpanId, TraceId>(
+    key: impl Into<String>,
+    value: impl Into<String>,
+) -> Result<(), TraceCtxError>
+where
+    SpanId: 'static + Clone + Send + Sync,
+    TraceId: 'static + Clone + Send + Sync,
+{
+    with_current_trace_ctx::<SpanId, TraceId, _, _>(|trace_ctx| {
+        trace_ctx.baggage.insert(key, value);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test::{SpanId, TestTelemetry, TraceId};
+    use crate::{register_dist_tracing_root_with_baggage, TelemetryLayer};
+    use std::sync::Mutex;
+    use tracing::instrument;
+    use tracing_subscriber::layer::Layer;
+    use tracing_subscriber::registry;
+
+    #[test]
+    fn w3c_header_round_trip() {
+        let baggage: Baggage = vec![("tenant", "acme corp"), ("cohort", "50%,b;c")]
+            .into_iter()
+            .collect();
+        let header = baggage.to_w3c_header();
+        assert_eq!(header, "cohort=50%25%2Cb%3Bc,tenant=acme%20corp");
+        assert_eq!(Baggage::from_w3c_header(&header), baggage);
+
+        let baggage = Baggage::from_w3c_header(" a = 1 ;prop=x, =skipped,b=%zz, c=3");
+        assert_eq!(baggage, vec![("a", "1"), ("c", "3")].into_iter().collect());
+    }
+
+    #[test]
+    fn descendants_inherit_baggage() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let cap = TestTelemetry::new(spans.clone(), Arc::default());
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x).with_baggage_fields();
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        #[instrument]
+        fn root() {
+            let incoming = vec![("tenant", "acme")].into_iter().collect();
+            register_dist_tracing_root_with_baggage::<SpanId, TraceId>(135, None, incoming)
+                .unwrap();
+            child("a");
+            child("b");
+            assert_eq!(
+                current_baggage::<SpanId, TraceId>().unwrap(),
+                vec![("tenant", "acme")].into_iter().collect()
+            );
+        }
+
+        #[instrument]
+        fn child(cohort: &'static str) {
+            set_baggage_item::<SpanId, TraceId>("cohort", cohort).unwrap();
+            grandchild();
+        }
+
+        #[instrument]
+        fn grandchild() {
+            let baggage = current_baggage::<SpanId, TraceId>().unwrap();
+            assert_eq!(baggage.get("tenant"), Some("acme"));
+            assert!(baggage.get("cohort").is_some());
+        }
+
+        tracing::subscriber::with_default(subscriber, root);
+
+        let spans = spans.lock().unwrap();
+        let cohorts: Vec<Option<&str>> = spans
+            .iter()
+            .map(|span| span.baggage.get("cohort"))
+            .collect();
+        assert_eq!(
+            cohorts,
+            vec![Some("a"), Some("a"), Some("b"), Some("b"), None]
+        );
+        assert!(spans
+            .iter()
+            .all(|span| span.baggage.get("tenant") == Some("acme")));
+    }
+}
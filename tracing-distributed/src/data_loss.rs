@@ -0,0 +1,119 @@
+use crate::stats::BackendStats;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+/// Name of the synthetic records backends publish to report data loss, see `DataLossReporter`.
+pub const DATA_LOSS_RECORD_NAME: &str = "tracing.dropped_events";
+
+/// Number of spans and events a `Telemetry` instance lost since its previous data loss report, by reason.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, Default)]
+pub struct DataLoss {
+    /// Items dropped because some buffer was full.
+    pub buffer_full: u64,
+    /// Items that failed to export.
+    pub export_errors: u64,
+    /// Time elapsed since the previous data loss report (or since the reporter was constructed).
+    pub period: Duration,
+}
+
+impl DataLoss {
+    /// Total number of items lost.
+    pub fn total(&self) -> u64 {
+        self.buffer_full + self.export_errors
+    }
+}
+
+/// Tracks the data lost by a `Telemetry` instance, so that it can periodically publish a synthetic
+/// record (named `DATA_LOSS_RECORD_NAME`) describing that loss to its own backend, where it's
+/// visible alongside the surviving data.
+///
+/// Backends call `poll` with a function returning their current counters whenever they're able to publish data, eg
+/// after successfully exporting some span, and publish a record for any `DataLoss` returned.
+/// Sampling is not data loss, and data dropped after shutdown can't be reported, so both are ignored.
+#[derive(Debug)]
+pub struct DataLossReporter {
+    interval: Duration,
+    // when loss was last reported, along with the counters at that time
+    reported: Mutex<(Instant, BackendStats)>,
+}
+
+impl DataLossReporter {
+    /// Construct a `DataLossReporter` that reports data loss at most once per `interval`.
+    pub fn new(interval: Duration) -> Self {
+        DataLossReporter {
+            interval,
+            reported: Mutex::new((Instant::now(), BackendStats::default())),
+        }
+    }
+
+    /// Returns the data lost since the previous report, if any, given a function returning the
+    /// backend's current counters. Returns `None` if `interval` has not elapsed since the previous
+    /// report, in which case the counters are not computed.
+    pub fn poll<F: FnOnce() -> BackendStats>(&self, stats: F) -> Option<DataLoss> {
+        // succeed or die. failure is unrecoverable (mutex poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let mut reported = self.reported.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let mut reported = self.reported.lock();
+
+        let (reported_at, previous) = *reported;
+        let period = reported_at.elapsed();
+        if period < self.interval {
+            return None;
+        }
+
+        let stats = stats();
+        let loss = DataLoss {
+            buffer_full: stats
+                .dropped_buffer_full
+                .saturating_sub(previous.dropped_buffer_full),
+            export_errors: stats.export_errors.saturating_sub(previous.export_errors),
+            period,
+        };
+        if loss.total() == 0 {
+            return None;
+        }
+
+        *reported = (Instant::now(), stats);
+        Some(loss)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(dropped_buffer_full: u64, export_errors: u64) -> BackendStats {
+        BackendStats {
+            dropped_buffer_full,
+            export_errors,
+            ..BackendStats::default()
+        }
+    }
+
+    #[test]
+    fn reports_loss_since_previous_report() {
+        let reporter = DataLossReporter::new(Duration::from_millis(20));
+        // nothing is reported until the interval elapses
+        assert_eq!(
+            reporter.poll(|| panic!("counters computed before the interval elapsed")),
+            None
+        );
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(reporter.poll(|| stats(0, 0)), None);
+
+        let loss = reporter.poll(|| stats(3, 1)).unwrap();
+        assert_eq!((loss.buffer_full, loss.export_errors), (3, 1));
+        assert!(loss.period >= Duration::from_millis(20));
+
+        std::thread::sleep(Duration::from_millis(20));
+        // counters that haven't grown since the previous report aren't reported again
+        assert_eq!(reporter.poll(|| stats(3, 1)), None);
+        let loss = reporter.poll(|| stats(5, 1)).unwrap();
+        assert_eq!((loss.buffer_full, loss.export_errors), (2, 0));
+    }
+}
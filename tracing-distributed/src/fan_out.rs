@@ -0,0 +1,598 @@
+use crate::propagation::IdCodec;
+use crate::stats::BackendStats;
+use crate::telemetry::{FlushError, Telemetry};
+use crate::trace::{Event, Span};
+use std::fmt;
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+use tracing::field::{Field, Visit};
+
+/// Telemetry capability that publishes events and spans to two backends sharing the same `SpanId`
+/// and `TraceId` types, eg to dual-write during a migration. Nest `FanOutTelemetry` instances to
+/// publish to more than two backends.
+///
+/// Backends with different id types can be combined by wrapping some of them in an `IdAdapter`.
+#[derive(Debug)]
+pub struct FanOutTelemetry<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> FanOutTelemetry<A, B> {
+    /// Construct a `FanOutTelemetry` that publishes to both of the provided `Telemetry` instances.
+    pub fn new(first: A, second: B) -> Self {
+        FanOutTelemetry { first, second }
+    }
+}
+
+/// Visitor that records tracing fields using the visitors of both backends of a `FanOutTelemetry`.
+#[derive(Debug)]
+pub struct FanOutVisitor<A, B>(pub A, pub B);
+
+impl<A: Visit, B: Visit> Visit for FanOutVisitor<A, B> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.record_i64(field, value);
+        self.1.record_i64(field, value);
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.record_u64(field, value);
+        self.1.record_u64(field, value);
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.record_bool(field, value);
+        self.1.record_bool(field, value);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.record_str(field, value);
+        self.1.record_str(field, value);
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.0.record_error(field, value);
+        self.1.record_error(field, value);
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.record_debug(field, value);
+        self.1.record_debug(field, value);
+    }
+}
+
+impl<A, B, SpanId, TraceId> Telemetry for FanOutTelemetry<A, B>
+where
+    A: Telemetry<SpanId = SpanId, TraceId = TraceId>,
+    B: Telemetry<SpanId = SpanId, TraceId = TraceId>,
+    SpanId: Send + Sync + Clone,
+    TraceId: Send + Sync + Clone,
+{
+    type Visitor = FanOutVisitor<A::Visitor, B::Visitor>;
+    type TraceId = TraceId;
+    type SpanId = SpanId;
+
+    fn mk_visitor(&self) -> Self::Visitor {
+        FanOutVisitor(self.first.mk_visitor(), self.second.mk_visitor())
+    }
+
+    fn report_span(&self, span: Span<Self::Visitor, SpanId, TraceId>) {
+        let skeleton = map_span(&span, SpanId::clone, TraceId::clone);
+        let FanOutVisitor(first, second) = span.values;
+        self.first
+            .report_span(span_with_values(skeleton.clone(), first));
+        self.second.report_span(span_with_values(skeleton, second));
+    }
+
+    fn report_event(&self, event: Event<Self::Visitor, SpanId, TraceId>) {
+        let skeleton = map_event(&event, SpanId::clone, TraceId::clone);
+        let FanOutVisitor(first, second) = event.values;
+        self.first
+            .report_event(event_with_values(skeleton.clone(), first));
+        self.second
+            .report_event(event_with_values(skeleton, second));
+    }
+
+    // both backends are flushed even if the first fails, sharing the timeout
+    fn flush(&self, timeout: Duration) -> Result<(), FlushError> {
+        let deadline = Instant::now() + timeout;
+        let first = self.first.flush(timeout);
+        let second = self
+            .second
+            .flush(deadline.saturating_duration_since(Instant::now()));
+        first.and(second)
+    }
+
+    fn shutdown(&self, timeout: Duration) -> Result<(), FlushError> {
+        let deadline = Instant::now() + timeout;
+        let first = self.first.shutdown(timeout);
+        let second = self
+            .second
+            .shutdown(deadline.saturating_duration_since(Instant::now()));
+        first.and(second)
+    }
+
+    fn stats(&self) -> BackendStats {
+        self.first.stats().combine(self.second.stats())
+    }
+}
+
+/// Telemetry capability that publishes events and spans using the `SpanId` and `TraceId` types
+/// of `C` to some backend using different id types, converting ids via their fixed-width
+/// representations. Both `C` and the wrapped backend must implement `IdCodec`.
+///
+/// For example, `IdAdapter<HoneycombTelemetry, OpenTelemetry>` publishes spans reported by a
+/// `TelemetryLayer` using opentelemetry ids to honeycomb.io, such that both backends publish
+/// the same trace and span ids when combined via `FanOutTelemetry`.
+pub struct IdAdapter<T, C> {
+    telemetry: T,
+    codec: PhantomData<fn() -> C>,
+}
+
+impl<T, C> IdAdapter<T, C> {
+    /// Construct an `IdAdapter` that publishes to the provided `Telemetry` instance.
+    pub fn new(telemetry: T) -> Self {
+        IdAdapter {
+            telemetry,
+            codec: PhantomData,
+        }
+    }
+}
+
+impl<T: fmt::Debug, C> fmt::Debug for IdAdapter<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IdAdapter")
+            .field("telemetry", &self.telemetry)
+            .finish()
+    }
+}
+
+impl<T, C> Telemetry for IdAdapter<T, C>
+where
+    T: Telemetry + IdCodec<SpanId = <T as Telemetry>::SpanId, TraceId = <T as Telemetry>::TraceId>,
+    C: IdCodec,
+    C::SpanId: Send + Sync + Clone,
+    C::TraceId: Send + Sync + Clone,
+{
+    type Visitor = T::Visitor;
+    type TraceId = C::TraceId;
+    type SpanId = C::SpanId;
+
+    fn mk_visitor(&self) -> Self::Visitor {
+        self.telemetry.mk_visitor()
+    }
+
+    fn report_span(&self, span: Span<Self::Visitor, C::SpanId, C::TraceId>) {
+        let skeleton = map_span(&span, convert_span_id::<C, T>, convert_trace_id::<C, T>);
+        self.telemetry
+            .report_span(span_with_values(skeleton, span.values));
+    }
+
+    fn report_event(&self, event: Event<Self::Visitor, C::SpanId, C::TraceId>) {
+        let skeleton = map_event(&event, convert_span_id::<C, T>, convert_trace_id::<C, T>);
+        self.telemetry
+            .report_event(event_with_values(skeleton, event.values));
+    }
+
+    fn flush(&self, timeout: Duration) -> Result<(), FlushError> {
+        self.telemetry.flush(timeout)
+    }
+
+    fn shutdown(&self, timeout: Duration) -> Result<(), FlushError> {
+        self.telemetry.shutdown(timeout)
+    }
+
+    fn stats(&self) -> BackendStats {
+        self.telemetry.stats()
+    }
+}
+
+fn convert_span_id<From: IdCodec, To: IdCodec>(span_id: &From::SpanId) -> To::SpanId {
+    To::decode_span_id(From::encode_span_id(span_id))
+}
+
+fn convert_trace_id<From: IdCodec, To: IdCodec>(trace_id: &From::TraceId) -> To::TraceId {
+    To::decode_trace_id(From::encode_trace_id(trace_id))
+}
+
+// copies all fields other than values, which are left empty to be filled in by the caller
+fn map_span<V, S1, T1, S2, T2>(
+    span: &Span<V, S1, T1>,
+    map_span_id: impl Fn(&S1) -> S2,
+    map_trace_id: impl Fn(&T1) -> T2,
+) -> Span<(), S2, T2> {
+    Span {
+        id: map_span_id(&span.id),
+        trace_id: map_trace_id(&span.trace_id),
+        parent_id: span.parent_id.as_ref().map(&map_span_id),
+        local_root: span.local_root,
+        follows_from: span
+            .follows_from
+            .iter()
+            .map(|(trace_id, span_id)| (map_trace_id(trace_id), map_span_id(span_id)))
+            .collect(),
+        initialized_at: span.initialized_at,
+        completed_at: span.completed_at,
+        busy: span.busy,
+        idle: span.idle,
+        enter_count: span.enter_count,
+        status: span.status.clone(),
+        kind: span.kind,
+        sample_rate: span.sample_rate,
+        baggage: span.baggage.clone(),
+        meta: span.meta,
+        service_name: span.service_name.clone(),
+        resource: span.resource.clone(),
+        values: (),
+    }
+}
+
+fn span_with_values<V, SpanId, TraceId>(
+    span: Span<(), SpanId, TraceId>,
+    values: V,
+) -> Span<V, SpanId, TraceId> {
+    Span {
+        id: span.id,
+        trace_id: span.trace_id,
+        parent_id: span.parent_id,
+        local_root: span.local_root,
+        follows_from: span.follows_from,
+        initialized_at: span.initialized_at,
+        completed_at: span.completed_at,
+        busy: span.busy,
+        idle: span.idle,
+        enter_count: span.enter_count,
+        status: span.status,
+        kind: span.kind,
+        sample_rate: span.sample_rate,
+        baggage: span.baggage,
+        meta: span.meta,
+        service_name: span.service_name,
+        resource: span.resource,
+        values,
+    }
+}
+
+// copies all fields other than values, which are left empty to be filled in by the caller
+fn map_event<V, S1, T1, S2, T2>(
+    event: &Event<V, S1, T1>,
+    map_span_id: impl Fn(&S1) -> S2,
+    map_trace_id: impl Fn(&T1) -> T2,
+) -> Event<(), S2, T2> {
+    Event {
+        trace_id: map_trace_id(&event.trace_id),
+        parent_id: event.parent_id.as_ref().map(map_span_id),
+        initialized_at: event.initialized_at,
+        sample_rate: event.sample_rate,
+        meta: event.meta,
+        service_name: event.service_name.clone(),
+        resource: event.resource.clone(),
+        values: (),
+    }
+}
+
+fn event_with_values<V, SpanId, TraceId>(
+    event: Event<(), SpanId, TraceId>,
+    values: V,
+) -> Event<V, SpanId, TraceId> {
+    Event {
+        trace_id: event.trace_id,
+        parent_id: event.parent_id,
+        initialized_at: event.initialized_at,
+        sample_rate: event.sample_rate,
+        meta: event.meta,
+        service_name: event.service_name,
+        resource: event.resource,
+        values,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test::{SpanId, TestTelemetry, TraceId};
+    use crate::telemetry::BlackholeVisitor;
+    use crate::{register_dist_tracing_root, TelemetryLayer};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use tracing::instrument;
+    use tracing_subscriber::layer::Layer;
+    use tracing_subscriber::registry;
+
+    // wider ids than those used by `TestTelemetry`
+    struct WideIds;
+
+    impl IdCodec for WideIds {
+        type TraceId = u128;
+        type SpanId = u64;
+
+        fn encode_trace_id(trace_id: &u128) -> u128 {
+            *trace_id
+        }
+
+        fn decode_trace_id(trace_id: u128) -> u128 {
+            trace_id
+        }
+
+        fn encode_span_id(span_id: &u64) -> u64 {
+            *span_id
+        }
+
+        fn decode_span_id(span_id: u64) -> u64 {
+            span_id
+        }
+    }
+
+    #[test]
+    fn fan_out_to_adapted_backends() {
+        let first_spans = Arc::new(Mutex::new(Vec::new()));
+        let first_events = Arc::new(Mutex::new(Vec::new()));
+        let second_spans = Arc::new(Mutex::new(Vec::new()));
+        let second_events = Arc::new(Mutex::new(Vec::new()));
+        let telemetry = FanOutTelemetry::new(
+            IdAdapter::<_, WideIds>::new(TestTelemetry::new(
+                first_spans.clone(),
+                first_events.clone(),
+            )),
+            IdAdapter::<_, WideIds>::new(TestTelemetry::new(
+                second_spans.clone(),
+                second_events.clone(),
+            )),
+        );
+        let layer = TelemetryLayer::new("test_svc_name", telemetry, |id| id.into_u64());
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        #[instrument]
+        fn f() {
+            register_dist_tracing_root::<u64, u128>(135, Some(246)).unwrap();
+            g();
+        }
+
+        #[instrument]
+        fn g() {
+            tracing::info!("event");
+        }
+
+        tracing::subscriber::with_default(subscriber, f);
+
+        for (spans, events) in [(first_spans, first_events), (second_spans, second_events)].iter() {
+            let spans = spans.lock().unwrap();
+            let events = events.lock().unwrap();
+            assert_eq!(spans.len(), 2);
+            assert_eq!(events.len(), 1);
+            assert!(spans.iter().all(|span| span.trace_id == 135));
+            assert_eq!(spans[1].parent_id, Some(tracing::Id::from_u64(246)));
+            assert_eq!(spans[0].parent_id, Some(spans[1].id.clone()));
+            assert_eq!(events[0].parent_id, Some(spans[0].id.clone()));
+        }
+    }
+
+    // ids rendered as fixed-width hex strings, unlike the numeric ids used by `TestTelemetry`
+    struct HexIds;
+
+    impl IdCodec for HexIds {
+        type TraceId = String;
+        type SpanId = String;
+
+        fn encode_trace_id(trace_id: &String) -> u128 {
+            u128::from_str_radix(trace_id, 16).unwrap()
+        }
+
+        fn decode_trace_id(trace_id: u128) -> String {
+            format!("{:032x}", trace_id)
+        }
+
+        fn encode_span_id(span_id: &String) -> u64 {
+            u64::from_str_radix(span_id, 16).unwrap()
+        }
+
+        fn decode_span_id(span_id: u64) -> String {
+            format!("{:016x}", span_id)
+        }
+    }
+
+    #[test]
+    fn id_adapter_converts_ids() {
+        assert_eq!(
+            convert_trace_id::<HexIds, TestTelemetry>(&format!("{:032x}", 135)),
+            135
+        );
+        assert_eq!(
+            convert_span_id::<HexIds, TestTelemetry>(&format!("{:016x}", 246)),
+            tracing::Id::from_u64(246)
+        );
+        assert_eq!(
+            convert_span_id::<TestTelemetry, HexIds>(&tracing::Id::from_u64(246)),
+            "00000000000000f6"
+        );
+
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let telemetry =
+            IdAdapter::<_, HexIds>::new(TestTelemetry::new(spans.clone(), events.clone()));
+        let layer = TelemetryLayer::new("test_svc_name", telemetry, |id| {
+            format!("{:016x}", id.into_u64())
+        });
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        let (root_id, child_id) = tracing::subscriber::with_default(subscriber, || {
+            let root = tracing::info_span!("root");
+            let _root_guard = root.enter();
+            register_dist_tracing_root::<String, String>(
+                format!("{:032x}", 135),
+                Some(format!("{:016x}", 246)),
+            )
+            .unwrap();
+            let child = tracing::info_span!("child");
+            let _child_guard = child.enter();
+            tracing::info!("event");
+            (root.id().unwrap(), child.id().unwrap())
+        });
+
+        // the wrapped backend sees the ids the layer reported, decoded into its own types
+        let spans = spans.lock().unwrap();
+        let events = events.lock().unwrap();
+        assert_eq!(spans.len(), 2);
+        assert!(spans.iter().all(|span| span.trace_id == 135));
+        assert_eq!(spans[0].id, child_id);
+        assert_eq!(spans[0].parent_id, Some(root_id.clone()));
+        assert_eq!(spans[1].id, root_id);
+        assert_eq!(spans[1].parent_id, Some(tracing::Id::from_u64(246)));
+        assert_eq!(events[0].trace_id, 135);
+        assert_eq!(events[0].parent_id, Some(child_id));
+    }
+
+    // records how often it was flushed and whether it was shut down, reporting fixed stats
+    #[derive(Default)]
+    struct Backend {
+        stats: BackendStats,
+        flushed: AtomicUsize,
+        shut_down: AtomicBool,
+    }
+
+    impl IdCodec for Backend {
+        type TraceId = TraceId;
+        type SpanId = SpanId;
+
+        fn encode_trace_id(trace_id: &TraceId) -> u128 {
+            u128::from(*trace_id)
+        }
+
+        fn decode_trace_id(trace_id: u128) -> TraceId {
+            trace_id as TraceId
+        }
+
+        fn encode_span_id(span_id: &SpanId) -> u64 {
+            span_id.into_u64()
+        }
+
+        fn decode_span_id(span_id: u64) -> SpanId {
+            tracing::Id::from_u64(span_id)
+        }
+    }
+
+    impl Telemetry for Backend {
+        type Visitor = BlackholeVisitor;
+        type TraceId = TraceId;
+        type SpanId = SpanId;
+
+        fn mk_visitor(&self) -> Self::Visitor {
+            BlackholeVisitor
+        }
+
+        fn report_span(&self, _: Span<Self::Visitor, Self::SpanId, Self::TraceId>) {}
+
+        fn report_event(&self, _: Event<Self::Visitor, Self::SpanId, Self::TraceId>) {}
+
+        fn flush(&self, _timeout: Duration) -> Result<(), FlushError> {
+            if self.shut_down.load(Ordering::Relaxed) {
+                return Err(FlushError::ShutDown);
+            }
+            self.flushed.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+
+        fn shutdown(&self, timeout: Duration) -> Result<(), FlushError> {
+            self.flush(timeout)?;
+            self.shut_down.store(true, Ordering::Relaxed);
+            Ok(())
+        }
+
+        fn stats(&self) -> BackendStats {
+            self.stats
+        }
+    }
+
+    fn backend(stats: BackendStats) -> Backend {
+        Backend {
+            stats,
+            ..Backend::default()
+        }
+    }
+
+    // three backends, one of them behind an `IdAdapter`
+    fn nested(
+        first: Backend,
+        second: Backend,
+        third: Backend,
+    ) -> FanOutTelemetry<Backend, FanOutTelemetry<Backend, IdAdapter<Backend, TestTelemetry>>> {
+        FanOutTelemetry::new(first, FanOutTelemetry::new(second, IdAdapter::new(third)))
+    }
+
+    #[test]
+    fn stats_are_combined() {
+        let telemetry = nested(
+            backend(BackendStats {
+                dropped_sampling: 1,
+                pending: 2,
+                ..BackendStats::default()
+            }),
+            backend(BackendStats {
+                dropped_buffer_full: 3,
+                export_errors: 4,
+                ..BackendStats::default()
+            }),
+            backend(BackendStats {
+                dropped_sampling: 5,
+                dropped_shut_down: 6,
+                pending: 7,
+                ..BackendStats::default()
+            }),
+        );
+
+        assert_eq!(
+            telemetry.stats(),
+            BackendStats {
+                dropped_sampling: 6,
+                dropped_buffer_full: 3,
+                dropped_shut_down: 6,
+                export_errors: 4,
+                pending: 9,
+            }
+        );
+    }
+
+    #[test]
+    fn flush_and_shutdown_reach_every_backend() {
+        let telemetry = nested(Backend::default(), Backend::default(), Backend::default());
+        let backends = || {
+            vec![
+                &telemetry.first,
+                &telemetry.second.first,
+                &telemetry.second.second.telemetry,
+            ]
+        };
+
+        assert_eq!(telemetry.flush(Duration::from_secs(1)), Ok(()));
+        assert!(backends()
+            .iter()
+            .all(|b| b.flushed.load(Ordering::Relaxed) == 1));
+
+        // a failing backend doesn't prevent the others from being flushed
+        telemetry.first.shut_down.store(true, Ordering::Relaxed);
+        assert_eq!(
+            telemetry.flush(Duration::from_secs(1)),
+            Err(FlushError::ShutDown)
+        );
+        assert_eq!(telemetry.second.first.flushed.load(Ordering::Relaxed), 2);
+        assert_eq!(
+            telemetry
+                .second
+                .second
+                .telemetry
+                .flushed
+                .load(Ordering::Relaxed),
+            2
+        );
+
+        telemetry.first.shut_down.store(false, Ordering::Relaxed);
+        assert_eq!(telemetry.shutdown(Duration::from_secs(1)), Ok(()));
+        assert!(backends()
+            .iter()
+            .all(|b| b.shut_down.load(Ordering::Relaxed)));
+        assert_eq!(
+            telemetry.shutdown(Duration::from_secs(1)),
+            Err(FlushError::ShutDown)
+        );
+    }
+}
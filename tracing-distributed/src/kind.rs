@@ -0,0 +1,139 @@
+use std::fmt;
+use std::str::FromStr;
+use tracing::field::{Field, Visit};
+
+/// Name of the span field used to set a span's `SpanKind`, eg `info_span!("request", span.kind = "server")`
+/// or `info_span!("request", span.kind = %SpanKind::Server)`.
+pub const SPAN_KIND_FIELD: &str = "span.kind";
+
+/// The relationship between a span and its remote parent or children, used by backends to derive
+/// dependencies between services.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, Default)]
+pub enum SpanKind {
+    /// The span represents an operation internal to a service. Used if no other kind is recorded.
+    #[default]
+    Internal,
+    /// The span handles a synchronous request from some remote client.
+    Server,
+    /// The span makes a synchronous request to some remote server.
+    Client,
+    /// The span sends a message to some broker, to be handled asynchronously by some consumer.
+    Producer,
+    /// The span handles a message sent by some remote producer.
+    Consumer,
+}
+
+/// Error returned when parsing an unknown span kind.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub struct ParseSpanKindError;
+
+impl FromStr for SpanKind {
+    type Err = ParseSpanKindError;
+
+    /// Parse a span kind from its (case-insensitive) name, eg `server`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "internal" => Ok(SpanKind::Internal),
+            "server" => Ok(SpanKind::Server),
+            "client" => Ok(SpanKind::Client),
+            "producer" => Ok(SpanKind::Producer),
+            "consumer" => Ok(SpanKind::Consumer),
+            _ => Err(ParseSpanKindError),
+        }
+    }
+}
+
+impl fmt::Display for SpanKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            SpanKind::Internal => "internal",
+            SpanKind::Server => "server",
+            SpanKind::Client => "client",
+            SpanKind::Producer => "producer",
+            SpanKind::Consumer => "consumer",
+        };
+        f.write_str(s)
+    }
+}
+
+// records the span kind field of spans, ignoring unknown span kinds
+#[derive(Default)]
+pub(crate) struct SpanKindVisitor(pub(crate) Option<SpanKind>);
+
+impl Visit for SpanKindVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == SPAN_KIND_FIELD {
+            if let Ok(kind) = value.parse() {
+                self.0 = Some(kind);
+            }
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == SPAN_KIND_FIELD {
+            self.record_str(field, &format!("{:?}", value))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test::{SpanId, TestTelemetry, TraceId};
+    use crate::{register_dist_tracing_root, TelemetryLayer};
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::Layer;
+    use tracing_subscriber::registry;
+
+    #[test]
+    fn span_kind_round_trip() {
+        for kind in [
+            SpanKind::Internal,
+            SpanKind::Server,
+            SpanKind::Client,
+            SpanKind::Producer,
+            SpanKind::Consumer,
+        ]
+        .iter()
+        {
+            assert_eq!(kind.to_string().parse(), Ok(*kind));
+        }
+        assert_eq!("SERVER".parse(), Ok(SpanKind::Server));
+        assert_eq!("rpc".parse::<SpanKind>(), Err(ParseSpanKindError));
+    }
+
+    #[test]
+    fn span_kind_from_field() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let cap = TestTelemetry::new(spans.clone(), Arc::default());
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let server = tracing::info_span!("server", span.kind = "server");
+            let _guard = server.enter();
+            register_dist_tracing_root::<SpanId, TraceId>(135, None).unwrap();
+
+            tracing::info_span!("client", span.kind = %SpanKind::Client).in_scope(|| {});
+            tracing::info_span!("unknown", span.kind = "rpc").in_scope(|| {});
+
+            let recorded = tracing::info_span!("recorded", span.kind = tracing::field::Empty);
+            recorded.record(SPAN_KIND_FIELD, "consumer");
+        });
+
+        let spans = spans.lock().unwrap();
+        let kinds: Vec<(&str, SpanKind)> = spans
+            .iter()
+            .map(|span| (span.meta.name(), span.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("client", SpanKind::Client),
+                ("unknown", SpanKind::Internal),
+                ("recorded", SpanKind::Consumer),
+                ("server", SpanKind::Server),
+            ]
+        );
+    }
+}
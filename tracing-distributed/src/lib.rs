@@ -3,18 +3,69 @@
 //! This crate provides:
 //! - `TelemetryLayer`, a generic tracing layer that handles publishing spans and events to arbitrary backends
 //! - Utilities for implementing distributed tracing for arbitrary backends
+//! - `Propagator`, a generic interface for propagating trace context across process boundaries via some `Carrier`,
+//!   with support for the [W3C Trace Context](https://www.w3.org/TR/trace-context/) and [B3](https://github.com/openzipkin/b3-propagation) formats
+//! - `testing`, an in-memory `Telemetry` implementation and assertions on the shape of the traces it records,
+//!   for testing instrumented code (requires the `testing` feature)
 //!
 //! As a tracing layer, `TelemetryLayer` can be composed with other layers to provide stdout logging, filtering, etc.
 //!
 //! This crate is primarily intended to be used by people implementing their own backends.
 //! A concrete implementation using honeycomb.io as a backend is available in the [`tracing-honeycomb` crate](https://crates.io/crates/tracing-honeycomb).
 
+mod b3;
+mod baggage;
+mod data_loss;
+mod fan_out;
+mod kind;
+mod propagation;
+mod resource;
+mod sampling;
+mod sampling_rules;
+mod stats;
+mod status;
+mod tail_sampling;
 mod telemetry;
 mod telemetry_layer;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 mod trace;
+mod w3c;
 
-pub use crate::telemetry::{BlackholeTelemetry, Telemetry};
-pub use crate::telemetry_layer::TelemetryLayer;
+pub use crate::b3::{
+    B3Encoding, B3Propagator, B3_FLAGS_HEADER, B3_SAMPLED_HEADER, B3_SINGLE_HEADER,
+    B3_SPAN_ID_HEADER, B3_TRACE_ID_HEADER,
+};
+pub use crate::baggage::{current_baggage, set_baggage_item, Baggage, BAGGAGE_HEADER};
+pub use crate::data_loss::{DataLoss, DataLossReporter, DATA_LOSS_RECORD_NAME};
+pub use crate::fan_out::{FanOutTelemetry, FanOutVisitor, IdAdapter};
+pub use crate::kind::{ParseSpanKindError, SpanKind, SPAN_KIND_FIELD};
+pub use crate::propagation::{
+    extract_dist_tracing_root, inject_dist_trace_ctx, Carrier, CompositePropagator, IdCodec,
+    Propagator, RemoteTraceCtx,
+};
+pub use crate::resource::{Resource, RESOURCE_ATTRIBUTES_ENV_VAR};
+pub use crate::sampling::{
+    trace_id_hash, AlwaysSample, NeverSample, ParentBasedSampler, RateLimitedSampler, RatioSampler,
+    RootSpan, Sampler, SamplingDecision,
+};
+#[cfg(feature = "rules_config")]
+pub use crate::sampling_rules::RulesConfigError;
+pub use crate::sampling_rules::{RulesSampler, SamplingAction, SamplingRule};
+pub use crate::stats::{BackendStats, TelemetryStats};
+pub use crate::status::{SpanStatus, StatusConvention};
+pub use crate::tail_sampling::{
+    IncompleteTracePolicy, TailSamplingConfig, TailSamplingRule, TailSamplingTelemetry,
+};
+pub use crate::telemetry::{BlackholeTelemetry, FlushError, Telemetry};
+pub use crate::telemetry_layer::{
+    dist_telemetry_stats, flush_dist_telemetry, shutdown_dist_telemetry, TelemetryLayer,
+};
 pub use crate::trace::{
-    current_dist_trace_ctx, register_dist_tracing_root, Event, Span, TraceCtxError,
+    current_dist_trace_ctx, register_dist_tracing_root, register_dist_tracing_root_with_baggage,
+    set_service_name, Event, Span, TraceCtxError,
+};
+pub use crate::w3c::{
+    current_w3c_trace_ctx, register_w3c_tracing_root, ParseTraceParentError, TraceParent,
+    W3CPropagator, TRACEPARENT_HEADER, TRACESTATE_HEADER, TRACE_FLAG_SAMPLED,
 };
@@ -0,0 +1,271 @@
+use crate::baggage::Baggage;
+use crate::trace::{current_trace_ctx, register_trace_ctx, TraceCtxError};
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+/// Conversion between a backend's `TraceId` and `SpanId` types and the fixed-width ids used by
+/// cross-process propagation formats: 128-bit trace ids and 64-bit span ids.
+///
+/// Typically implemented by a backend's `Telemetry` type, which already fixes both id types.
+pub trait IdCodec {
+    /// Globally unique identifier, uniquely identifies a trace.
+    type TraceId;
+    /// Identifies spans within a trace.
+    type SpanId;
+
+    /// Encode a `TraceId` as a 128-bit value.
+    fn encode_trace_id(trace_id: &Self::TraceId) -> u128;
+
+    /// Decode a `TraceId` from a 128-bit value. Propagation formats never pass zero, which is an
+    /// invalid trace id.
+    fn decode_trace_id(trace_id: u128) -> Self::TraceId;
+
+    /// Encode a `SpanId` as a 64-bit value.
+    fn encode_span_id(span_id: &Self::SpanId) -> u64;
+
+    /// Decode a `SpanId` from a 64-bit value. Propagation formats never pass zero, which is an
+    /// invalid span id.
+    fn decode_span_id(span_id: u64) -> Self::SpanId;
+}
+
+/// Key-value storage that trace context is injected into and extracted from when crossing a
+/// process boundary, eg HTTP headers, gRPC metadata, Kafka record headers or environment variables.
+///
+/// Propagators always use lowercase keys. Implementations backed by case-insensitive storage (eg HTTP
+/// headers) should look keys up case-insensitively.
+pub trait Carrier {
+    /// Get the value associated with `key`, if any.
+    fn get(&self, key: &str) -> Option<&str>;
+
+    /// Set the value associated with `key`, replacing any existing value.
+    fn set(&mut self, key: &str, value: String);
+}
+
+impl<S: BuildHasher> Carrier for HashMap<String, String, S> {
+    fn get(&self, key: &str) -> Option<&str> {
+        HashMap::get(self, key).map(|s| s.as_str())
+    }
+
+    fn set(&mut self, key: &str, value: String) {
+        self.insert(key.to_string(), value);
+    }
+}
+
+/// Trace context exchanged with some other process.
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub struct RemoteTraceCtx<SpanId, TraceId> {
+    /// `TraceId` identifying the trace
+    pub trace_id: TraceId,
+    /// id of the span in the sending process: the current span when injecting,
+    /// the remote parent span when extracting
+    pub span_id: SpanId,
+    /// opaque vendor-specific state (eg w3c `tracestate`) to be forwarded as-is
+    pub trace_state: Option<String>,
+    /// whether the trace is sampled, `None` if the sender deferred the sampling decision
+    pub sampled: Option<bool>,
+    /// request-scoped key-value pairs propagated along with the trace context
+    pub baggage: Baggage,
+}
+
+impl<SpanId, TraceId> RemoteTraceCtx<SpanId, TraceId> {
+    /// Construct a `RemoteTraceCtx` with no vendor-specific state, sampling decision or baggage.
+    pub fn new(trace_id: TraceId, span_id: SpanId) -> Self {
+        RemoteTraceCtx {
+            trace_id,
+            span_id,
+            trace_state: None,
+            sampled: None,
+            baggage: Baggage::default(),
+        }
+    }
+}
+
+/// Represents the ability to write trace context to a `Carrier` and to read it back in some
+/// other process, using some particular wire format.
+pub trait Propagator<SpanId, TraceId> {
+    /// Write `ctx` to `carrier`.
+    fn inject(&self, ctx: &RemoteTraceCtx<SpanId, TraceId>, carrier: &mut dyn Carrier);
+
+    /// Read trace context from `carrier`. Returns `None` if `carrier` does not hold valid
+    /// trace context in this propagator's format.
+    fn extract(&self, carrier: &dyn Carrier) -> Option<RemoteTraceCtx<SpanId, TraceId>>;
+}
+
+/// `Propagator` that combines several propagators, eg to support multiple wire formats during a migration.
+///
+/// Injects using every inner propagator and extracts using the first inner propagator, in order,
+/// that finds trace context in the carrier.
+pub struct CompositePropagator<SpanId, TraceId> {
+    propagators: Vec<Box<dyn Propagator<SpanId, TraceId> + Send + Sync>>,
+}
+
+impl<SpanId, TraceId> CompositePropagator<SpanId, TraceId> {
+    /// Construct a `CompositePropagator` from a list of propagators, in order of precedence.
+    pub fn new(propagators: Vec<Box<dyn Propagator<SpanId, TraceId> + Send + Sync>>) -> Self {
+        CompositePropagator { propagators }
+    }
+}
+
+impl<SpanId, TraceId> Propagator<SpanId, TraceId> for CompositePropagator<SpanId, TraceId> {
+    fn inject(&self, ctx: &RemoteTraceCtx<SpanId, TraceId>, carrier: &mut dyn Carrier) {
+        for propagator in self.propagators.iter() {
+            propagator.inject(ctx, carrier);
+        }
+    }
+
+    fn extract(&self, carrier: &dyn Carrier) -> Option<RemoteTraceCtx<SpanId, TraceId>> {
+        self.propagators
+            .iter()
+            .find_map(|propagator| propagator.extract(carrier))
+    }
+}
+
+/// Write the distributed trace context associated with the current span to `carrier`, using the
+/// provided `Propagator`.
+pub fn inject_dist_trace_ctx<SpanId, TraceId, P>(
+    propagator: &P,
+    carrier: &mut dyn Carrier,
+) -> Result<(), TraceCtxError>
+where
+    SpanId: 'static + Clone + Send + Sync,
+    TraceId: 'static + Clone + Send + Sync,
+    P: Propagator<SpanId, TraceId> + ?Sized,
+{
+    let (trace_ctx, span_id) = current_trace_ctx::<SpanId, TraceId>()?;
+    let ctx = RemoteTraceCtx {
+        trace_id: trace_ctx.trace_id,
+        span_id,
+        trace_state: trace_ctx.trace_state,
+        sampled: Some(trace_ctx.sampling.is_sampled()),
+        baggage: trace_ctx.baggage,
+    };
+    propagator.inject(&ctx, carrier);
+    Ok(())
+}
+
+/// Register the current span as the local root of a distributed trace, using trace context read
+/// from `carrier` by the provided `Propagator`. By default, spans and events in the trace are not
+/// reported if the sender marked it as not sampled.
+pub fn extract_dist_tracing_root<SpanId, TraceId, P>(
+    propagator: &P,
+    carrier: &dyn Carrier,
+) -> Result<(), TraceCtxError>
+where
+    SpanId: 'static + Clone + Send + Sync,
+    TraceId: 'static + Clone + Send + Sync,
+    P: Propagator<SpanId, TraceId> + ?Sized,
+{
+    let ctx = propagator
+        .extract(carrier)
+        .ok_or(TraceCtxError::NoPropagatedTraceCtx)?;
+    register_trace_ctx(
+        ctx.trace_id,
+        Some(ctx.span_id),
+        ctx.trace_state,
+        ctx.sampled,
+        ctx.baggage,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test::{SpanId, TestTelemetry, TraceId};
+    use crate::{TelemetryLayer, W3CPropagator, TRACEPARENT_HEADER};
+    use std::sync::{Arc, Mutex};
+    use tracing::instrument;
+    use tracing_subscriber::layer::Layer;
+    use tracing_subscriber::registry;
+
+    // propagates ids as decimal strings under a custom key
+    struct TestPropagator;
+
+    impl Propagator<SpanId, TraceId> for TestPropagator {
+        fn inject(&self, ctx: &RemoteTraceCtx<SpanId, TraceId>, carrier: &mut dyn Carrier) {
+            let value = format!("{}:{}", ctx.trace_id, ctx.span_id.into_u64());
+            carrier.set("x-test-trace", value);
+        }
+
+        fn extract(&self, carrier: &dyn Carrier) -> Option<RemoteTraceCtx<SpanId, TraceId>> {
+            let mut iter = carrier.get("x-test-trace")?.split(':');
+            let trace_id = iter.next()?.parse().ok()?;
+            let span_id = iter.next()?.parse().ok()?;
+            Some(RemoteTraceCtx::new(
+                trace_id,
+                tracing::Id::from_u64(span_id),
+            ))
+        }
+    }
+
+    fn composite() -> CompositePropagator<SpanId, TraceId> {
+        CompositePropagator::new(vec![
+            Box::new(W3CPropagator::<TestTelemetry>::default()),
+            Box::new(TestPropagator),
+        ])
+    }
+
+    #[test]
+    fn composite_injects_all_formats() {
+        let mut carrier = HashMap::new();
+        let ctx = RemoteTraceCtx::new(135, tracing::Id::from_u64(246));
+        composite().inject(&ctx, &mut carrier);
+
+        assert_eq!(
+            Carrier::get(&carrier, TRACEPARENT_HEADER),
+            Some("00-00000000000000000000000000000087-00000000000000f6-01")
+        );
+        assert_eq!(Carrier::get(&carrier, "x-test-trace"), Some("135:246"));
+    }
+
+    #[test]
+    fn composite_extracts_first_match() {
+        let mut carrier = HashMap::new();
+        carrier.insert("x-test-trace".to_string(), "1:2".to_string());
+        assert_eq!(
+            composite().extract(&carrier),
+            Some(RemoteTraceCtx::new(1, tracing::Id::from_u64(2)))
+        );
+
+        carrier.insert(
+            TRACEPARENT_HEADER.to_string(),
+            "00-00000000000000000000000000000003-0000000000000004-01".to_string(),
+        );
+        assert_eq!(
+            composite().extract(&carrier),
+            Some(RemoteTraceCtx {
+                sampled: Some(true),
+                ..RemoteTraceCtx::new(3, tracing::Id::from_u64(4))
+            })
+        );
+    }
+
+    #[test]
+    fn extract_then_inject() {
+        let cap = TestTelemetry::new(Arc::new(Mutex::new(Vec::new())), Arc::default());
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        #[instrument]
+        fn f() {
+            let mut incoming = HashMap::new();
+            incoming.insert("x-test-trace".to_string(), "135:246".to_string());
+            extract_dist_tracing_root(&composite(), &incoming).unwrap();
+
+            let mut outgoing = HashMap::new();
+            inject_dist_trace_ctx(&TestPropagator, &mut outgoing).unwrap();
+            let current_span_id = tracing::Span::current().id().unwrap().into_u64();
+            assert_eq!(
+                outgoing.get("x-test-trace"),
+                Some(&format!("135:{}", current_span_id))
+            );
+
+            let empty: HashMap<String, String> = HashMap::new();
+            assert_eq!(
+                extract_dist_tracing_root(&composite(), &empty),
+                Err(TraceCtxError::NoPropagatedTraceCtx)
+            );
+        }
+
+        tracing::subscriber::with_default(subscriber, f);
+    }
+}
@@ -0,0 +1,178 @@
+use crate::baggage::percent_decode;
+use std::collections::BTreeMap;
+use std::iter::FromIterator;
+use std::sync::Arc;
+
+/// Name of the environment variable from which `Resource::from_env` reads additional attributes,
+/// as a comma-separated list of `key=value` pairs (as used by OpenTelemetry SDKs).
+pub const RESOURCE_ATTRIBUTES_ENV_VAR: &str = "OTEL_RESOURCE_ATTRIBUTES";
+
+/// Process-wide key-value pairs describing the entity producing telemetry (eg its hostname, pid,
+/// version or region), configured once via `TelemetryLayer::with_resource` and attached to every
+/// span and event it reports. Keys should follow the OpenTelemetry resource semantic conventions
+/// (eg `host.name`, `service.version`, `cloud.region`, `k8s.pod.name`) where applicable.
+///
+/// Cheap to clone: entries are only copied when a clone is modified.
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Default)]
+pub struct Resource(Arc<BTreeMap<String, String>>);
+
+impl Resource {
+    /// Construct an empty `Resource`.
+    pub fn new() -> Self {
+        Resource::default()
+    }
+
+    /// Construct a `Resource` describing the current process, as detected from the environment:
+    /// - `host.name`, from the `HOSTNAME` environment variable or `/etc/hostname`
+    /// - `process.pid`
+    /// - `process.executable.name`, the file name of the current executable
+    /// - `k8s.pod.name`, the hostname if running in a kubernetes pod
+    /// - any attributes listed by the `OTEL_RESOURCE_ATTRIBUTES` environment variable, which take
+    ///   precedence over detected ones
+    ///
+    /// Attributes that can't be detected are omitted. Use `with_attribute` to add attributes known
+    /// at build time, eg `service.version` or a git sha.
+    pub fn from_env() -> Self {
+        let mut resource = Resource::new();
+
+        let hostname = std::env::var("HOSTNAME").ok().or_else(|| {
+            std::fs::read_to_string("/etc/hostname")
+                .ok()
+                .map(|s| s.trim().to_string())
+        });
+        if let Some(hostname) = hostname.filter(|hostname| !hostname.is_empty()) {
+            // pods are named after their hostname unless configured otherwise
+            if std::env::var_os("KUBERNETES_SERVICE_HOST").is_some() {
+                resource.insert("k8s.pod.name", hostname.clone());
+            }
+            resource.insert("host.name", hostname);
+        }
+
+        resource.insert("process.pid", std::process::id().to_string());
+
+        let executable_name = std::env::current_exe().ok().and_then(|path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        });
+        if let Some(executable_name) = executable_name {
+            resource.insert("process.executable.name", executable_name);
+        }
+
+        if let Ok(attributes) = std::env::var(RESOURCE_ATTRIBUTES_ENV_VAR) {
+            for (key, value) in Resource::from_attributes_env_value(&attributes).iter() {
+                resource.insert(key, value);
+            }
+        }
+
+        resource
+    }
+
+    /// Add an attribute, replacing any previous value for `key`.
+    pub fn with_attribute(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.insert(key, value);
+        self
+    }
+
+    /// Get the value associated with `key`, if any.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(|s| s.as_str())
+    }
+
+    /// Set the value associated with `key`, returning the previous value, if any.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        Arc::make_mut(&mut self.0).insert(key.into(), value.into())
+    }
+
+    /// Iterate over all attributes, ordered by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Returns the number of attributes.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns true if there are no attributes.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // parses an `OTEL_RESOURCE_ATTRIBUTES` value, skipping malformed entries
+    fn from_attributes_env_value(s: &str) -> Self {
+        s.split(',')
+            .filter_map(|entry| {
+                let mut key_value = entry.splitn(2, '=');
+                let (key, value) = (key_value.next()?, key_value.next()?);
+                let key = key.trim();
+                if key.is_empty() {
+                    return None;
+                }
+                Some((key.to_string(), percent_decode(value.trim())?))
+            })
+            .collect()
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for Resource {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Resource(Arc::new(
+            iter.into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test::{SpanId, TestTelemetry, TraceId};
+    use crate::{register_dist_tracing_root, TelemetryLayer};
+    use std::sync::Mutex;
+    use tracing::instrument;
+    use tracing_subscriber::layer::Layer;
+    use tracing_subscriber::registry;
+
+    #[test]
+    fn parse_attributes_env_value() {
+        let resource = Resource::from_attributes_env_value(
+            "cloud.region=us-east-1, service.namespace = a%2Cb,=skipped,bad=%zz,novalue",
+        );
+        assert_eq!(
+            resource,
+            vec![("cloud.region", "us-east-1"), ("service.namespace", "a,b")]
+                .into_iter()
+                .collect()
+        );
+
+        let resource = Resource::from_env();
+        assert_eq!(
+            resource.get("process.pid"),
+            Some(std::process::id().to_string().as_str())
+        );
+    }
+
+    #[test]
+    fn resource_attached_to_spans_and_events() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let cap = TestTelemetry::new(spans.clone(), events.clone());
+        let resource = Resource::new().with_attribute("service.version", "1.2.3");
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x).with_resource(resource);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        #[instrument]
+        fn root() {
+            register_dist_tracing_root::<SpanId, TraceId>(135, None).unwrap();
+            tracing::info!("event");
+        }
+
+        tracing::subscriber::with_default(subscriber, root);
+
+        let spans = spans.lock().unwrap();
+        let events = events.lock().unwrap();
+        assert_eq!(spans[0].resource.get("service.version"), Some("1.2.3"));
+        assert_eq!(events[0].resource.get("service.version"), Some("1.2.3"));
+    }
+}
@@ -0,0 +1,484 @@
+use crate::propagation::IdCodec;
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::time::Instant;
+use tracing::field::{Field, Visit};
+use tracing::Metadata;
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+/// Whether to sample a trace, as decided by some `Sampler`.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub enum SamplingDecision {
+    /// Report the trace's spans and events, each of which stands in for `sample_rate` spans or
+    /// events in total, eg a sampler that keeps one in every 10 traces reports kept traces with a
+    /// sample rate of 10. Backends can use the sample rate to re-weight counts.
+    Sample {
+        /// Number of traces represented by each sampled trace, at least 1.
+        sample_rate: u64,
+    },
+    /// Don't report the trace's spans and events.
+    Drop,
+}
+
+impl SamplingDecision {
+    /// Decision to sample a trace that represents only itself, ie with a sample rate of 1.
+    pub fn sample() -> Self {
+        SamplingDecision::Sample { sample_rate: 1 }
+    }
+
+    /// Returns true if the trace is sampled.
+    pub fn is_sampled(&self) -> bool {
+        matches!(self, SamplingDecision::Sample { .. })
+    }
+
+    /// Number of traces represented by a sampled trace, 1 if the trace is not sampled.
+    pub fn sample_rate(&self) -> u64 {
+        match self {
+            SamplingDecision::Sample { sample_rate } => (*sample_rate).max(1),
+            SamplingDecision::Drop => 1,
+        }
+    }
+}
+
+impl From<bool> for SamplingDecision {
+    /// Sample the trace with a sample rate of 1, or drop it.
+    fn from(sampled: bool) -> Self {
+        if sampled {
+            SamplingDecision::sample()
+        } else {
+            SamplingDecision::Drop
+        }
+    }
+}
+
+/// Decides whether to sample a trace when its local root is registered (eg via
+/// `register_dist_tracing_root`) or automatically created. The decision is stored in the trace
+/// context and inherited by all spans in the trace. Spans and events in unsampled traces are not
+/// reported, and the decision is propagated to remote children.
+pub trait Sampler<TraceId>: Send + Sync {
+    /// Decide whether to sample the trace with the provided id, given its local root span and the
+    /// sampling decision of its remote parent span, if one was propagated.
+    fn sample(
+        &self,
+        trace_id: &TraceId,
+        parent_sampled: Option<bool>,
+        root: &RootSpan<'_>,
+    ) -> SamplingDecision;
+
+    /// Whether this sampler inspects the fields of local root spans. If so, the fields of spans
+    /// with no trace ctx are recorded in case they are registered as trace roots, which has some
+    /// cost. Defaults to false, in which case `RootSpan::field` always returns `None`.
+    fn uses_fields(&self) -> bool {
+        false
+    }
+}
+
+/// The local root span of a trace, as seen by a `Sampler` deciding whether to sample the trace.
+#[derive(Clone, Copy, Debug)]
+pub struct RootSpan<'a> {
+    meta: &'static Metadata<'static>,
+    fields: Option<&'a RootFields>,
+}
+
+impl<'a> RootSpan<'a> {
+    /// Construct a `RootSpan` with the provided metadata and no recorded fields.
+    pub fn new(meta: &'static Metadata<'static>) -> Self {
+        RootSpan { meta, fields: None }
+    }
+
+    pub(crate) fn with_fields(
+        meta: &'static Metadata<'static>,
+        fields: Option<&'a RootFields>,
+    ) -> Self {
+        RootSpan { meta, fields }
+    }
+
+    /// `tracing::Metadata` of the root span, eg its name, target and level.
+    pub fn metadata(&self) -> &'static Metadata<'static> {
+        self.meta
+    }
+
+    /// Value of the root span's field with the provided name, if it was recorded before the trace
+    /// root was registered. String values are returned as-is, other values are `Debug`-formatted.
+    pub fn field(&self, name: &str) -> Option<&'a str> {
+        self.fields
+            .and_then(|fields| fields.0.get(name))
+            .map(String::as_str)
+    }
+}
+
+// fields of a span that may become a local trace root, recorded only if the sampler uses them
+#[derive(Default, Debug)]
+pub(crate) struct RootFields(HashMap<&'static str, String>);
+
+impl Visit for RootFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name(), format!("{:?}", value));
+    }
+}
+
+/// Sampler that samples every trace.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, Default)]
+pub struct AlwaysSample;
+
+impl<TraceId> Sampler<TraceId> for AlwaysSample {
+    fn sample(&self, _: &TraceId, _: Option<bool>, _: &RootSpan<'_>) -> SamplingDecision {
+        SamplingDecision::sample()
+    }
+}
+
+/// Sampler that samples no traces.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, Default)]
+pub struct NeverSample;
+
+impl<TraceId> Sampler<TraceId> for NeverSample {
+    fn sample(&self, _: &TraceId, _: Option<bool>, _: &RootSpan<'_>) -> SamplingDecision {
+        SamplingDecision::Drop
+    }
+}
+
+/// Sampler that samples the provided ratio of traces, between 0 and 1, with a sample rate of
+/// the inverse of the ratio (rounded to the nearest integer), with trace ids converted using `C`.
+///
+/// The decision is derived from a fixed hash of the 128-bit encoding of the trace id, so every
+/// service sampling the same ratio makes the same decision for a trace, regardless of how it was built.
+pub struct RatioSampler<C> {
+    ratio: f64,
+    codec: PhantomData<fn() -> C>,
+}
+
+impl<C> RatioSampler<C> {
+    /// Construct a `RatioSampler` that samples the provided ratio of traces. Ratios are clamped to
+    /// between 0 and 1.
+    pub fn new(ratio: f64) -> Self {
+        RatioSampler {
+            ratio: ratio.max(0.0).min(1.0),
+            codec: PhantomData,
+        }
+    }
+}
+
+impl<C> Clone for RatioSampler<C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<C> Copy for RatioSampler<C> {}
+
+impl<C> PartialEq for RatioSampler<C> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ratio == other.ratio
+    }
+}
+
+impl<C> fmt::Debug for RatioSampler<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RatioSampler")
+            .field("ratio", &self.ratio)
+            .finish()
+    }
+}
+
+impl<C: IdCodec> Sampler<C::TraceId> for RatioSampler<C> {
+    fn sample(&self, trace_id: &C::TraceId, _: Option<bool>, _: &RootSpan<'_>) -> SamplingDecision {
+        if trace_id_fraction::<C>(trace_id, HEAD_SAMPLING_SEED) < self.ratio {
+            SamplingDecision::Sample {
+                sample_rate: (1.0 / self.ratio).round() as u64,
+            }
+        } else {
+            SamplingDecision::Drop
+        }
+    }
+}
+
+// seed of the hash from which head sampling decisions are derived
+pub(crate) const HEAD_SAMPLING_SEED: u64 = 0;
+
+// maps a trace id to a number in [0, 1), uniformly distributed across trace ids. Only depends on
+// the encoded trace id and seed, so that decisions are the same across processes and builds
+pub(crate) fn trace_id_fraction<C: IdCodec>(trace_id: &C::TraceId, seed: u64) -> f64 {
+    // take the top 53 bits, the precision of an f64
+    (trace_id_hash(C::encode_trace_id(trace_id), seed) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Hash of the 128-bit encoding of a trace id (see `IdCodec::encode_trace_id`), uniformly
+/// distributed across trace ids, even if they are sequential or have fixed bits (eg uuids).
+///
+/// Hashes with different seeds are independent, so samplers that derive their decisions from the
+/// trace id should each use their own seed: otherwise a trace kept by one sampler would be more
+/// likely to be kept by the next, and sample rates multiplied across samplers would over-weight counts.
+pub fn trace_id_hash(trace_id: u128, seed: u64) -> u64 {
+    // splitmix64 finalizer, with the seed added as a multiple of the golden ratio
+    let mut z = ((trace_id as u64) ^ ((trace_id >> 64) as u64))
+        .wrapping_add(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Sampler that honors the sampling decision of the remote parent span, if one was propagated,
+/// and otherwise defers to the wrapped sampler. This keeps decisions consistent across all the
+/// services a trace passes through.
+///
+/// Traces sampled by a remote parent have a sample rate of 1, as the parent's sample rate is not
+/// propagated.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub struct ParentBasedSampler<S> {
+    root: S,
+}
+
+impl<S> ParentBasedSampler<S> {
+    /// Construct a `ParentBasedSampler` that uses the provided sampler for traces with no remote
+    /// parent, or whose remote parent deferred the sampling decision.
+    pub fn new(root: S) -> Self {
+        ParentBasedSampler { root }
+    }
+}
+
+impl<TraceId, S: Sampler<TraceId>> Sampler<TraceId> for ParentBasedSampler<S> {
+    fn sample(
+        &self,
+        trace_id: &TraceId,
+        parent_sampled: Option<bool>,
+        root: &RootSpan<'_>,
+    ) -> SamplingDecision {
+        match parent_sampled {
+            Some(sampled) => sampled.into(),
+            None => self.root.sample(trace_id, parent_sampled, root),
+        }
+    }
+
+    fn uses_fields(&self) -> bool {
+        self.root.uses_fields()
+    }
+}
+
+/// Sampler that samples up to the provided number of traces per second, using a token bucket
+/// that allows bursts of up to a second's worth of traces. Sampled traces have a sample rate of
+/// 1, as the number of traces they represent is not known.
+pub struct RateLimitedSampler {
+    traces_per_second: f64,
+    // burst size, at least one trace so that rates below one trace per second can sample anything
+    capacity: f64,
+    bucket: Mutex<TokenBucket>,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimitedSampler {
+    /// Construct a `RateLimitedSampler` that samples up to the provided number of traces per second.
+    pub fn new(traces_per_second: f64) -> Self {
+        let traces_per_second = traces_per_second.max(0.0);
+        let capacity = if traces_per_second > 0.0 {
+            traces_per_second.max(1.0)
+        } else {
+            0.0
+        };
+        RateLimitedSampler {
+            traces_per_second,
+            capacity,
+            bucket: Mutex::new(TokenBucket {
+                tokens: capacity,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+}
+
+impl fmt::Debug for RateLimitedSampler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimitedSampler")
+            .field("traces_per_second", &self.traces_per_second)
+            .finish()
+    }
+}
+
+impl<TraceId> Sampler<TraceId> for RateLimitedSampler {
+    fn sample(&self, _: &TraceId, _: Option<bool>, _: &RootSpan<'_>) -> SamplingDecision {
+        // succeed or die. failure is unrecoverable (mutex poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let mut bucket = self.bucket.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let mut bucket = self.bucket.lock();
+
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(bucket.refilled_at);
+        bucket.tokens =
+            (bucket.tokens + elapsed.as_secs_f64() * self.traces_per_second).min(self.capacity);
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            SamplingDecision::sample()
+        } else {
+            SamplingDecision::Drop
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test::{SpanId, TestTelemetry, TraceId};
+    use crate::{
+        register_dist_tracing_root, register_w3c_tracing_root, TelemetryLayer, TraceParent,
+    };
+    use std::sync::Arc;
+    use tracing::instrument;
+    use tracing_subscriber::layer::Layer;
+    use tracing_subscriber::registry;
+
+    fn root() -> RootSpan<'static> {
+        tracing::subscriber::with_default(registry::Registry::default(), || {
+            RootSpan::new(
+                tracing::info_span!("root")
+                    .metadata()
+                    .expect("span is enabled"),
+            )
+        })
+    }
+
+    #[test]
+    fn ratio_sampler() {
+        let sampled = |sampler: RatioSampler<TestTelemetry>| {
+            (0..10_000u64)
+                .filter(|trace_id| sampler.sample(trace_id, None, &root()).is_sampled())
+                .count()
+        };
+        assert_eq!(sampled(RatioSampler::new(0.0)), 0);
+        assert_eq!(sampled(RatioSampler::new(1.0)), 10_000);
+        let quarter = sampled(RatioSampler::new(0.25));
+        assert!(quarter > 2_000 && quarter < 3_000, "sampled {}", quarter);
+        let sampler = RatioSampler::<TestTelemetry>::new(0.25);
+        assert!((0..100u64)
+            .map(|trace_id| sampler.sample(&trace_id, None, &root()))
+            .filter(SamplingDecision::is_sampled)
+            .all(|decision| decision.sample_rate() == 4));
+
+        // decisions depend only on the trace id
+        let sampler = RatioSampler::<TestTelemetry>::new(0.5);
+        for trace_id in 0..100u64 {
+            assert_eq!(
+                sampler.sample(&trace_id, None, &root()),
+                sampler.sample(&trace_id, Some(false), &root())
+            );
+        }
+
+        // decisions are derived from a fixed hash of the encoded trace id, so they never change
+        assert!(
+            (trace_id_fraction::<TestTelemetry>(&1, HEAD_SAMPLING_SEED) - 0.338_166_601).abs()
+                < 1e-9
+        );
+        assert!(
+            (trace_id_fraction::<TestTelemetry>(&135, HEAD_SAMPLING_SEED) - 0.011_229_691).abs()
+                < 1e-9
+        );
+    }
+
+    #[test]
+    fn rate_limited_sampler() {
+        let sampler = RateLimitedSampler::new(10.0);
+        let sampled = (0..100u64)
+            .filter(|trace_id| sampler.sample(trace_id, None, &root()).is_sampled())
+            .count();
+        // a burst of up to a second's worth of traces is allowed, plus whatever was refilled meanwhile
+        assert!((10..=11).contains(&sampled), "sampled {}", sampled);
+
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert_eq!(
+            sampler.sample(&0u64, None, &root()),
+            SamplingDecision::sample()
+        );
+
+        let sampler = RateLimitedSampler::new(0.0);
+        assert_eq!(sampler.sample(&0u64, None, &root()), SamplingDecision::Drop);
+    }
+
+    #[test]
+    fn parent_based_sampler() {
+        let sampler = ParentBasedSampler::new(NeverSample);
+        assert_eq!(
+            sampler.sample(&0u64, Some(true), &root()),
+            SamplingDecision::sample()
+        );
+        assert_eq!(
+            sampler.sample(&0u64, Some(false), &root()),
+            SamplingDecision::Drop
+        );
+        assert_eq!(sampler.sample(&0u64, None, &root()), SamplingDecision::Drop);
+    }
+
+    #[test]
+    fn layer_consults_sampler() {
+        let spans = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let cap = TestTelemetry::new(spans.clone(), events.clone());
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x)
+            .with_sampler(ParentBasedSampler::new(RatioSampler::<TestTelemetry>::new(
+                0.5,
+            )))
+            .with_auto_trace_roots(|| 0);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        #[instrument]
+        fn local(trace_id: TraceId) {
+            register_dist_tracing_root::<SpanId, TraceId>(trace_id, None).unwrap();
+            tracing::info!("event");
+        }
+
+        #[instrument]
+        fn remote(sampled: bool) {
+            let traceparent = TraceParent {
+                trace_id: 135,
+                parent_id: 246,
+                trace_flags: if sampled { 1 } else { 0 },
+            };
+            register_w3c_tracing_root::<TestTelemetry>(&traceparent, None).unwrap();
+            tracing::info!("event");
+        }
+
+        let sampler = RatioSampler::<TestTelemetry>::new(0.5);
+        let expected: Vec<TraceId> = (1..=20)
+            .filter(|trace_id| sampler.sample(trace_id, None, &root()).is_sampled())
+            .chain(vec![135])
+            .collect();
+
+        tracing::subscriber::with_default(subscriber, || {
+            for trace_id in 1..=20 {
+                local(trace_id);
+            }
+            remote(true);
+            remote(false);
+        });
+
+        let spans = spans.lock().unwrap();
+        let events = events.lock().unwrap();
+        let trace_ids: Vec<TraceId> = spans.iter().map(|s| s.trace_id).collect();
+        let event_trace_ids: Vec<TraceId> = events.iter().map(|e| e.trace_id).collect();
+        assert_eq!(trace_ids, expected);
+        assert_eq!(event_trace_ids, expected);
+
+        // traces sampled locally stand in for 2 traces, the remote parent's sample rate is unknown
+        let sample_rates: Vec<u64> = spans.iter().map(|s| s.sample_rate).collect();
+        let mut expected_sample_rates = vec![2; expected.len() - 1];
+        expected_sample_rates.push(1);
+        assert_eq!(sample_rates, expected_sample_rates);
+        assert_eq!(
+            events.iter().map(|e| e.sample_rate).collect::<Vec<_>>(),
+            expected_sample_rates
+        );
+    }
+}
@@ -0,0 +1,563 @@
+use crate::propagation::IdCodec;
+use crate::sampling::{trace_id_fraction, RootSpan, Sampler, SamplingDecision, HEAD_SAMPLING_SEED};
+use std::fmt;
+use std::marker::PhantomData;
+use tracing::Level;
+
+#[cfg(feature = "rules_config")]
+use serde::Deserialize;
+#[cfg(feature = "rules_config")]
+use std::collections::BTreeMap;
+
+/// What a `SamplingRule` does with the traces it matches.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub enum SamplingAction {
+    /// Sample the trace.
+    Keep,
+    /// Drop the trace.
+    Drop,
+    /// Sample one in every `n` traces, with a sample rate of `n`. A rate of 0 is treated as 1.
+    /// Decisions are derived from a fixed hash of the encoded trace id, as with `RatioSampler`.
+    SampleRate(u64),
+}
+
+impl SamplingAction {
+    fn decide<C: IdCodec>(self, trace_id: &C::TraceId) -> SamplingDecision {
+        match self {
+            SamplingAction::Keep => SamplingDecision::sample(),
+            SamplingAction::Drop => SamplingDecision::Drop,
+            SamplingAction::SampleRate(sample_rate) => {
+                let sample_rate = sample_rate.max(1);
+                if trace_id_fraction::<C>(trace_id, HEAD_SAMPLING_SEED) < 1.0 / sample_rate as f64 {
+                    SamplingDecision::Sample { sample_rate }
+                } else {
+                    SamplingDecision::Drop
+                }
+            }
+        }
+    }
+}
+
+/// Rule used by `RulesSampler` to decide whether to sample a trace, based on its local root span.
+/// A rule matches if all of its conditions hold, so a rule with no conditions matches every trace.
+#[derive(PartialEq, Clone, Debug)]
+pub struct SamplingRule {
+    /// Match root spans with the provided name.
+    pub span_name: Option<String>,
+    /// Match root spans whose target is the provided module path or one of its submodules.
+    pub target: Option<String>,
+    /// Match root spans at the provided level or a more severe one, eg `Level::WARN` matches
+    /// warnings and errors.
+    pub level: Option<Level>,
+    /// Match root spans with the provided field values. String values are compared as-is, other
+    /// values are compared to their `Debug` formatting, eg `200` or `true`.
+    pub fields: Vec<(String, String)>,
+    /// What to do with matching traces.
+    pub action: SamplingAction,
+}
+
+impl SamplingRule {
+    /// Construct a `SamplingRule` with no conditions that applies the provided action.
+    pub fn new(action: SamplingAction) -> Self {
+        SamplingRule {
+            span_name: None,
+            target: None,
+            level: None,
+            fields: Vec::new(),
+            action,
+        }
+    }
+
+    fn matches(&self, root: &RootSpan<'_>) -> bool {
+        let meta = root.metadata();
+        self.span_name
+            .as_ref()
+            .map_or(true, |span_name| meta.name() == span_name)
+            && self.target.as_ref().map_or(true, |target| {
+                meta.target().starts_with(target.as_str())
+                    && (meta.target().len() == target.len()
+                        || meta.target()[target.len()..].starts_with("::"))
+            })
+            // more verbose levels compare greater
+            && self.level.map_or(true, |level| *meta.level() <= level)
+            && self
+                .fields
+                .iter()
+                .all(|(name, value)| root.field(name) == Some(value.as_str()))
+    }
+}
+
+/// Sampler that applies the action of the first `SamplingRule` matching the local root span of
+/// each trace, similar to the rules of Honeycomb's Refinery. Traces matching no rule are sampled
+/// with the default sample rate, 1 unless configured otherwise. Trace ids are converted using `C`.
+///
+/// Rules are applied regardless of the sampling decision of the remote parent span. Wrap a
+/// `RulesSampler` in a `ParentBasedSampler` to honor propagated decisions instead.
+///
+/// Rules matching on fields only see fields recorded before the trace root was registered,
+/// eg those provided when the root span was created.
+pub struct RulesSampler<C> {
+    rules: Vec<SamplingRule>,
+    default_sample_rate: u64,
+    codec: PhantomData<fn() -> C>,
+}
+
+impl<C> RulesSampler<C> {
+    /// Construct a `RulesSampler` that applies the provided rules in order, keeping traces that
+    /// match no rule.
+    pub fn new(rules: Vec<SamplingRule>) -> Self {
+        RulesSampler {
+            rules,
+            default_sample_rate: 1,
+            codec: PhantomData,
+        }
+    }
+
+    /// Sample one in every `sample_rate` traces that match no rule, instead of all of them. A rate
+    /// of 0 is treated as 1. Add a final rule with no conditions to drop them instead.
+    pub fn with_default_sample_rate(mut self, sample_rate: u64) -> Self {
+        self.default_sample_rate = sample_rate.max(1);
+        self
+    }
+
+    /// Load sampling rules from a TOML document, eg:
+    ///
+    /// ```toml
+    /// # applied to traces matching no rule, defaults to 1
+    /// default_sample_rate = 10
+    ///
+    /// [[rules]]
+    /// span_name = "request"
+    /// fields = { "http.route" = "/health" }
+    /// drop = true
+    ///
+    /// [[rules]]
+    /// level = "warn"
+    /// keep = true
+    ///
+    /// [[rules]]
+    /// target = "my_app::batch"
+    /// sample_rate = 100
+    /// ```
+    ///
+    /// Each rule must set exactly one of `keep = true`, `drop = true` or `sample_rate`.
+    #[cfg(feature = "rules_config")]
+    pub fn from_toml(document: &str) -> Result<Self, RulesConfigError> {
+        let config: RulesConfig =
+            toml::from_str(document).map_err(|e| RulesConfigError::Parse(e.to_string()))?;
+        config.into_sampler()
+    }
+
+    /// Load sampling rules from a JSON document with the same structure as the TOML document
+    /// accepted by `from_toml`, eg `{"rules": [{"level": "warn", "keep": true}]}`.
+    #[cfg(feature = "rules_config")]
+    pub fn from_json(document: &str) -> Result<Self, RulesConfigError> {
+        let config: RulesConfig =
+            serde_json::from_str(document).map_err(|e| RulesConfigError::Parse(e.to_string()))?;
+        config.into_sampler()
+    }
+}
+
+impl<C> Clone for RulesSampler<C> {
+    fn clone(&self) -> Self {
+        RulesSampler {
+            rules: self.rules.clone(),
+            default_sample_rate: self.default_sample_rate,
+            codec: PhantomData,
+        }
+    }
+}
+
+impl<C> PartialEq for RulesSampler<C> {
+    fn eq(&self, other: &Self) -> bool {
+        self.rules == other.rules && self.default_sample_rate == other.default_sample_rate
+    }
+}
+
+impl<C> fmt::Debug for RulesSampler<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RulesSampler")
+            .field("rules", &self.rules)
+            .field("default_sample_rate", &self.default_sample_rate)
+            .finish()
+    }
+}
+
+impl<C: IdCodec> Sampler<C::TraceId> for RulesSampler<C> {
+    fn sample(
+        &self,
+        trace_id: &C::TraceId,
+        _: Option<bool>,
+        root: &RootSpan<'_>,
+    ) -> SamplingDecision {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(root))
+            .map_or(
+                SamplingAction::SampleRate(self.default_sample_rate),
+                |rule| rule.action,
+            )
+            .decide::<C>(trace_id)
+    }
+
+    fn uses_fields(&self) -> bool {
+        self.rules.iter().any(|rule| !rule.fields.is_empty())
+    }
+}
+
+/// Errors that can occur while loading sampling rules via `RulesSampler::from_toml` or
+/// `RulesSampler::from_json`. Rules are identified by their (zero-based) position.
+#[cfg(feature = "rules_config")]
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+#[non_exhaustive]
+pub enum RulesConfigError {
+    /// The document is malformed or doesn't have the expected structure.
+    Parse(String),
+    /// The rule's `level` is not one of `error`, `warn`, `info`, `debug` or `trace`.
+    InvalidLevel {
+        /// position of the rule
+        rule: usize,
+        /// the unrecognized level
+        level: String,
+    },
+    /// The rule sets more or less than one of `keep = true`, `drop = true` or `sample_rate`.
+    InvalidAction {
+        /// position of the rule
+        rule: usize,
+    },
+}
+
+#[cfg(feature = "rules_config")]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesConfig {
+    #[serde(default)]
+    rules: Vec<RuleConfig>,
+    default_sample_rate: Option<u64>,
+}
+
+#[cfg(feature = "rules_config")]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleConfig {
+    span_name: Option<String>,
+    target: Option<String>,
+    level: Option<String>,
+    #[serde(default)]
+    fields: BTreeMap<String, FieldValue>,
+    #[serde(default)]
+    keep: bool,
+    #[serde(default)]
+    drop: bool,
+    sample_rate: Option<u64>,
+}
+
+// field values may be written as any scalar, and are matched against their recorded formatting
+#[cfg(feature = "rules_config")]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FieldValue {
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    String(String),
+}
+
+#[cfg(feature = "rules_config")]
+impl FieldValue {
+    fn into_string(self) -> String {
+        match self {
+            FieldValue::Bool(b) => b.to_string(),
+            FieldValue::Int(i) => i.to_string(),
+            FieldValue::UInt(u) => u.to_string(),
+            FieldValue::Float(f) => format!("{:?}", f),
+            FieldValue::String(s) => s,
+        }
+    }
+}
+
+#[cfg(feature = "rules_config")]
+impl RulesConfig {
+    fn into_sampler<C>(self) -> Result<RulesSampler<C>, RulesConfigError> {
+        let rules = self
+            .rules
+            .into_iter()
+            .enumerate()
+            .map(|(idx, rule)| rule.into_rule(idx))
+            .collect::<Result<Vec<_>, _>>()?;
+        let sampler = RulesSampler::new(rules);
+        Ok(match self.default_sample_rate {
+            Some(sample_rate) => sampler.with_default_sample_rate(sample_rate),
+            None => sampler,
+        })
+    }
+}
+
+#[cfg(feature = "rules_config")]
+impl RuleConfig {
+    fn into_rule(self, idx: usize) -> Result<SamplingRule, RulesConfigError> {
+        let action = match (self.keep, self.drop, self.sample_rate) {
+            (true, false, None) => SamplingAction::Keep,
+            (false, true, None) => SamplingAction::Drop,
+            (false, false, Some(sample_rate)) => SamplingAction::SampleRate(sample_rate),
+            _ => return Err(RulesConfigError::InvalidAction { rule: idx }),
+        };
+        let level = match self.level {
+            Some(level) => {
+                Some(
+                    level
+                        .parse::<Level>()
+                        .map_err(|_| RulesConfigError::InvalidLevel {
+                            rule: idx,
+                            level: level.clone(),
+                        })?,
+                )
+            }
+            None => None,
+        };
+
+        Ok(SamplingRule {
+            span_name: self.span_name,
+            target: self.target,
+            level,
+            fields: self
+                .fields
+                .into_iter()
+                .map(|(name, value)| (name, value.into_string()))
+                .collect(),
+            action,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test::{SpanId, TestTelemetry, TraceId};
+    use crate::{register_dist_tracing_root, ParentBasedSampler, TelemetryLayer};
+    use std::sync::Arc;
+    use tracing::Metadata;
+    use tracing_subscriber::layer::Layer;
+    use tracing_subscriber::registry;
+
+    fn root<F: FnOnce() -> tracing::Span>(mk_span: F) -> RootSpan<'static> {
+        tracing::subscriber::with_default(registry::Registry::default(), || {
+            let meta: &'static Metadata<'static> = mk_span().metadata().expect("span is enabled");
+            RootSpan::new(meta)
+        })
+    }
+
+    fn sampler() -> RulesSampler<TestTelemetry> {
+        RulesSampler::new(vec![
+            SamplingRule {
+                span_name: Some("health".to_string()),
+                ..SamplingRule::new(SamplingAction::Drop)
+            },
+            SamplingRule {
+                level: Some(Level::WARN),
+                ..SamplingRule::new(SamplingAction::Keep)
+            },
+            SamplingRule {
+                target: Some("my_app::batch".to_string()),
+                ..SamplingRule::new(SamplingAction::SampleRate(4))
+            },
+        ])
+        .with_default_sample_rate(2)
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let sampler = sampler();
+        let decisions = |root: RootSpan<'static>| -> Vec<SamplingDecision> {
+            (0..1_000u64)
+                .map(|trace_id| sampler.sample(&trace_id, Some(true), &root))
+                .collect()
+        };
+        let sampled = |root: RootSpan<'static>| {
+            decisions(root)
+                .into_iter()
+                .filter(SamplingDecision::is_sampled)
+                .count()
+        };
+
+        // earlier rules take precedence over later ones, regardless of the parent's decision
+        assert_eq!(sampled(root(|| tracing::info_span!("health"))), 0);
+        assert_eq!(sampled(root(|| tracing::error_span!("health"))), 0);
+        assert!(decisions(root(|| tracing::warn_span!("request")))
+            .iter()
+            .all(|decision| *decision == SamplingDecision::sample()));
+        assert_eq!(sampled(root(|| tracing::error_span!("request"))), 1_000);
+        assert_eq!(
+            sampled(root(
+                || tracing::error_span!(target: "my_app::batch", "job")
+            )),
+            1_000
+        );
+
+        // targets match submodules, but not modules that merely share a prefix
+        let batch = decisions(root(
+            || tracing::info_span!(target: "my_app::batch::nightly", "job"),
+        ));
+        let kept = batch.iter().filter(|d| d.is_sampled()).count();
+        assert!(kept > 150 && kept < 350, "kept {}", kept);
+        assert!(batch
+            .iter()
+            .filter(|d| d.is_sampled())
+            .all(|d| d.sample_rate() == 4));
+        let other = decisions(root(
+            || tracing::info_span!(target: "my_app::batches", "job"),
+        ));
+        let kept = other.iter().filter(|d| d.is_sampled()).count();
+        assert!(kept > 400 && kept < 600, "kept {}", kept);
+        assert!(other
+            .iter()
+            .filter(|d| d.is_sampled())
+            .all(|d| d.sample_rate() == 2));
+    }
+
+    #[test]
+    fn target_rules_match_module_boundaries() {
+        let rule = SamplingRule {
+            target: Some("foo".to_string()),
+            ..SamplingRule::new(SamplingAction::Keep)
+        };
+        assert!(rule.matches(&root(|| tracing::info_span!(target: "foo", "span"))));
+        assert!(rule.matches(&root(|| tracing::info_span!(target: "foo::bar", "span"))));
+        assert!(!rule.matches(&root(|| tracing::info_span!(target: "foobar", "span"))));
+        assert!(!rule.matches(&root(|| tracing::info_span!(target: "foobar::foo", "span"))));
+        assert!(!rule.matches(&root(|| tracing::info_span!(target: "fo", "span"))));
+        assert!(!rule.matches(&root(|| tracing::info_span!(target: "bar::foo", "span"))));
+    }
+
+    #[test]
+    fn rule_conditions_must_all_hold() {
+        let rule = SamplingRule {
+            span_name: Some("request".to_string()),
+            level: Some(Level::WARN),
+            ..SamplingRule::new(SamplingAction::Keep)
+        };
+        assert!(rule.matches(&root(|| tracing::warn_span!("request"))));
+        assert!(rule.matches(&root(|| tracing::error_span!("request"))));
+        assert!(!rule.matches(&root(|| tracing::info_span!("request"))));
+        assert!(!rule.matches(&root(|| tracing::error_span!("response"))));
+
+        // a rule with no conditions matches every root span
+        let rule = SamplingRule::new(SamplingAction::Keep);
+        assert!(rule.matches(&root(|| tracing::trace_span!(target: "any", "span"))));
+    }
+
+    #[test]
+    fn field_rules() {
+        let spans = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let cap = TestTelemetry::new(spans.clone(), events.clone());
+        let rules = RulesSampler::<TestTelemetry>::new(vec![
+            SamplingRule {
+                fields: vec![("http.route".to_string(), "/health".to_string())],
+                ..SamplingRule::new(SamplingAction::Drop)
+            },
+            SamplingRule {
+                fields: vec![("status".to_string(), "500".to_string())],
+                ..SamplingRule::new(SamplingAction::Keep)
+            },
+            SamplingRule::new(SamplingAction::Drop),
+        ]);
+        assert!(Sampler::<TraceId>::uses_fields(&rules));
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x)
+            .with_sampler(ParentBasedSampler::new(rules));
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        fn request(trace_id: TraceId, route: &str, status: Option<u64>) {
+            let span = tracing::info_span!(
+                "request",
+                http.route = route,
+                status = tracing::field::Empty
+            );
+            if let Some(status) = status {
+                span.record("status", status);
+            }
+            let _guard = span.enter();
+            register_dist_tracing_root::<SpanId, TraceId>(trace_id, None).unwrap();
+        }
+
+        tracing::subscriber::with_default(subscriber, || {
+            request(1, "/health", Some(500));
+            request(2, "/users", Some(500));
+            request(3, "/users", Some(200));
+            request(4, "/users", None);
+        });
+
+        let trace_ids: Vec<TraceId> = spans.lock().unwrap().iter().map(|s| s.trace_id).collect();
+        assert_eq!(trace_ids, vec![2]);
+    }
+
+    #[cfg(feature = "rules_config")]
+    #[test]
+    fn rules_from_toml_and_json() {
+        let toml = r#"
+            default_sample_rate = 2
+
+            [[rules]]
+            span_name = "health"
+            drop = true
+
+            [[rules]]
+            level = "warn"
+            keep = true
+
+            [[rules]]
+            target = "my_app::batch"
+            sample_rate = 4
+        "#;
+        assert_eq!(RulesSampler::from_toml(toml), Ok(sampler()));
+
+        let json = r#"{
+            "default_sample_rate": 2,
+            "rules": [
+                {"span_name": "health", "drop": true},
+                {"level": "WARN", "keep": true},
+                {"target": "my_app::batch", "sample_rate": 4}
+            ]
+        }"#;
+        assert_eq!(RulesSampler::from_json(json), Ok(sampler()));
+
+        let fields = r#"
+            [[rules]]
+            fields = { "http.route" = "/health", status = 500, cached = true }
+            drop = true
+        "#;
+        let mut expected = SamplingRule::new(SamplingAction::Drop);
+        expected.fields = vec![
+            ("cached".to_string(), "true".to_string()),
+            ("http.route".to_string(), "/health".to_string()),
+            ("status".to_string(), "500".to_string()),
+        ];
+        assert_eq!(
+            RulesSampler::<TestTelemetry>::from_toml(fields),
+            Ok(RulesSampler::new(vec![expected]))
+        );
+
+        assert_eq!(
+            RulesSampler::<TestTelemetry>::from_toml(
+                "[[rules]]\nkeep = true\n\n[[rules]]\nkeep = true\ndrop = true"
+            ),
+            Err(RulesConfigError::InvalidAction { rule: 1 })
+        );
+        assert_eq!(
+            RulesSampler::<TestTelemetry>::from_json(r#"{"rules": [{"span_name": "health"}]}"#),
+            Err(RulesConfigError::InvalidAction { rule: 0 })
+        );
+        assert_eq!(
+            RulesSampler::<TestTelemetry>::from_toml("[[rules]]\nlevel = \"loud\"\nkeep = true"),
+            Err(RulesConfigError::InvalidLevel {
+                rule: 0,
+                level: "loud".to_string()
+            })
+        );
+        assert!(matches!(
+            RulesSampler::<TestTelemetry>::from_toml("[[rules]]\nname = \"health\"\nkeep = true"),
+            Err(RulesConfigError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,133 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters describing the spans and events observed by a `TelemetryLayer`, along with what
+/// happened to them. Obtained via `TelemetryLayer::stats` or `dist_telemetry_stats`, eg to export
+/// them to some metrics system.
+///
+/// Spans are counted once they close. Every span or event observed is either reported to the
+/// layer's `Telemetry` instance or dropped for one of the reasons counted below.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, Default)]
+pub struct TelemetryStats {
+    /// Spans closed.
+    pub spans_observed: u64,
+    /// Events recorded.
+    pub events_observed: u64,
+    /// Spans handed to the `Telemetry` instance.
+    pub spans_reported: u64,
+    /// Events handed to the `Telemetry` instance.
+    pub events_reported: u64,
+    /// Spans dropped because their trace was not sampled.
+    pub spans_unsampled: u64,
+    /// Events dropped because their trace was not sampled.
+    pub events_unsampled: u64,
+    /// Spans dropped because they were not part of any trace.
+    pub spans_without_trace_ctx: u64,
+    /// Events dropped because they were not part of any trace.
+    pub events_without_trace_ctx: u64,
+    /// Number of trace ctxs currently registered for local trace roots.
+    pub registered_trace_ctxs: usize,
+    /// Counters reported by the `Telemetry` instance for the data handed to it.
+    pub backend: BackendStats,
+}
+
+/// Counters describing what a `Telemetry` instance did with the spans and events reported to it.
+/// Spans and events are counted as they're delivered to the backend, so a span along with its
+/// links may count as several items.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, Default)]
+pub struct BackendStats {
+    /// Items dropped by sampling performed by the backend itself, eg tail sampling.
+    pub dropped_sampling: u64,
+    /// Items dropped because some buffer was full.
+    pub dropped_buffer_full: u64,
+    /// Items dropped because they were reported after the backend was shut down.
+    pub dropped_shut_down: u64,
+    /// Errors returned while exporting items to the backend.
+    pub export_errors: u64,
+    /// Number of items currently buffered, awaiting export.
+    pub pending: usize,
+}
+
+impl BackendStats {
+    /// Sum of the counters of two backends, eg those wrapped by a `FanOutTelemetry`.
+    pub fn combine(self, other: BackendStats) -> BackendStats {
+        BackendStats {
+            dropped_sampling: self.dropped_sampling + other.dropped_sampling,
+            dropped_buffer_full: self.dropped_buffer_full + other.dropped_buffer_full,
+            dropped_shut_down: self.dropped_shut_down + other.dropped_shut_down,
+            export_errors: self.export_errors + other.export_errors,
+            pending: self.pending + other.pending,
+        }
+    }
+}
+
+// what happened to a span or event observed by the layer
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub(crate) enum Outcome {
+    Reported,
+    Unsampled,
+    NoTraceCtx,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct LayerCounters {
+    spans: OutcomeCounters,
+    events: OutcomeCounters,
+}
+
+#[derive(Debug, Default)]
+struct OutcomeCounters {
+    reported: AtomicU64,
+    unsampled: AtomicU64,
+    no_trace_ctx: AtomicU64,
+}
+
+impl OutcomeCounters {
+    fn record(&self, outcome: Outcome) {
+        let counter = match outcome {
+            Outcome::Reported => &self.reported,
+            Outcome::Unsampled => &self.unsampled,
+            Outcome::NoTraceCtx => &self.no_trace_ctx,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // (reported, unsampled, no trace ctx)
+    fn load(&self) -> (u64, u64, u64) {
+        (
+            self.reported.load(Ordering::Relaxed),
+            self.unsampled.load(Ordering::Relaxed),
+            self.no_trace_ctx.load(Ordering::Relaxed),
+        )
+    }
+}
+
+impl LayerCounters {
+    pub(crate) fn record_span(&self, outcome: Outcome) {
+        self.spans.record(outcome);
+    }
+
+    pub(crate) fn record_event(&self, outcome: Outcome) {
+        self.events.record(outcome);
+    }
+
+    pub(crate) fn stats(
+        &self,
+        registered_trace_ctxs: usize,
+        backend: BackendStats,
+    ) -> TelemetryStats {
+        let (spans_reported, spans_unsampled, spans_without_trace_ctx) = self.spans.load();
+        let (events_reported, events_unsampled, events_without_trace_ctx) = self.events.load();
+        TelemetryStats {
+            spans_observed: spans_reported + spans_unsampled + spans_without_trace_ctx,
+            events_observed: events_reported + events_unsampled + events_without_trace_ctx,
+            spans_reported,
+            events_reported,
+            spans_unsampled,
+            events_unsampled,
+            spans_without_trace_ctx,
+            events_without_trace_ctx,
+            registered_trace_ctxs,
+            backend,
+        }
+    }
+}
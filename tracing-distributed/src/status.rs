@@ -0,0 +1,206 @@
+use std::fmt;
+use tracing::field::{Field, Visit};
+
+/// Whether the operation represented by a span completed successfully, as derived from the fields
+/// and events observed in that span using some `StatusConvention`.
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub enum SpanStatus {
+    /// No error was observed in the span.
+    Ok,
+    /// The span failed, with a message describing the first error observed in it.
+    Error(String),
+}
+
+/// Convention used to derive a `SpanStatus` from the fields and events observed in a span.
+///
+/// The default convention recognizes the errors recorded by `#[instrument(err)]`, which records
+/// an `ERROR`-level event with an `error` field inside the instrumented span.
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub struct StatusConvention {
+    /// Name of a field that marks a span as failed if recorded on the span itself or on an event
+    /// inside it, using the field's value as the error message.
+    pub error_field: Option<&'static str>,
+    /// Whether an `ERROR`-level event inside a span marks it as failed, using the event's message
+    /// (or name, if it has no message) as the error message.
+    pub error_level_events: bool,
+}
+
+impl Default for StatusConvention {
+    fn default() -> Self {
+        StatusConvention {
+            error_field: Some("error"),
+            error_level_events: true,
+        }
+    }
+}
+
+impl StatusConvention {
+    /// Convention under which every span has status `SpanStatus::Ok`.
+    pub fn disabled() -> Self {
+        StatusConvention {
+            error_field: None,
+            error_level_events: false,
+        }
+    }
+
+    // derives an error message from the fields of some span
+    pub(crate) fn span_error(&self, record: impl FnOnce(&mut ErrorVisitor)) -> Option<String> {
+        let error_field = self.error_field?;
+        let mut visitor = ErrorVisitor::new(error_field);
+        record(&mut visitor);
+        visitor.error
+    }
+
+    // derives an error message from the metadata and fields of some event
+    pub(crate) fn event_error(&self, event: &tracing::Event<'_>) -> Option<String> {
+        let is_error_level = *event.metadata().level() == tracing::Level::ERROR;
+        if self.error_field.is_none() && !(self.error_level_events && is_error_level) {
+            return None;
+        }
+
+        let mut visitor = ErrorVisitor::new(self.error_field.unwrap_or_default());
+        event.record(&mut visitor);
+
+        match visitor.error {
+            Some(error) => Some(error),
+            None if self.error_level_events && is_error_level => Some(
+                visitor
+                    .message
+                    .unwrap_or_else(|| event.metadata().name().to_string()),
+            ),
+            None => None,
+        }
+    }
+}
+
+// records the value of the error field and the message of spans and events
+pub(crate) struct ErrorVisitor {
+    error_field: &'static str,
+    error: Option<String>,
+    message: Option<String>,
+}
+
+impl ErrorVisitor {
+    fn new(error_field: &'static str) -> Self {
+        ErrorVisitor {
+            error_field,
+            error: None,
+            message: None,
+        }
+    }
+
+    fn record(&mut self, field: &Field, value: String) {
+        if field.name() == self.error_field {
+            self.error = Some(value);
+        } else if field.name() == "message" {
+            self.message = Some(value);
+        }
+    }
+}
+
+impl Visit for ErrorVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, value.to_string())
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record(field, format!("{:?}", value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test::{SpanId, TestTelemetry, TraceId};
+    use crate::{register_dist_tracing_root, TelemetryLayer};
+    use std::sync::{Arc, Mutex};
+    use tracing::instrument;
+    use tracing_subscriber::layer::Layer;
+    use tracing_subscriber::registry;
+
+    #[instrument]
+    fn root(f: fn()) {
+        register_dist_tracing_root::<SpanId, TraceId>(135, None).unwrap();
+        f()
+    }
+
+    #[instrument(err)]
+    fn fails() -> Result<(), String> {
+        Err("boom".to_string())
+    }
+
+    #[instrument(err)]
+    fn succeeds() -> Result<(), String> {
+        tracing::warn!("not an error");
+        Ok(())
+    }
+
+    #[instrument(fields(error))]
+    fn records_error_field() {
+        tracing::Span::current().record("error", "field error");
+        tracing::error!("later error");
+    }
+
+    #[instrument]
+    fn logs_error() {
+        tracing::error!("logged error");
+    }
+
+    fn statuses(status_convention: StatusConvention) -> Vec<(&'static str, SpanStatus)> {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let cap = TestTelemetry::new(spans.clone(), Arc::default());
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x)
+            .with_status_convention(status_convention);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            root(|| {
+                let _ = fails();
+            });
+            root(|| {
+                let _ = succeeds();
+            });
+            root(records_error_field);
+            root(logs_error);
+        });
+
+        let spans = spans.lock().unwrap();
+        spans
+            .iter()
+            .filter(|span| span.meta.name() != "root")
+            .map(|span| (span.meta.name(), span.status.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn default_convention() {
+        assert_eq!(
+            statuses(StatusConvention::default()),
+            vec![
+                ("fails", SpanStatus::Error("boom".to_string())),
+                ("succeeds", SpanStatus::Ok),
+                (
+                    "records_error_field",
+                    SpanStatus::Error("field error".to_string())
+                ),
+                ("logs_error", SpanStatus::Error("logged error".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_conventions() {
+        let error_field_only = StatusConvention {
+            error_level_events: false,
+            ..StatusConvention::default()
+        };
+        assert_eq!(
+            statuses(error_field_only)[3],
+            ("logs_error", SpanStatus::Ok)
+        );
+
+        assert!(statuses(StatusConvention::disabled())
+            .iter()
+            .all(|(_, status)| *status == SpanStatus::Ok));
+    }
+}
@@ -0,0 +1,530 @@
+use crate::propagation::IdCodec;
+use crate::sampling::trace_id_fraction;
+use crate::stats::BackendStats;
+use crate::status::SpanStatus;
+use crate::telemetry::{FlushError, Telemetry};
+use crate::trace::{Event, Span};
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+// seed of the hash from which `TailSamplingRule::Random` decisions are derived, distinct from that
+// of head samplers so that the two decisions are independent
+const TAIL_SAMPLING_SEED: u64 = 0x7461_696c;
+
+/// Rule used by `TailSamplingTelemetry` to decide whether to keep a trace once it's complete.
+#[derive(PartialEq, Clone, Debug)]
+pub enum TailSamplingRule {
+    /// Keep traces containing a span with status `SpanStatus::Error`.
+    AnyError,
+    /// Keep traces containing a span that took at least the provided duration.
+    MinDuration(Duration),
+    /// Keep traces containing a span with the provided name.
+    SpanName(String),
+    /// Keep traces at random, with the provided probability between 0 and 1. Typically used as a
+    /// fallback, to keep a sample of traces not matched by any other rule.
+    ///
+    /// The decision is derived from the trace id, so that every part of a trace evaluated
+    /// separately (eg in other services) is kept or dropped together. It is independent of head
+    /// sampling decisions (eg by `RatioSampler`), so this rule keeps the provided share of head-sampled
+    /// traces. Traces only kept by this rule have their sample rate multiplied by the inverse of the
+    /// probability (rounded to the nearest integer), so that backends re-weight counts.
+    Random(f64),
+}
+
+/// What `TailSamplingTelemetry` does with traces evicted before their local root span completes,
+/// either because they were buffered for longer than `TailSamplingConfig::trace_timeout` or to
+/// make room for new traces.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub enum IncompleteTracePolicy {
+    /// Drop the trace.
+    Drop,
+    /// Evaluate the sampling rules against the spans buffered so far, reporting them if kept.
+    Evaluate,
+}
+
+/// Configuration for `TailSamplingTelemetry`.
+#[derive(PartialEq, Clone, Debug)]
+pub struct TailSamplingConfig {
+    /// Traces are kept if any rule matches, and dropped otherwise.
+    pub rules: Vec<TailSamplingRule>,
+    /// Maximum number of traces buffered at once. The oldest buffered trace is evicted to make room
+    /// for new traces once this limit is reached.
+    pub max_traces: usize,
+    /// Maximum number of spans and events buffered per trace, any further spans and events are dropped.
+    pub max_spans_per_trace: usize,
+    /// Traces buffered for longer than this are evicted.
+    pub trace_timeout: Duration,
+    /// What to do with evicted traces.
+    pub incomplete_trace_policy: IncompleteTracePolicy,
+}
+
+impl TailSamplingConfig {
+    /// Construct a `TailSamplingConfig` using the provided rules, buffering up to 10,000 traces of
+    /// up to 1,000 spans and events for up to a minute.
+    pub fn new(rules: Vec<TailSamplingRule>) -> Self {
+        TailSamplingConfig {
+            rules,
+            max_traces: 10_000,
+            max_spans_per_trace: 1_000,
+            trace_timeout: Duration::from_secs(60),
+            incomplete_trace_policy: IncompleteTracePolicy::Evaluate,
+        }
+    }
+}
+
+/// Telemetry capability that buffers the spans and events of each trace until its local root span
+/// completes, then reports the entire trace to the wrapped `Telemetry` instance if it matches any
+/// of the configured `TailSamplingRule`s. This allows keeping rare slow or failing traces that
+/// head sampling (which decides before the trace starts) would usually drop.
+///
+/// Traces with multiple local roots in the same process are evaluated separately for each local root.
+/// Trace ids are converted using the wrapped `Telemetry` instance's `IdCodec` implementation.
+pub struct TailSamplingTelemetry<T: Telemetry> {
+    telemetry: T,
+    config: TailSamplingConfig,
+    buffer: Mutex<TraceBuffer<T::Visitor, T::SpanId, T::TraceId>>,
+    // spans and events of traces that were not kept
+    dropped_sampling: AtomicU64,
+    // spans and events past the per-trace limit
+    dropped_buffer_full: AtomicU64,
+}
+
+struct TraceBuffer<V, SpanId, TraceId> {
+    traces: HashMap<TraceId, BufferedTrace<V, SpanId, TraceId>>,
+    // trace ids in the order they were first buffered, along with when that happened
+    arrivals: VecDeque<(Instant, TraceId)>,
+}
+
+struct BufferedTrace<V, SpanId, TraceId> {
+    trace_id: TraceId,
+    buffered_at: Instant,
+    // spans and events in the order they were reported, so that events are reported before their parent span
+    items: Vec<Item<V, SpanId, TraceId>>,
+}
+
+// traces returned from `TailSamplingTelemetry::buffer`, along with whether they're complete
+type ReleasedTraces<T> = Vec<(
+    bool,
+    BufferedTrace<<T as Telemetry>::Visitor, <T as Telemetry>::SpanId, <T as Telemetry>::TraceId>,
+)>;
+
+enum Item<V, SpanId, TraceId> {
+    Span(Span<V, SpanId, TraceId>),
+    Event(Event<V, SpanId, TraceId>),
+}
+
+impl<T> TailSamplingTelemetry<T>
+where
+    T: Telemetry + IdCodec<TraceId = <T as Telemetry>::TraceId>,
+    <T as Telemetry>::TraceId: Hash + Eq,
+{
+    /// Construct a `TailSamplingTelemetry` that reports traces kept under the provided config to
+    /// the provided `Telemetry` instance.
+    pub fn new(telemetry: T, config: TailSamplingConfig) -> Self {
+        TailSamplingTelemetry {
+            telemetry,
+            config,
+            buffer: Mutex::new(TraceBuffer {
+                traces: HashMap::new(),
+                arrivals: VecDeque::new(),
+            }),
+            dropped_sampling: AtomicU64::new(0),
+            dropped_buffer_full: AtomicU64::new(0),
+        }
+    }
+
+    // buffers an item, returning any traces that are complete or were evicted as a result
+    fn buffer(
+        &self,
+        trace_id: <T as Telemetry>::TraceId,
+        item: Item<T::Visitor, <T as Telemetry>::SpanId, <T as Telemetry>::TraceId>,
+        completes_trace: bool,
+    ) -> ReleasedTraces<T> {
+        // succeed or die. failure is unrecoverable (mutex poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let mut buffer = self.buffer.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let mut buffer = self.buffer.lock();
+
+        let now = Instant::now();
+        let mut res = Vec::new();
+
+        // evict traces that timed out, along with the oldest traces if there's no room for a new trace
+        while let Some((buffered_at, oldest)) = buffer.arrivals.front().cloned() {
+            let timed_out = now.saturating_duration_since(buffered_at) >= self.config.trace_timeout;
+            let full = !buffer.traces.contains_key(&trace_id)
+                && buffer.traces.len() >= self.config.max_traces;
+            if !(timed_out || full) {
+                break;
+            }
+            buffer.arrivals.pop_front();
+            // traces that already completed are no longer buffered, or were buffered again later
+            let evict = match buffer.traces.get(&oldest) {
+                Some(trace) => trace.buffered_at == buffered_at,
+                None => false,
+            };
+            if evict {
+                let trace = buffer.traces.remove(&oldest).expect("trace not buffered");
+                res.push((false, trace));
+            }
+        }
+
+        let (is_new, mut trace) = match buffer.traces.remove(&trace_id) {
+            Some(trace) => (false, trace),
+            None => (
+                true,
+                BufferedTrace {
+                    trace_id: trace_id.clone(),
+                    buffered_at: now,
+                    items: Vec::new(),
+                },
+            ),
+        };
+        if trace.items.len() < self.config.max_spans_per_trace {
+            trace.items.push(item);
+        } else {
+            self.dropped_buffer_full.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if completes_trace {
+            res.push((true, trace));
+        } else {
+            if is_new {
+                buffer.arrivals.push_back((now, trace_id.clone()));
+            }
+            buffer.traces.insert(trace_id, trace);
+        }
+
+        // arrivals of completed traces are only removed once they time out, so drop them
+        // early if they start to outnumber buffered traces
+        if buffer.arrivals.len() > 2 * self.config.max_traces {
+            let TraceBuffer { traces, arrivals } = &mut *buffer;
+            arrivals.retain(|(buffered_at, trace_id)| match traces.get(trace_id) {
+                Some(trace) => trace.buffered_at == *buffered_at,
+                None => false,
+            });
+        }
+
+        res
+    }
+
+    fn evaluate(
+        &self,
+        complete: bool,
+        trace: BufferedTrace<T::Visitor, <T as Telemetry>::SpanId, <T as Telemetry>::TraceId>,
+    ) {
+        if !complete && self.config.incomplete_trace_policy == IncompleteTracePolicy::Drop {
+            self.dropped_sampling
+                .fetch_add(trace.items.len() as u64, Ordering::Relaxed);
+            return;
+        }
+
+        let spans = || {
+            trace.items.iter().filter_map(|item| match item {
+                Item::Span(span) => Some(span),
+                Item::Event(_) => None,
+            })
+        };
+        let matched = self.config.rules.iter().any(|rule| match rule {
+            TailSamplingRule::AnyError => spans().any(|span| span.status != SpanStatus::Ok),
+            TailSamplingRule::MinDuration(min_duration) => spans().any(|span| {
+                span.completed_at
+                    .duration_since(span.initialized_at)
+                    .map_or(false, |duration| duration >= *min_duration)
+            }),
+            TailSamplingRule::SpanName(name) => spans().any(|span| span.meta.name() == name),
+            TailSamplingRule::Random(_) => false,
+        });
+        // traces not matched by any other rule stand in for all those dropped by the random rules
+        let sample_rate = if matched {
+            Some(1)
+        } else {
+            let probability = self
+                .config
+                .rules
+                .iter()
+                .filter_map(|rule| match rule {
+                    TailSamplingRule::Random(probability) => Some(*probability),
+                    _ => None,
+                })
+                .fold(0.0, f64::max);
+            if trace_id_fraction::<T>(&trace.trace_id, TAIL_SAMPLING_SEED) < probability {
+                Some((1.0 / probability.min(1.0)).round() as u64)
+            } else {
+                None
+            }
+        };
+
+        if let Some(sample_rate) = sample_rate {
+            for item in trace.items {
+                match item {
+                    Item::Span(mut span) => {
+                        span.sample_rate = span.sample_rate.saturating_mul(sample_rate);
+                        self.telemetry.report_span(span)
+                    }
+                    Item::Event(mut event) => {
+                        event.sample_rate = event.sample_rate.saturating_mul(sample_rate);
+                        self.telemetry.report_event(event)
+                    }
+                }
+            }
+        } else {
+            self.dropped_sampling
+                .fetch_add(trace.items.len() as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<T> Telemetry for TailSamplingTelemetry<T>
+where
+    T: Telemetry + IdCodec<TraceId = <T as Telemetry>::TraceId>,
+    <T as Telemetry>::TraceId: Hash + Eq,
+{
+    type Visitor = T::Visitor;
+    type TraceId = <T as Telemetry>::TraceId;
+    type SpanId = <T as Telemetry>::SpanId;
+
+    fn mk_visitor(&self) -> Self::Visitor {
+        self.telemetry.mk_visitor()
+    }
+
+    fn report_span(&self, span: Span<Self::Visitor, Self::SpanId, Self::TraceId>) {
+        let trace_id = span.trace_id.clone();
+        let local_root = span.local_root;
+        // traces are evaluated and reported without holding the lock
+        for (complete, trace) in self.buffer(trace_id, Item::Span(span), local_root) {
+            self.evaluate(complete, trace);
+        }
+    }
+
+    fn report_event(&self, event: Event<Self::Visitor, Self::SpanId, Self::TraceId>) {
+        let trace_id = event.trace_id.clone();
+        for (complete, trace) in self.buffer(trace_id, Item::Event(event), false) {
+            self.evaluate(complete, trace);
+        }
+    }
+
+    // traces still buffered are incomplete, so they're left to complete or time out
+    fn flush(&self, timeout: Duration) -> Result<(), FlushError> {
+        self.telemetry.flush(timeout)
+    }
+
+    // evicts all buffered traces, handling them according to the incomplete trace policy
+    fn shutdown(&self, timeout: Duration) -> Result<(), FlushError> {
+        let deadline = Instant::now() + timeout;
+        let traces: Vec<_> = {
+            // succeed or die. failure is unrecoverable (mutex poisoned)
+            #[cfg(not(feature = "use_parking_lot"))]
+            let mut buffer = self.buffer.lock().unwrap();
+            #[cfg(feature = "use_parking_lot")]
+            let mut buffer = self.buffer.lock();
+
+            buffer.arrivals.clear();
+            buffer.traces.drain().map(|(_, trace)| trace).collect()
+        };
+        for trace in traces {
+            self.evaluate(false, trace);
+        }
+        self.telemetry
+            .shutdown(deadline.saturating_duration_since(Instant::now()))
+    }
+
+    // counts the spans and events dropped by tail sampling, on top of those dropped by the wrapped
+    // telemetry. buffered spans and events are pending
+    fn stats(&self) -> BackendStats {
+        let pending = {
+            // succeed or die. failure is unrecoverable (mutex poisoned)
+            #[cfg(not(feature = "use_parking_lot"))]
+            let buffer = self.buffer.lock().unwrap();
+            #[cfg(feature = "use_parking_lot")]
+            let buffer = self.buffer.lock();
+
+            buffer.traces.values().map(|trace| trace.items.len()).sum()
+        };
+        let own = BackendStats {
+            dropped_sampling: self.dropped_sampling.load(Ordering::Relaxed),
+            dropped_buffer_full: self.dropped_buffer_full.load(Ordering::Relaxed),
+            pending,
+            ..BackendStats::default()
+        };
+        own.combine(self.telemetry.stats())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test::{SpanId, TestTelemetry, TraceId};
+    use crate::{register_dist_tracing_root, RatioSampler, TelemetryLayer};
+    use std::sync::Arc;
+    use tracing::instrument;
+    use tracing_subscriber::layer::Layer;
+    use tracing_subscriber::registry;
+
+    #[instrument]
+    fn request(trace_id: TraceId, fail: bool, work: Duration) {
+        register_dist_tracing_root::<SpanId, TraceId>(trace_id, None).unwrap();
+        step(fail, work);
+    }
+
+    #[instrument]
+    fn step(fail: bool, work: Duration) {
+        tracing::info!("working");
+        std::thread::sleep(work);
+        if fail {
+            tracing::error!("failed");
+        }
+    }
+
+    // returns the ids of reported traces, one per reported span
+    fn reported_trace_ids(config: TailSamplingConfig, f: impl Fn()) -> (Vec<TraceId>, usize) {
+        let spans = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let cap =
+            TailSamplingTelemetry::new(TestTelemetry::new(spans.clone(), events.clone()), config);
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, f);
+
+        let trace_ids = spans.lock().unwrap().iter().map(|s| s.trace_id).collect();
+        let event_count = events.lock().unwrap().len();
+        (trace_ids, event_count)
+    }
+
+    #[test]
+    fn keeps_matching_traces() {
+        let config = TailSamplingConfig::new(vec![
+            TailSamplingRule::AnyError,
+            TailSamplingRule::MinDuration(Duration::from_millis(50)),
+        ]);
+        let (trace_ids, event_count) = reported_trace_ids(config, || {
+            request(1, false, Duration::from_millis(0));
+            request(2, true, Duration::from_millis(0));
+            request(3, false, Duration::from_millis(50));
+        });
+
+        assert_eq!(trace_ids, vec![2, 2, 3, 3]);
+        assert_eq!(event_count, 3);
+
+        let config = TailSamplingConfig::new(vec![TailSamplingRule::SpanName("step".into())]);
+        let (trace_ids, _) = reported_trace_ids(config, || {
+            request(1, false, Duration::from_millis(0));
+        });
+        assert_eq!(trace_ids, vec![1, 1]);
+
+        let config = TailSamplingConfig::new(vec![TailSamplingRule::Random(0.0)]);
+        let (trace_ids, _) = reported_trace_ids(config, || {
+            request(1, true, Duration::from_millis(0));
+        });
+        assert!(trace_ids.is_empty());
+    }
+
+    #[test]
+    fn random_rule_is_consistent_per_trace() {
+        let config = TailSamplingConfig::new(vec![TailSamplingRule::Random(0.5)]);
+        // each trace is completed twice, as if by two local roots evaluated separately
+        let (trace_ids, _) = reported_trace_ids(config, || {
+            for trace_id in (1..=20).chain(1..=20) {
+                request(trace_id, false, Duration::from_millis(0));
+            }
+        });
+
+        let kept: Vec<TraceId> = (1..=20)
+            .filter(|trace_id| {
+                trace_id_fraction::<TestTelemetry>(trace_id, TAIL_SAMPLING_SEED) < 0.5
+            })
+            .collect();
+        assert!(!kept.is_empty() && kept.len() < 20);
+        // both parts of each kept trace are reported, each with two spans
+        let expected: Vec<TraceId> = kept
+            .iter()
+            .chain(kept.iter())
+            .flat_map(|trace_id| vec![*trace_id, *trace_id])
+            .collect();
+        assert_eq!(trace_ids, expected);
+    }
+
+    #[test]
+    fn random_rule_is_independent_of_head_sampling() {
+        let spans = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let config = TailSamplingConfig::new(vec![TailSamplingRule::Random(0.5)]);
+        let cap =
+            TailSamplingTelemetry::new(TestTelemetry::new(spans.clone(), Arc::default()), config);
+        let layer =
+            TelemetryLayer::new("test_svc_name", cap, |x| x)
+                .with_sampler(RatioSampler::<TestTelemetry>::new(0.5));
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        let traces = 1_000;
+        tracing::subscriber::with_default(subscriber, || {
+            for trace_id in 1..=traces {
+                request(trace_id, false, Duration::from_millis(0));
+            }
+        });
+
+        // about half of the head-sampled half of traces are kept, each standing in for 4 traces
+        let spans = spans.lock().unwrap();
+        let kept = spans.len() / 2;
+        assert!(kept > 200 && kept < 300, "kept {}", kept);
+        assert!(spans.iter().all(|span| span.sample_rate == 4));
+    }
+
+    #[test]
+    fn evicts_incomplete_traces() {
+        #[instrument]
+        fn incomplete(trace_id: TraceId, fail: bool) {
+            register_dist_tracing_root::<SpanId, TraceId>(trace_id, None).unwrap();
+            step(fail, Duration::from_millis(0));
+            // the local root is still open when the next trace is buffered
+            request(trace_id + 1, false, Duration::from_millis(0));
+        }
+
+        let config = TailSamplingConfig {
+            max_traces: 1,
+            ..TailSamplingConfig::new(vec![TailSamplingRule::AnyError])
+        };
+        // trace 10 is evicted to make room for trace 11, and its failing step span reported.
+        // its local root is then evaluated on its own, and dropped as it did not fail
+        let (trace_ids, _) = reported_trace_ids(config.clone(), || incomplete(10, true));
+        assert_eq!(trace_ids, vec![10]);
+
+        let config = TailSamplingConfig {
+            incomplete_trace_policy: IncompleteTracePolicy::Drop,
+            ..config
+        };
+        let (trace_ids, _) = reported_trace_ids(config, || incomplete(10, true));
+        assert!(trace_ids.is_empty());
+    }
+
+    #[test]
+    fn shutdown_evaluates_buffered_traces() {
+        #[instrument]
+        fn interrupted(trace_id: TraceId) {
+            register_dist_tracing_root::<SpanId, TraceId>(trace_id, None).unwrap();
+            step(true, Duration::from_millis(0));
+            // the process exits before the local root completes
+            let timeout = Duration::from_secs(1);
+            assert_eq!(
+                crate::flush_dist_telemetry::<TestTelemetry>(timeout),
+                Err(FlushError::TelemetryLayerNotRegistered)
+            );
+            crate::flush_dist_telemetry::<TailSamplingTelemetry<TestTelemetry>>(timeout).unwrap();
+            crate::shutdown_dist_telemetry::<TailSamplingTelemetry<TestTelemetry>>(timeout)
+                .unwrap();
+        }
+
+        let config = TailSamplingConfig::new(vec![TailSamplingRule::AnyError]);
+        let (trace_ids, event_count) = reported_trace_ids(config, || interrupted(10));
+        // the local root completes after shutdown, so only its failing step is reported
+        assert_eq!(trace_ids, vec![10]);
+        assert_eq!(event_count, 2);
+    }
+}
@@ -1,5 +1,7 @@
+use crate::stats::BackendStats;
 use crate::trace::{Event, Span};
 use std::marker::PhantomData;
+use std::time::Duration;
 
 /// Represents the ability to publish events and spans to some arbitrary backend.
 pub trait Telemetry {
@@ -18,6 +20,39 @@ pub trait Telemetry {
 
     /// Report an `Event` to this Telemetry instance's backend.
     fn report_event(&self, event: Event<Self::Visitor, Self::SpanId, Self::TraceId>);
+
+    /// Deliver all spans and events reported so far to this Telemetry instance's backend, waiting
+    /// up to `timeout` for buffered data to be sent. Does nothing by default, which is appropriate
+    /// for implementations that don't buffer data.
+    fn flush(&self, _timeout: Duration) -> Result<(), FlushError> {
+        Ok(())
+    }
+
+    /// Flush buffered data and release the backend's resources, waiting up to `timeout`. Spans and
+    /// events reported afterwards are dropped. Equivalent to `flush` by default.
+    fn shutdown(&self, timeout: Duration) -> Result<(), FlushError> {
+        self.flush(timeout)
+    }
+
+    /// Counters describing what this Telemetry instance did with the spans and events reported to
+    /// it. All zero by default, which is appropriate for implementations that never drop data.
+    fn stats(&self) -> BackendStats {
+        BackendStats::default()
+    }
+}
+
+/// Errors that can occur while flushing or shutting down a `Telemetry` instance.
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+#[non_exhaustive]
+pub enum FlushError {
+    /// Expected a `TelemetryLayer` using the requested `Telemetry` type to be registered with the default subscriber.
+    TelemetryLayerNotRegistered,
+    /// Buffered data was not delivered before the timeout elapsed.
+    Timeout,
+    /// The backend was already shut down.
+    ShutDown,
+    /// The backend failed to deliver buffered data, with the provided error message.
+    Backend(String),
 }
 
 /// Visitor that records no information when visiting tracing fields.
@@ -59,6 +94,7 @@ where
 #[cfg(test)]
 pub(crate) mod test {
     use super::*;
+    use crate::propagation::IdCodec;
     use std::sync::Arc;
     use std::sync::Mutex;
 
@@ -81,6 +117,28 @@ pub(crate) mod test {
         }
     }
 
+    impl IdCodec for TestTelemetry {
+        type TraceId = TraceId;
+        type SpanId = SpanId;
+
+        // truncates, test trace ids only use the low 64 bits
+        fn encode_trace_id(trace_id: &TraceId) -> u128 {
+            u128::from(*trace_id)
+        }
+
+        fn decode_trace_id(trace_id: u128) -> TraceId {
+            trace_id as TraceId
+        }
+
+        fn encode_span_id(span_id: &SpanId) -> u64 {
+            span_id.into_u64()
+        }
+
+        fn decode_span_id(span_id: u64) -> SpanId {
+            tracing::Id::from_u64(span_id)
+        }
+    }
+
     impl Telemetry for TestTelemetry {
         type Visitor = BlackholeVisitor;
         type SpanId = SpanId;
@@ -1,8 +1,17 @@
-use crate::telemetry::Telemetry;
+use crate::baggage::Baggage;
+use crate::kind::{SpanKind, SpanKindVisitor};
+use crate::resource::Resource;
+use crate::sampling::{
+    AlwaysSample, ParentBasedSampler, RootFields, RootSpan, Sampler, SamplingDecision,
+};
+use crate::stats::{LayerCounters, Outcome, TelemetryStats};
+use crate::status::{SpanStatus, StatusConvention};
+use crate::telemetry::{FlushError, Telemetry};
 use crate::trace;
 use std::any::TypeId;
 use std::collections::HashMap;
-use std::time::SystemTime;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use tracing::span::{Attributes, Id, Record};
 use tracing::{Event, Subscriber};
 use tracing_subscriber::{layer::Context, registry, Layer};
@@ -16,7 +25,13 @@ use std::sync::RwLock;
 /// using the provided `Telemetry` capability.
 pub struct TelemetryLayer<Telemetry, SpanId, TraceId> {
     pub(crate) telemetry: Telemetry,
-    service_name: &'static str,
+    service_name: Arc<str>,
+    resource: Resource,
+    status_convention: StatusConvention,
+    report_baggage: bool,
+    // if present, used to generate trace ids for events that are not part of any trace
+    generate_orphan_trace_id: Option<Box<dyn 'static + Send + Sync + Fn() -> TraceId>>,
+    counters: LayerCounters,
     // used to construct span ids to avoid collisions
     pub(crate) trace_ctx_registry: TraceCtxRegistry<SpanId, TraceId>,
 }
@@ -25,12 +40,51 @@ pub struct TelemetryLayer<Telemetry, SpanId, TraceId> {
 pub(crate) struct TraceCtx<SpanId, TraceId> {
     pub(crate) parent_span: Option<SpanId>,
     pub(crate) trace_id: TraceId,
+    // opaque vendor-specific state (eg w3c `tracestate`) received from a remote parent
+    pub(crate) trace_state: Option<String>,
+    // spans and events are only reported for sampled traces
+    pub(crate) sampling: SamplingDecision,
+    pub(crate) baggage: Baggage,
+    // overrides the layer's service name for spans and events in this trace, if set
+    pub(crate) service_name: Option<Arc<str>>,
+}
+
+impl<SpanId, TraceId> TraceCtx<SpanId, TraceId>
+where
+    TraceId: Clone,
+{
+    // trace ctx inherited by spans below the local trace root, which have no remote parent
+    fn descendant(&self) -> Self {
+        TraceCtx {
+            trace_id: self.trace_id.clone(),
+            parent_span: None,
+            trace_state: self.trace_state.clone(),
+            sampling: self.sampling,
+            baggage: self.baggage.clone(),
+            service_name: self.service_name.clone(),
+        }
+    }
 }
 
 // resolvable via downcast_ref, to avoid propagating 'T' parameter of TelemetryLayer where not req'd
 pub(crate) struct TraceCtxRegistry<SpanId, TraceId> {
     registry: RwLock<HashMap<Id, TraceCtx<SpanId, TraceId>>>,
     promote_span_id: Box<dyn 'static + Send + Sync + Fn(Id) -> SpanId>,
+    // if present, used to generate trace ids for top-level spans with no registered trace ctx
+    generate_trace_id: Option<Box<dyn 'static + Send + Sync + Fn() -> TraceId>>,
+    sampler: Box<dyn 'static + Sampler<TraceId>>,
+}
+
+impl<SpanId, TraceId> TraceCtxRegistry<SpanId, TraceId> {
+    // number of trace ctxs currently registered for local trace roots
+    pub(crate) fn len(&self) -> usize {
+        #[cfg(not(feature = "use_parking_lot"))]
+        let trace_ctx_registry = self.registry.read().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let trace_ctx_registry = self.registry.read();
+
+        trace_ctx_registry.len()
+    }
 }
 
 impl<SpanId, TraceId> TraceCtxRegistry<SpanId, TraceId>
@@ -42,17 +96,22 @@ where
         (self.promote_span_id)(id)
     }
 
-    pub(crate) fn record_trace_ctx(
+    // decides whether to sample a new local trace root
+    pub(crate) fn sample(
         &self,
-        trace_id: TraceId,
-        remote_parent_span: Option<SpanId>,
-        id: Id,
-    ) {
-        let trace_ctx = TraceCtx {
-            trace_id,
-            parent_span: remote_parent_span,
-        };
+        trace_id: &TraceId,
+        parent_sampled: Option<bool>,
+        root: &RootSpan<'_>,
+    ) -> SamplingDecision {
+        self.sampler.sample(trace_id, parent_sampled, root)
+    }
+
+    // whether to record the fields of spans that may become new local trace roots
+    pub(crate) fn uses_fields(&self) -> bool {
+        self.sampler.uses_fields()
+    }
 
+    pub(crate) fn record_trace_ctx(&self, trace_ctx: TraceCtx<SpanId, TraceId>, id: Id) {
         #[cfg(not(feature = "use_parking_lot"))]
         let mut trace_ctx_registry = self.registry.write().expect("write lock!");
         #[cfg(feature = "use_parking_lot")]
@@ -61,6 +120,28 @@ where
         trace_ctx_registry.insert(id, trace_ctx); // TODO: handle overwrite?
     }
 
+    // removes and returns the trace ctx registered for the span with the provided id, if any
+    pub(crate) fn remove_trace_ctx(&self, id: &Id) -> Option<TraceCtx<SpanId, TraceId>> {
+        // most spans are not local trace roots, so avoid taking the write lock if possible
+        {
+            #[cfg(not(feature = "use_parking_lot"))]
+            let trace_ctx_registry = self.registry.read().unwrap();
+            #[cfg(feature = "use_parking_lot")]
+            let trace_ctx_registry = self.registry.read();
+
+            if !trace_ctx_registry.contains_key(id) {
+                return None;
+            }
+        }
+
+        #[cfg(not(feature = "use_parking_lot"))]
+        let mut trace_ctx_registry = self.registry.write().expect("write lock!");
+        #[cfg(feature = "use_parking_lot")]
+        let mut trace_ctx_registry = self.registry.write();
+
+        trace_ctx_registry.remove(id)
+    }
+
     pub(crate) fn eval_ctx<
         'a,
         X: 'a + registry::LookupSpan<'a>,
@@ -87,54 +168,80 @@ where
                         }
                         Some(local_trace_root) => {
                             write_guard.insert(LazyTraceCtx(local_trace_root.clone()));
-
-                            let res = if path.is_empty() {
-                                local_trace_root.clone()
-                            } else {
-                                TraceCtx {
-                                    trace_id: local_trace_root.trace_id.clone(),
-                                    parent_span: None,
-                                }
-                            };
-
-                            for span_ref in path.into_iter() {
-                                let mut write_guard = span_ref.extensions_mut();
-                                write_guard.insert::<LazyTraceCtx<SpanId, TraceId>>(LazyTraceCtx(
-                                    TraceCtx {
-                                        trace_id: local_trace_root.trace_id.clone(),
-                                        parent_span: None,
-                                    },
-                                ));
-                            }
-                            return Some(res);
+                            return Some(Self::inherit_ctx(local_trace_root, path));
                         }
                     }
                 }
                 Some(LazyTraceCtx(already_evaluated)) => {
-                    let res = if path.is_empty() {
-                        already_evaluated.clone()
-                    } else {
-                        TraceCtx {
-                            trace_id: already_evaluated.trace_id.clone(),
-                            parent_span: None,
-                        }
-                    };
-
-                    for span_ref in path.into_iter() {
-                        let mut write_guard = span_ref.extensions_mut();
-                        write_guard.insert::<LazyTraceCtx<SpanId, TraceId>>(LazyTraceCtx(
-                            TraceCtx {
-                                trace_id: already_evaluated.trace_id.clone(),
-                                parent_span: None,
-                            },
-                        ));
-                    }
-                    return Some(res);
+                    return Some(Self::inherit_ctx(already_evaluated, path));
                 }
             }
         }
 
-        None
+        // no enclosing span has a trace ctx, so the top-level span may become a new local trace root
+        let generate_trace_id = self.generate_trace_id.as_ref()?;
+        let root = path.pop()?;
+        let trace_id = generate_trace_id();
+        let sampling = {
+            let extensions = root.extensions();
+            let root_span = RootSpan::with_fields(root.metadata(), extensions.get::<RootFields>());
+            self.sample(&trace_id, None, &root_span)
+        };
+        let local_trace_root = TraceCtx {
+            sampling,
+            trace_id,
+            parent_span: None,
+            trace_state: None,
+            baggage: Baggage::default(),
+            service_name: None,
+        };
+        root.extensions_mut()
+            .insert(LazyTraceCtx(local_trace_root.clone()));
+
+        Some(Self::inherit_ctx(&local_trace_root, path))
+    }
+
+    // whether the trace of the provided span is sampled, if already known without walking its
+    // ancestors: ie if the span is a registered trace root, its trace ctx was already evaluated, or
+    // its parent's was known when it was created
+    fn known_sampling<'a, X: 'a + registry::LookupSpan<'a>>(
+        &self,
+        span_ref: &registry::SpanRef<'a, X>,
+    ) -> Option<bool> {
+        let extensions = span_ref.extensions();
+        if let Some(LazyTraceCtx(trace_ctx)) = extensions.get::<LazyTraceCtx<SpanId, TraceId>>() {
+            return Some(trace_ctx.sampling.is_sampled());
+        }
+
+        #[cfg(not(feature = "use_parking_lot"))]
+        let trace_ctx_registry = self.registry.read().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let trace_ctx_registry = self.registry.read();
+
+        match trace_ctx_registry.get(&span_ref.id()) {
+            Some(trace_ctx) => Some(trace_ctx.sampling.is_sampled()),
+            None => extensions.get::<KnownSampling>().map(|known| known.0),
+        }
+    }
+
+    // caches the trace ctx inherited from some ancestor (or the span itself, if path is empty)
+    // on each span in the path, returning the trace ctx of the first span in the path
+    fn inherit_ctx<'a, X: 'a + registry::LookupSpan<'a>>(
+        ancestor_ctx: &TraceCtx<SpanId, TraceId>,
+        path: Vec<registry::SpanRef<'a, X>>,
+    ) -> TraceCtx<SpanId, TraceId> {
+        let res = if path.is_empty() {
+            ancestor_ctx.clone()
+        } else {
+            ancestor_ctx.descendant()
+        };
+
+        for span_ref in path.into_iter() {
+            let mut write_guard = span_ref.extensions_mut();
+            write_guard
+                .insert::<LazyTraceCtx<SpanId, TraceId>>(LazyTraceCtx(ancestor_ctx.descendant()));
+        }
+        res
     }
 
     pub(crate) fn new<F: 'static + Send + Sync + Fn(Id) -> SpanId>(f: F) -> Self {
@@ -144,6 +251,8 @@ where
         TraceCtxRegistry {
             registry,
             promote_span_id,
+            generate_trace_id: None,
+            sampler: Box::new(ParentBasedSampler::new(AlwaysSample)),
         }
     }
 }
@@ -156,19 +265,211 @@ where
     /// Construct a new TelemetryLayer using the provided `Telemetry` capability.
     /// Uses the provided function, `F`, to promote `tracing::span::Id` instances to the
     /// `SpanId` type associated with the provided `Telemetry` instance.
+    ///
+    /// The service name may be overridden for individual traces via `set_service_name`.
     pub fn new<F: 'static + Send + Sync + Fn(Id) -> SpanId>(
-        service_name: &'static str,
+        service_name: impl Into<Arc<str>>,
         telemetry: T,
         promote_span_id: F,
     ) -> Self {
         let trace_ctx_registry = TraceCtxRegistry::new(promote_span_id);
 
         TelemetryLayer {
-            service_name,
+            service_name: service_name.into(),
+            resource: Resource::default(),
+            status_convention: StatusConvention::default(),
+            report_baggage: false,
+            generate_orphan_trace_id: None,
+            counters: LayerCounters::default(),
             telemetry,
             trace_ctx_registry,
         }
     }
+
+    /// Treat any top-level span that has no registered trace ctx as the local root of a new trace,
+    /// with a trace id generated by the provided function, `G`. By default, spans are only reported
+    /// if they or some ancestor were registered as a distributed tracing root.
+    ///
+    /// Trace ctx is evaluated lazily, when an event or span is first reported (or the current trace
+    /// ctx is first requested), so trace roots must be registered before that happens to take precedence
+    /// over generated trace ids.
+    pub fn with_auto_trace_roots<G: 'static + Send + Sync + Fn() -> TraceId>(
+        mut self,
+        generate_trace_id: G,
+    ) -> Self {
+        self.trace_ctx_registry.generate_trace_id = Some(Box::new(generate_trace_id));
+        self
+    }
+
+    /// Report events that are not part of any trace (ie that have no parent span, or whose ancestors
+    /// never registered a trace ctx), eg standalone `error!` events in a worker, as single-event traces
+    /// with trace ids generated by the provided function, `G`. By default, such events are dropped.
+    ///
+    /// Orphan events are reported with no parent span id, and the layer's `Sampler` decides whether
+    /// to sample each of them as if it was the root of its trace. Complements `with_auto_trace_roots`,
+    /// which does not apply to events outside of any span.
+    pub fn with_orphan_events<G: 'static + Send + Sync + Fn() -> TraceId>(
+        mut self,
+        generate_trace_id: G,
+    ) -> Self {
+        self.generate_orphan_trace_id = Some(Box::new(generate_trace_id));
+        self
+    }
+
+    /// Decide whether to sample new traces using the provided `Sampler`. By default, traces are
+    /// sampled unless their remote parent was propagated as not sampled.
+    ///
+    /// The fields of spans and events in unsampled traces are not recorded, so most of the cost of
+    /// instrumentation is avoided for them.
+    pub fn with_sampler<S: 'static + Sampler<TraceId>>(mut self, sampler: S) -> Self {
+        self.trace_ctx_registry.sampler = Box::new(sampler);
+        self
+    }
+
+    /// Attach the baggage associated with each span to reported spans, so that backends can publish
+    /// baggage entries as span fields.
+    pub fn with_baggage_fields(mut self) -> Self {
+        self.report_baggage = true;
+        self
+    }
+
+    /// Attach the provided `Resource`, describing this process, to all reported spans and events.
+    /// See `Resource::from_env` to detect common attributes from the environment.
+    pub fn with_resource(mut self, resource: Resource) -> Self {
+        self.resource = resource;
+        self
+    }
+
+    /// The `Telemetry` capability used by this layer, eg to read backend-specific counters. The layer
+    /// can be retrieved from a subscriber it was composed into via `Dispatch::downcast_ref`.
+    pub fn telemetry(&self) -> &T {
+        &self.telemetry
+    }
+
+    /// Derive the status of reported spans using the provided `StatusConvention` instead of the default.
+    pub fn with_status_convention(mut self, status_convention: StatusConvention) -> Self {
+        self.status_convention = status_convention;
+        self
+    }
+}
+
+impl<T, SpanId, TraceId> TelemetryLayer<T, SpanId, TraceId>
+where
+    T: Telemetry,
+{
+    /// Deliver all spans and events reported so far to the backend, waiting up to `timeout`. Spans
+    /// that haven't completed yet are not reported, so this is typically called once all work is done.
+    pub fn flush(&self, timeout: Duration) -> Result<(), FlushError> {
+        self.telemetry.flush(timeout)
+    }
+
+    /// Flush buffered data and release the backend's resources, waiting up to `timeout`. Spans and
+    /// events reported afterwards are dropped, so this is typically called just before the process exits.
+    pub fn shutdown(&self, timeout: Duration) -> Result<(), FlushError> {
+        self.telemetry.shutdown(timeout)
+    }
+
+    /// Counters describing the spans and events observed by this layer and what happened to them,
+    /// including those reported by its `Telemetry` instance.
+    pub fn stats(&self) -> TelemetryStats {
+        self.counters
+            .stats(self.trace_ctx_registry.len(), self.telemetry.stats())
+    }
+}
+
+impl<T, SpanId, TraceId> TelemetryLayer<T, SpanId, TraceId>
+where
+    SpanId: 'static + Clone + Send + Sync,
+    TraceId: 'static + Clone + Send + Sync,
+    T: Telemetry<SpanId = SpanId, TraceId = TraceId>,
+{
+    // reports an event that is not part of any trace as a single-event trace, if configured to
+    fn report_orphan_event(&self, event: &Event<'_>, initialized_at: SystemTime) {
+        let generate_trace_id = match &self.generate_orphan_trace_id {
+            Some(generate_trace_id) => generate_trace_id,
+            None => {
+                self.counters.record_event(Outcome::NoTraceCtx);
+                return;
+            }
+        };
+
+        let trace_id = generate_trace_id();
+        let fields = if self.trace_ctx_registry.uses_fields() {
+            let mut fields = RootFields::default();
+            event.record(&mut fields);
+            Some(fields)
+        } else {
+            None
+        };
+        let sampling = self.trace_ctx_registry.sample(
+            &trace_id,
+            None,
+            &RootSpan::with_fields(event.metadata(), fields.as_ref()),
+        );
+        if !sampling.is_sampled() {
+            self.counters.record_event(Outcome::Unsampled);
+            return;
+        }
+
+        let mut visitor = self.telemetry.mk_visitor();
+        event.record(&mut visitor);
+
+        let event = trace::Event {
+            trace_id,
+            parent_id: None,
+            initialized_at,
+            sample_rate: sampling.sample_rate(),
+            meta: event.metadata(),
+            service_name: self.service_name.clone(),
+            resource: self.resource.clone(),
+            values: visitor,
+        };
+
+        self.telemetry.report_event(event);
+        self.counters.record_event(Outcome::Reported);
+    }
+}
+
+/// Flush the `Telemetry` instance of type `T` used by the `TelemetryLayer` registered with the
+/// default subscriber, waiting up to `timeout`. See `TelemetryLayer::flush`.
+pub fn flush_dist_telemetry<T>(timeout: Duration) -> Result<(), FlushError>
+where
+    T: 'static + Telemetry,
+{
+    with_telemetry_layer::<T, _, _>(|layer| layer.flush(timeout))
+        .unwrap_or(Err(FlushError::TelemetryLayerNotRegistered))
+}
+
+/// Shut down the `Telemetry` instance of type `T` used by the `TelemetryLayer` registered with the
+/// default subscriber, waiting up to `timeout`. See `TelemetryLayer::shutdown`.
+pub fn shutdown_dist_telemetry<T>(timeout: Duration) -> Result<(), FlushError>
+where
+    T: 'static + Telemetry,
+{
+    with_telemetry_layer::<T, _, _>(|layer| layer.shutdown(timeout))
+        .unwrap_or(Err(FlushError::TelemetryLayerNotRegistered))
+}
+
+/// Retrieve the counters of the `TelemetryLayer` using a `Telemetry` instance of type `T` registered
+/// with the default subscriber, if any. See `TelemetryLayer::stats`.
+pub fn dist_telemetry_stats<T>() -> Option<TelemetryStats>
+where
+    T: 'static + Telemetry,
+{
+    with_telemetry_layer::<T, _, _>(TelemetryLayer::stats)
+}
+
+// applies `f` to the `TelemetryLayer` registered with the default subscriber, if any
+fn with_telemetry_layer<T, R, F>(f: F) -> Option<R>
+where
+    T: 'static + Telemetry,
+    F: Fn(&TelemetryLayer<T, T::SpanId, T::TraceId>) -> R,
+{
+    tracing::dispatcher::get_default(|dispatch| {
+        dispatch
+            .downcast_ref::<TelemetryLayer<T, T::SpanId, T::TraceId>>()
+            .map(&f)
+    })
 }
 
 impl<S, TraceId, SpanId, V, T> Layer<S> for TelemetryLayer<T, SpanId, TraceId>
@@ -181,21 +482,133 @@ where
 {
     fn new_span(&self, attrs: &Attributes, id: &Id, ctx: Context<S>) {
         let span = ctx.span(id).expect("span data not found during new_span");
+
+        // spans in unsampled traces are never reported, so don't bother recording their fields.
+        // only the parent is consulted, as the trace ctx is resolved lazily (once the span's events
+        // or the span itself are reported) so that trace roots may still be registered in the meantime
+        let sampled = span
+            .parent()
+            .and_then(|parent| self.trace_ctx_registry.known_sampling(&parent));
+
         let mut extensions_mut = span.extensions_mut();
         extensions_mut.insert(SpanInitAt::new());
+        extensions_mut.insert(SpanTimings::new());
+        extensions_mut.insert(SpanLinks::<SpanId, TraceId>(Vec::new()));
+
+        match sampled {
+            Some(false) => {
+                extensions_mut.insert(KnownSampling(false));
+                return;
+            }
+            Some(true) => {
+                extensions_mut.insert(KnownSampling(true));
+            }
+            None => {
+                if self.trace_ctx_registry.uses_fields() {
+                    let mut fields = RootFields::default();
+                    attrs.record(&mut fields);
+                    extensions_mut.insert(fields);
+                }
+            }
+        }
 
         let mut visitor: V = self.telemetry.mk_visitor();
         attrs.record(&mut visitor);
         extensions_mut.insert::<V>(visitor);
+
+        let mut kind_visitor = SpanKindVisitor::default();
+        attrs.record(&mut kind_visitor);
+        if let Some(kind) = kind_visitor.0 {
+            extensions_mut.insert(kind);
+        }
+
+        if let Some(error) = self
+            .status_convention
+            .span_error(|visitor| attrs.record(visitor))
+        {
+            extensions_mut.insert(SpanError(error));
+        }
     }
 
     fn on_record(&self, id: &Id, values: &Record, ctx: Context<S>) {
         let span = ctx.span(id).expect("span data not found during on_record");
         let mut extensions_mut = span.extensions_mut();
-        let visitor: &mut V = extensions_mut
-            .get_mut()
-            .expect("fields extension not found during on_record");
+        if let Some(fields) = extensions_mut.get_mut::<RootFields>() {
+            values.record(fields);
+        }
+        // fields of spans in unsampled traces are not recorded
+        let visitor: &mut V = match extensions_mut.get_mut() {
+            Some(visitor) => visitor,
+            None => return,
+        };
         values.record(visitor);
+
+        let mut kind_visitor = SpanKindVisitor::default();
+        values.record(&mut kind_visitor);
+        if let Some(kind) = kind_visitor.0 {
+            extensions_mut.replace(kind);
+        }
+
+        // only the first error observed in a span is kept
+        if extensions_mut.get_mut::<SpanError>().is_none() {
+            if let Some(error) = self
+                .status_convention
+                .span_error(|visitor| values.record(visitor))
+            {
+                extensions_mut.insert(SpanError(error));
+            }
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<S>) {
+        let span = ctx.span(id).expect("span data not found during on_enter");
+        let mut extensions_mut = span.extensions_mut();
+        let timings: &mut SpanTimings = extensions_mut
+            .get_mut()
+            .expect("timings extension not found during on_enter");
+        timings.enter();
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<S>) {
+        let span = ctx.span(id).expect("span data not found during on_exit");
+        let mut extensions_mut = span.extensions_mut();
+        let timings: &mut SpanTimings = extensions_mut
+            .get_mut()
+            .expect("timings extension not found during on_exit");
+        timings.exit();
+    }
+
+    fn on_follows_from(&self, id: &Id, follows: &Id, ctx: Context<S>) {
+        // the followed span may already be closed, in which case its trace ctx can't be resolved
+        if ctx.span(follows).is_none() {
+            return;
+        }
+
+        let iter = itertools::unfold(Some(follows.clone()), |st| match st {
+            Some(target_id) => {
+                let res = ctx
+                    .span(target_id)
+                    .expect("span data not found during eval_ctx");
+                *st = res.parent().map(|x| x.id());
+                Some(res)
+            }
+            None => None,
+        });
+
+        // links are resolved via the followed span's own trace ctx, which may differ from this span's
+        if let Some(follows_trace_ctx) = self.trace_ctx_registry.eval_ctx(iter) {
+            let span = ctx
+                .span(id)
+                .expect("span data not found during on_follows_from");
+            let mut extensions_mut = span.extensions_mut();
+            let SpanLinks(links): &mut SpanLinks<SpanId, TraceId> = extensions_mut
+                .get_mut()
+                .expect("links extension not found during on_follows_from");
+            links.push((
+                follows_trace_ctx.trace_id,
+                self.trace_ctx_registry.promote_span_id(follows.clone()),
+            ));
+        }
     }
 
     fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
@@ -205,22 +618,17 @@ where
         } else if event.is_root() {
             // don't bother checking thread local if span is explicitly root according to this fn
             None
-        } else if let Some(parent_id) = ctx.current_span().id() {
-            // implicit parent from threadlocal ctx
-            Some(parent_id.clone())
         } else {
-            // no parent span, thus this is a root span
-            None
+            // implicit parent from threadlocal ctx, or no parent span (thus this is a root span)
+            ctx.current_span().id().cloned()
         };
 
         match parent_id {
-            None => {} // not part of a trace, don't bother recording via honeycomb
+            // not part of a trace
+            None => self.report_orphan_event(event, SystemTime::now()),
             Some(parent_id) => {
                 let initialized_at = SystemTime::now();
 
-                let mut visitor = self.telemetry.mk_visitor();
-                event.record(&mut visitor);
-
                 // TODO: dedup
                 let iter = itertools::unfold(Some(parent_id.clone()), |st| match st {
                     Some(target_id) => {
@@ -232,19 +640,51 @@ where
                     }
                     None => None,
                 });
+                let parent_trace_ctx = self.trace_ctx_registry.eval_ctx(iter);
+
+                // events in unsampled traces are never reported and their parent spans' statuses
+                // are irrelevant, so don't bother recording their fields
+                if parent_trace_ctx
+                    .as_ref()
+                    .map_or(false, |trace_ctx| !trace_ctx.sampling.is_sampled())
+                {
+                    self.counters.record_event(Outcome::Unsampled);
+                    return;
+                }
+
+                if let Some(error) = self.status_convention.event_error(event) {
+                    let parent = ctx
+                        .span(&parent_id)
+                        .expect("span data not found during on_event");
+                    let mut extensions_mut = parent.extensions_mut();
+                    // only the first error observed in a span is kept
+                    if extensions_mut.get_mut::<SpanError>().is_none() {
+                        extensions_mut.insert(SpanError(error));
+                    }
+                }
+
+                // events that are not part of a trace are only reported if configured to
+                if let Some(parent_trace_ctx) = parent_trace_ctx {
+                    let mut visitor = self.telemetry.mk_visitor();
+                    event.record(&mut visitor);
 
-                // only report event if it's part of a trace
-                if let Some(parent_trace_ctx) = self.trace_ctx_registry.eval_ctx(iter) {
                     let event = trace::Event {
                         trace_id: parent_trace_ctx.trace_id,
                         parent_id: Some(self.trace_ctx_registry.promote_span_id(parent_id)),
                         initialized_at,
+                        sample_rate: parent_trace_ctx.sampling.sample_rate(),
                         meta: event.metadata(),
-                        service_name: &self.service_name,
+                        service_name: parent_trace_ctx
+                            .service_name
+                            .unwrap_or_else(|| self.service_name.clone()),
+                        resource: self.resource.clone(),
                         values: visitor,
                     };
 
                     self.telemetry.report_event(event);
+                    self.counters.record_event(Outcome::Reported);
+                } else {
+                    self.report_orphan_event(event, initialized_at);
                 }
             }
         }
@@ -265,15 +705,45 @@ where
             None => None,
         });
 
-        // if span's enclosing ctx has a trace id, eval & use to report telemetry
-        if let Some(trace_ctx) = self.trace_ctx_registry.eval_ctx(iter) {
+        let trace_ctx = self.trace_ctx_registry.eval_ctx(iter);
+
+        // the registry may reuse the ids of closed spans, so the trace ctx registered for
+        // this span (if it's a local trace root) must not outlive it
+        let registered = self.trace_ctx_registry.remove_trace_ctx(&id).is_some();
+        // spans with no parent are either registered or automatic trace roots
+        let local_root = registered || span.parent().is_none();
+
+        match &trace_ctx {
+            Some(trace_ctx) if !trace_ctx.sampling.is_sampled() => {
+                self.counters.record_span(Outcome::Unsampled)
+            }
+            None => self.counters.record_span(Outcome::NoTraceCtx),
+            Some(_) => {}
+        }
+
+        // if span's enclosing ctx has a trace id and is sampled, use it to report telemetry
+        if let Some(trace_ctx) = trace_ctx.filter(|trace_ctx| trace_ctx.sampling.is_sampled()) {
             let mut extensions_mut = span.extensions_mut();
+            // fields are not recorded for spans created in a trace that was unsampled at the time,
+            // which may later have been registered as the root of some other trace
             let visitor: V = extensions_mut
                 .remove()
-                .expect("should be present on all spans");
+                .unwrap_or_else(|| self.telemetry.mk_visitor());
             let SpanInitAt(initialized_at) = extensions_mut
                 .remove()
                 .expect("should be present on all spans");
+            let SpanLinks(follows_from) = extensions_mut
+                .remove()
+                .expect("should be present on all spans");
+            let mut timings: SpanTimings = extensions_mut
+                .remove()
+                .expect("should be present on all spans");
+            timings.close();
+            let kind: SpanKind = extensions_mut.remove().unwrap_or_default();
+            let status = match extensions_mut.remove() {
+                Some(SpanError(error)) => SpanStatus::Error(error),
+                None => SpanStatus::Ok,
+            };
 
             let completed_at = SystemTime::now();
 
@@ -288,20 +758,42 @@ where
                 id: self.trace_ctx_registry.promote_span_id(id),
                 meta: span.metadata(),
                 parent_id,
+                local_root,
+                follows_from,
                 initialized_at,
                 trace_id: trace_ctx.trace_id,
                 completed_at,
-                service_name: self.service_name,
+                busy: timings.busy,
+                idle: timings.idle,
+                enter_count: timings.enter_count,
+                status,
+                kind,
+                sample_rate: trace_ctx.sampling.sample_rate(),
+                baggage: if self.report_baggage {
+                    trace_ctx.baggage
+                } else {
+                    Baggage::default()
+                },
+                service_name: trace_ctx
+                    .service_name
+                    .unwrap_or_else(|| self.service_name.clone()),
+                resource: self.resource.clone(),
                 values: visitor,
             };
 
             self.telemetry.report_span(span);
+            self.counters.record_span(Outcome::Reported);
         };
     }
 
-    // FIXME: do I need to do something here? I think no (better to require explicit re-marking as root after copy).
-    // called when span copied, needed iff span has trace id/etc already? nah,
-    // fn on_id_change(&self, _old: &Id, _new: &Id, _ctx: Context<'_, S>) {}
+    // called when a span is cloned and the subscriber returns a different id for the clone,
+    // in which case any trace ctx registered for the span moves to the new id
+    fn on_id_change(&self, old: &Id, new: &Id, _ctx: Context<'_, S>) {
+        if let Some(trace_ctx) = self.trace_ctx_registry.remove_trace_ctx(old) {
+            self.trace_ctx_registry
+                .record_trace_ctx(trace_ctx, new.clone());
+        }
+    }
 
     unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
         // This `downcast_raw` impl allows downcasting this layer to any of
@@ -317,11 +809,70 @@ where
     }
 }
 
-// TODO: delete?
-struct LazyTraceCtx<SpanId, TraceId>(TraceCtx<SpanId, TraceId>);
+// trace ctx evaluated for some span, cached to avoid re-evaluating it for each of the span's descendants
+pub(crate) struct LazyTraceCtx<SpanId, TraceId>(pub(crate) TraceCtx<SpanId, TraceId>);
+
+// whether the trace of a span was known to be sampled when it was created, only used to skip
+// recording the fields of spans in unsampled traces
+struct KnownSampling(bool);
 
 struct SpanInitAt(SystemTime);
 
+// first error observed in a span, as derived via the layer's status convention
+struct SpanError(String);
+
+// time spent inside and outside of a span, accumulated as it's entered and exited
+struct SpanTimings {
+    busy: Duration,
+    idle: Duration,
+    enter_count: u64,
+    // spans may be entered concurrently on multiple threads, time is only idle if no thread is inside
+    entered: u64,
+    last: Instant,
+}
+
+impl SpanTimings {
+    fn new() -> Self {
+        SpanTimings {
+            busy: Duration::from_secs(0),
+            idle: Duration::from_secs(0),
+            enter_count: 0,
+            entered: 0,
+            last: Instant::now(),
+        }
+    }
+
+    // accumulate time elapsed since the last transition as busy or idle time
+    fn transition(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(self.last);
+        if self.entered > 0 {
+            self.busy += elapsed;
+        } else {
+            self.idle += elapsed;
+        }
+        self.last = now;
+    }
+
+    fn enter(&mut self) {
+        self.transition();
+        self.entered += 1;
+        self.enter_count += 1;
+    }
+
+    fn exit(&mut self) {
+        self.transition();
+        self.entered = self.entered.saturating_sub(1);
+    }
+
+    fn close(&mut self) {
+        self.transition();
+    }
+}
+
+// spans followed by a span, paired with the ids of the traces they belong to
+struct SpanLinks<SpanId, TraceId>(Vec<(TraceId, SpanId)>);
+
 impl SpanInitAt {
     fn new() -> Self {
         let initialized_at = SystemTime::now();
@@ -334,6 +885,7 @@ impl SpanInitAt {
 mod tests {
     use super::*;
     use crate::telemetry::test::{SpanId, TestTelemetry, TraceId};
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
     use std::sync::Arc;
     use std::sync::Mutex;
     use std::time::Duration;
@@ -425,6 +977,575 @@ mod tests {
         });
     }
 
+    // number of trace ctxs registered with the current subscriber's telemetry layer
+    fn registered_trace_ctx_count() -> usize {
+        tracing::dispatcher::get_default(|dispatch| {
+            let trace_ctx_registry = dispatch
+                .downcast_ref::<TraceCtxRegistry<SpanId, TraceId>>()
+                .unwrap();
+
+            #[cfg(not(feature = "use_parking_lot"))]
+            let trace_ctx_registry = trace_ctx_registry.registry.read().unwrap();
+            #[cfg(feature = "use_parking_lot")]
+            let trace_ctx_registry = trace_ctx_registry.registry.read();
+
+            trace_ctx_registry.len()
+        })
+    }
+
+    // the registry reuses the ids of closed spans, which must not inherit the trace they
+    // belonged to
+    #[test]
+    fn test_closed_root_is_unregistered() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let cap = TestTelemetry::new(spans.clone(), Arc::default());
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let root_id = {
+                let root = tracing::info_span!("root");
+                let _guard = root.enter();
+                trace::register_dist_tracing_root(
+                    explicit_trace_id(),
+                    Some(explicit_parent_span_id()),
+                )
+                .unwrap();
+                assert_eq!(registered_trace_ctx_count(), 1);
+                root.id().unwrap()
+            };
+            assert_eq!(registered_trace_ctx_count(), 0);
+
+            // the registry hands out the freed slot again with a bumped generation, which wraps
+            // around to the closed root's id after a few thousand spans
+            let reused = (0..100_000)
+                .map(|_| tracing::info_span!("reused"))
+                .find(|span| span.id().as_ref() == Some(&root_id))
+                .expect("registry never reused the closed root's id");
+            assert_eq!(reused.id(), Some(root_id));
+
+            // a span with the closed root's id must not inherit its trace ctx
+            let _guard = reused.enter();
+            assert_eq!(
+                trace::current_dist_trace_ctx::<SpanId, TraceId>(),
+                Err(trace::TraceCtxError::NoParentNodeHasTraceCtx)
+            );
+
+            // and can be registered as the root of a new trace
+            trace::register_dist_tracing_root::<SpanId, TraceId>(246, None).unwrap();
+            assert_eq!(
+                trace::current_dist_trace_ctx::<SpanId, TraceId>().map(|(trace_id, _)| trace_id),
+                Ok(246)
+            );
+        });
+
+        let trace_ids: Vec<TraceId> = spans.lock().unwrap().iter().map(|s| s.trace_id).collect();
+        assert_eq!(trace_ids, vec![explicit_trace_id(), 246]);
+    }
+
+    // delegates to a `Registry`, except that cloning the span `from` yields the span `to`, as
+    // subscribers that change span ids on clone do
+    struct IdChangingRegistry {
+        registry: registry::Registry,
+        remap: Mutex<Option<(Id, Id)>>,
+    }
+
+    impl Subscriber for IdChangingRegistry {
+        fn register_callsite(
+            &self,
+            meta: &'static tracing::Metadata<'static>,
+        ) -> tracing_core::Interest {
+            self.registry.register_callsite(meta)
+        }
+
+        fn enabled(&self, meta: &tracing::Metadata<'_>) -> bool {
+            self.registry.enabled(meta)
+        }
+
+        fn new_span(&self, attrs: &tracing::span::Attributes<'_>) -> Id {
+            self.registry.new_span(attrs)
+        }
+
+        fn record(&self, id: &Id, values: &tracing::span::Record<'_>) {
+            self.registry.record(id, values)
+        }
+
+        fn record_follows_from(&self, id: &Id, follows: &Id) {
+            self.registry.record_follows_from(id, follows)
+        }
+
+        fn event(&self, event: &Event<'_>) {
+            self.registry.event(event)
+        }
+
+        fn enter(&self, id: &Id) {
+            self.registry.enter(id)
+        }
+
+        fn exit(&self, id: &Id) {
+            self.registry.exit(id)
+        }
+
+        fn clone_span(&self, id: &Id) -> Id {
+            match self.remap.lock().unwrap().take() {
+                Some((from, to)) if &from == id => self.registry.clone_span(&to),
+                _ => self.registry.clone_span(id),
+            }
+        }
+
+        fn try_close(&self, id: Id) -> bool {
+            self.registry.try_close(id)
+        }
+
+        fn current_span(&self) -> tracing_core::span::Current {
+            self.registry.current_span()
+        }
+
+        unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+            if id == TypeId::of::<Self>() {
+                Some(self as *const Self as *const ())
+            } else {
+                self.registry.downcast_raw(id)
+            }
+        }
+    }
+
+    impl<'a> registry::LookupSpan<'a> for IdChangingRegistry {
+        type Data = <registry::Registry as registry::LookupSpan<'a>>::Data;
+
+        fn span_data(&'a self, id: &Id) -> Option<Self::Data> {
+            self.registry.span_data(id)
+        }
+    }
+
+    #[test]
+    fn test_id_change_moves_trace_ctx() {
+        let cap = TestTelemetry::new(Arc::default(), Arc::default());
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x);
+        let subscriber = layer.with_subscriber(IdChangingRegistry {
+            registry: registry::Registry::default(),
+            remap: Mutex::new(None),
+        });
+
+        tracing::subscriber::with_default(subscriber, || {
+            let old = tracing::info_span!("old");
+            let new = tracing::info_span!("new");
+            old.in_scope(|| {
+                trace::register_dist_tracing_root::<SpanId, TraceId>(explicit_trace_id(), None)
+                    .unwrap();
+            });
+
+            tracing::dispatcher::get_default(|dispatch| {
+                let subscriber = dispatch
+                    .downcast_ref::<IdChangingRegistry>()
+                    .expect("subscriber is an IdChangingRegistry");
+                *subscriber.remap.lock().unwrap() = Some((old.id().unwrap(), new.id().unwrap()));
+                // the layer is notified of the id change
+                dispatch.clone_span(&old.id().unwrap());
+            });
+
+            assert_eq!(registered_trace_ctx_count(), 1);
+            // the trace ctx moved to the new id, so the old span is no longer a trace root
+            new.in_scope(|| {
+                assert_eq!(
+                    trace::current_dist_trace_ctx::<SpanId, TraceId>()
+                        .map(|(trace_id, span_id)| (trace_id, span_id == new.id().unwrap())),
+                    Ok((explicit_trace_id(), true))
+                );
+            });
+            old.in_scope(|| {
+                assert_eq!(
+                    trace::current_dist_trace_ctx::<SpanId, TraceId>(),
+                    Err(trace::TraceCtxError::NoParentNodeHasTraceCtx)
+                );
+            });
+        });
+    }
+
+    #[test]
+    fn test_follows_from_across_traces() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let cap = TestTelemetry::new(spans.clone(), Arc::default());
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let request = tracing::info_span!("request");
+            request.in_scope(|| {
+                trace::register_dist_tracing_root::<SpanId, TraceId>(explicit_trace_id(), None)
+                    .unwrap();
+            });
+            let request_child = tracing::info_span!(parent: &request, "request_child");
+            let untraced = tracing::info_span!(parent: None, "untraced");
+
+            let batch = tracing::info_span!(parent: None, "batch");
+            batch.in_scope(|| {
+                trace::register_dist_tracing_root::<SpanId, TraceId>(246, None).unwrap();
+            });
+            batch.follows_from(&request_child);
+            batch.follows_from(&untraced);
+        });
+
+        let spans = spans.lock().unwrap();
+        let batch = spans.iter().find(|s| s.meta.name() == "batch").unwrap();
+        let request_child = spans
+            .iter()
+            .find(|s| s.meta.name() == "request_child")
+            .unwrap();
+
+        // spans outside of any trace can't be linked to
+        assert_eq!(batch.trace_id, 246);
+        assert_eq!(
+            batch.follows_from,
+            vec![(explicit_trace_id(), request_child.id.clone())]
+        );
+        assert!(request_child.follows_from.is_empty());
+    }
+
+    #[test]
+    fn test_auto_trace_roots() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let cap = TestTelemetry::new(spans.clone(), events.clone());
+        let generated_trace_id = Arc::new(Mutex::new(0));
+        let generator = generated_trace_id.clone();
+        let layer =
+            TelemetryLayer::new("test_svc_name", cap, |x| x).with_auto_trace_roots(move || {
+                let mut trace_id = generator.lock().unwrap();
+                *trace_id += 1;
+                *trace_id
+            });
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        #[instrument]
+        fn job() {
+            tracing::info!("event");
+            step();
+        }
+
+        #[instrument]
+        fn step() {
+            tracing::info!("event");
+        }
+
+        #[instrument]
+        fn request() {
+            trace::register_dist_tracing_root(explicit_trace_id(), Some(explicit_parent_span_id()))
+                .unwrap();
+            step();
+        }
+
+        tracing::subscriber::with_default(subscriber, || {
+            job();
+            job();
+            request();
+        });
+
+        let spans = spans.lock().unwrap();
+        let events = events.lock().unwrap();
+        let trace_ids: Vec<TraceId> = spans.iter().map(|span| span.trace_id).collect();
+        let root_parent_ids: Vec<Option<SpanId>> = spans
+            .iter()
+            .filter(|span| span.meta.name() != "step")
+            .map(|span| span.parent_id.clone())
+            .collect();
+
+        // each top-level span starts a new trace, unless explicitly registered as a trace root
+        assert_eq!(
+            trace_ids,
+            vec![1, 1, 2, 2, explicit_trace_id(), explicit_trace_id()]
+        );
+        assert_eq!(
+            root_parent_ids,
+            vec![None, None, Some(explicit_parent_span_id())]
+        );
+        assert_eq!(events.len(), 5);
+        assert_eq!(*generated_trace_id.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_busy_and_idle_time() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let cap = TestTelemetry::new(spans.clone(), Arc::default());
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        #[instrument]
+        async fn parked() {
+            trace::register_dist_tracing_root(explicit_trace_id(), Some(explicit_parent_span_id()))
+                .unwrap();
+            tokio::time::delay_for(Duration::from_millis(50)).await;
+        }
+
+        #[instrument]
+        fn working() {
+            trace::register_dist_tracing_root(explicit_trace_id(), Some(explicit_parent_span_id()))
+                .unwrap();
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        tracing::subscriber::with_default(subscriber, || {
+            let mut rt = Runtime::new().unwrap();
+            rt.block_on(parked());
+            working();
+        });
+
+        let spans = spans.lock().unwrap();
+        let (parked, working) = (&spans[0], &spans[1]);
+
+        assert!(parked.enter_count >= 2);
+        assert!(parked.idle >= Duration::from_millis(50));
+        assert!(parked.busy < parked.idle);
+
+        assert_eq!(working.enter_count, 1);
+        assert!(working.busy >= Duration::from_millis(50));
+        assert!(working.idle < working.busy);
+    }
+
+    // counts the visitors constructed to record fields
+    struct CountingTelemetry(TestTelemetry, Arc<AtomicUsize>);
+
+    impl crate::Telemetry for CountingTelemetry {
+        type Visitor = crate::telemetry::BlackholeVisitor;
+        type TraceId = TraceId;
+        type SpanId = SpanId;
+
+        fn mk_visitor(&self) -> Self::Visitor {
+            self.1.fetch_add(1, Ordering::Relaxed);
+            self.0.mk_visitor()
+        }
+
+        fn report_span(&self, span: trace::Span<Self::Visitor, SpanId, TraceId>) {
+            self.0.report_span(span)
+        }
+
+        fn report_event(&self, event: trace::Event<Self::Visitor, SpanId, TraceId>) {
+            self.0.report_event(event)
+        }
+    }
+
+    #[test]
+    fn test_unsampled_fields_not_recorded() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let visitors = Arc::new(AtomicUsize::new(0));
+        let cap = CountingTelemetry(
+            TestTelemetry::new(spans.clone(), events.clone()),
+            visitors.clone(),
+        );
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        #[instrument]
+        fn root(sampled: bool) {
+            let traceparent = crate::TraceParent {
+                trace_id: u128::from(explicit_trace_id()),
+                parent_id: 246,
+                trace_flags: if sampled { 1 } else { 0 },
+            };
+            crate::register_w3c_tracing_root::<TestTelemetry>(&traceparent, None).unwrap();
+            child();
+        }
+
+        #[instrument(fields(recorded))]
+        fn child() {
+            tracing::Span::current().record("recorded", "value");
+            tracing::error!("event");
+        }
+
+        tracing::subscriber::with_default(subscriber, || {
+            // the root span is recorded before it's registered, its child and event are not
+            root(false);
+            assert_eq!(visitors.load(Ordering::Relaxed), 1);
+            root(true);
+            assert_eq!(visitors.load(Ordering::Relaxed), 4);
+        });
+
+        let spans = spans.lock().unwrap();
+        let child = &spans[0];
+        assert_eq!(spans.len(), 2);
+        assert_eq!(events.lock().unwrap().len(), 1);
+        assert_eq!(child.status, SpanStatus::Error("event".to_string()));
+    }
+
+    #[test]
+    fn test_root_registered_after_child_created() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let visitors = Arc::new(AtomicUsize::new(0));
+        let cap = CountingTelemetry(
+            TestTelemetry::new(spans.clone(), events.clone()),
+            visitors.clone(),
+        );
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let root = tracing::info_span!("root");
+            let child = root.in_scope(|| tracing::info_span!("child"));
+            let grandchild = child.in_scope(|| tracing::info_span!("grandchild"));
+            // the trace ctx of spans created so far isn't known yet, so all their fields are recorded
+            assert_eq!(visitors.load(Ordering::Relaxed), 3);
+
+            root.in_scope(|| {
+                trace::register_dist_tracing_root::<SpanId, TraceId>(explicit_trace_id(), None)
+                    .unwrap()
+            });
+            grandchild.in_scope(|| tracing::info!("event"));
+        });
+
+        // spans created before the root was registered belong to its trace
+        let spans = spans.lock().unwrap();
+        let names: Vec<&str> = spans.iter().map(|span| span.meta.name()).collect();
+        assert_eq!(names, vec!["grandchild", "child", "root"]);
+        assert!(spans
+            .iter()
+            .all(|span| span.trace_id == explicit_trace_id()));
+        assert_eq!(spans[0].parent_id.as_ref(), Some(&spans[1].id));
+        assert_eq!(spans[1].parent_id.as_ref(), Some(&spans[2].id));
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].trace_id, explicit_trace_id());
+    }
+
+    #[test]
+    fn test_stats() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let cap = TestTelemetry::new(spans, events);
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        #[instrument]
+        fn root(sampled: bool) {
+            let traceparent = crate::TraceParent {
+                trace_id: u128::from(explicit_trace_id()),
+                parent_id: 246,
+                trace_flags: if sampled { 1 } else { 0 },
+            };
+            crate::register_w3c_tracing_root::<TestTelemetry>(&traceparent, None).unwrap();
+            assert_eq!(
+                crate::dist_telemetry_stats::<TestTelemetry>()
+                    .unwrap()
+                    .registered_trace_ctxs,
+                1
+            );
+            child();
+        }
+
+        #[instrument]
+        fn child() {
+            tracing::info!("event");
+        }
+
+        tracing::subscriber::with_default(subscriber, || {
+            root(true);
+            root(false);
+            // not part of any trace
+            child();
+            tracing::info!("orphan");
+
+            let stats = crate::dist_telemetry_stats::<TestTelemetry>().unwrap();
+            assert_eq!(
+                stats,
+                TelemetryStats {
+                    spans_observed: 5,
+                    events_observed: 4,
+                    spans_reported: 2,
+                    events_reported: 1,
+                    spans_unsampled: 2,
+                    events_unsampled: 1,
+                    spans_without_trace_ctx: 1,
+                    events_without_trace_ctx: 2,
+                    registered_trace_ctxs: 0,
+                    backend: Default::default(),
+                }
+            );
+        });
+
+        assert_eq!(crate::dist_telemetry_stats::<TestTelemetry>(), None);
+    }
+
+    #[test]
+    fn test_orphan_events() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let cap = TestTelemetry::new(spans.clone(), events.clone());
+        let next_trace_id = AtomicU64::new(1);
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x)
+            .with_orphan_events(move || next_trace_id.fetch_add(1, Ordering::Relaxed));
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        #[instrument]
+        fn untraced() {
+            tracing::warn!("in untraced span");
+        }
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::error!(code = 7, "orphan");
+            untraced();
+
+            let stats = crate::dist_telemetry_stats::<TestTelemetry>().unwrap();
+            assert_eq!(stats.events_reported, 2);
+            assert_eq!(stats.events_without_trace_ctx, 0);
+            assert_eq!(stats.spans_without_trace_ctx, 1);
+        });
+
+        // spans not part of any trace are still dropped
+        assert!(spans.lock().unwrap().is_empty());
+        let events = events.lock().unwrap();
+        let trace_ids: Vec<_> = events.iter().map(|e| e.trace_id).collect();
+        assert_eq!(trace_ids, vec![1, 2]);
+        assert!(events.iter().all(|e| e.parent_id.is_none()));
+    }
+
+    #[test]
+    fn test_service_name_override() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let cap = TestTelemetry::new(spans.clone(), events.clone());
+        let service_name = String::from("test_svc_name");
+        let layer = TelemetryLayer::new(service_name, cap, |x| x);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        #[instrument]
+        fn root(trace_id: TraceId, service_name: Option<&'static str>) {
+            trace::register_dist_tracing_root::<SpanId, TraceId>(trace_id, None).unwrap();
+            if let Some(service_name) = service_name {
+                trace::set_service_name::<SpanId, TraceId>(service_name).unwrap();
+            }
+            child();
+        }
+
+        #[instrument]
+        fn child() {
+            tracing::info!("event");
+        }
+
+        tracing::subscriber::with_default(subscriber, || {
+            root(1, Some("billing"));
+            root(2, None);
+        });
+
+        let service_names = |trace_id| {
+            let spans = spans.lock().unwrap();
+            let events = events.lock().unwrap();
+            spans
+                .iter()
+                .filter(|span| span.trace_id == trace_id)
+                .map(|span| span.service_name.to_string())
+                .chain(
+                    events
+                        .iter()
+                        .filter(|event| event.trace_id == trace_id)
+                        .map(|event| event.service_name.to_string()),
+                )
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(service_names(1), vec!["billing"; 3]);
+        assert_eq!(service_names(2), vec!["test_svc_name"; 3]);
+    }
+
     fn with_test_scenario_runner<F>(f: F)
     where
         F: Fn(),
@@ -0,0 +1,333 @@
+//! Test support for crates instrumented with `tracing`, enabled by the `testing` feature.
+//!
+//! `RecordingTelemetry` keeps every span and event reported by a `TelemetryLayer` in memory, so that
+//! tests can assert on the shape of the traces their instrumentation produces:
+//!
+//! ```ignore
+//! let telemetry = RecordingTelemetry::new();
+//! let layer = TelemetryLayer::new("my_service", telemetry.clone(), |id| id);
+//! tracing::subscriber::with_default(layer.with_subscriber(Registry::default()), handle_request);
+//!
+//! telemetry
+//!     .recording()
+//!     .assert_child_of("query", "handle_request")
+//!     .assert_event_in_span("query", "table", "users")
+//!     .assert_no_orphan_spans();
+//! ```
+
+use crate::propagation::IdCodec;
+use crate::telemetry::Telemetry;
+use crate::trace::{Event, Span};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use tracing::field::{Field, Visit};
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+/// Visitor that captures the fields of spans and events as strings. `&str` values are captured as-is,
+/// all other values via their `Debug` representation (eg `7`, `true`).
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct RecordingVisitor(BTreeMap<&'static str, String>);
+
+impl RecordingVisitor {
+    /// Get the captured value of the field named `name`, if any.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(|s| s.as_str())
+    }
+
+    /// Iterate over all captured fields, ordered by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        self.0.iter().map(|(k, v)| (*k, v.as_str()))
+    }
+}
+
+impl Visit for RecordingVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name(), format!("{:?}", value));
+    }
+}
+
+/// Telemetry implementation that records all spans and events reported to it in memory, for use
+/// in tests. Clones share the same recording, so a clone can be kept to inspect the spans and
+/// events reported by the layer that owns the original.
+///
+/// Defaults to `tracing::Id` span ids (use `|id| id` to construct the layer) and `u128` trace ids,
+/// for which `IdCodec` is implemented so that traces can be propagated via `W3CPropagator` or `B3Propagator`.
+#[derive(Debug)]
+pub struct RecordingTelemetry<SpanId = tracing::Id, TraceId = u128> {
+    recording: Arc<Mutex<Recording<SpanId, TraceId>>>,
+}
+
+impl<SpanId, TraceId> RecordingTelemetry<SpanId, TraceId>
+where
+    SpanId: Clone,
+    TraceId: Clone,
+{
+    /// Construct a `RecordingTelemetry` with an empty recording.
+    pub fn new() -> Self {
+        RecordingTelemetry {
+            recording: Arc::new(Mutex::new(Recording::default())),
+        }
+    }
+
+    /// Snapshot of the spans and events reported so far.
+    pub fn recording(&self) -> Recording<SpanId, TraceId> {
+        self.lock().clone()
+    }
+
+    /// Discard the spans and events reported so far.
+    pub fn clear(&self) {
+        *self.lock() = Recording::default();
+    }
+
+    fn lock(&self) -> impl std::ops::DerefMut<Target = Recording<SpanId, TraceId>> + '_ {
+        // succeed or die. failure is unrecoverable (mutex poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let recording = self.recording.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let recording = self.recording.lock();
+        recording
+    }
+}
+
+impl<SpanId: Clone, TraceId: Clone> Default for RecordingTelemetry<SpanId, TraceId> {
+    fn default() -> Self {
+        RecordingTelemetry::new()
+    }
+}
+
+impl<SpanId, TraceId> Clone for RecordingTelemetry<SpanId, TraceId> {
+    fn clone(&self) -> Self {
+        RecordingTelemetry {
+            recording: self.recording.clone(),
+        }
+    }
+}
+
+impl<SpanId, TraceId> Telemetry for RecordingTelemetry<SpanId, TraceId>
+where
+    SpanId: 'static + Clone + Send + Sync,
+    TraceId: 'static + Clone + Send + Sync,
+{
+    type Visitor = RecordingVisitor;
+    type TraceId = TraceId;
+    type SpanId = SpanId;
+
+    fn mk_visitor(&self) -> Self::Visitor {
+        RecordingVisitor::default()
+    }
+
+    fn report_span(&self, span: Span<Self::Visitor, Self::SpanId, Self::TraceId>) {
+        self.lock().spans.push(span);
+    }
+
+    fn report_event(&self, event: Event<Self::Visitor, Self::SpanId, Self::TraceId>) {
+        self.lock().events.push(event);
+    }
+}
+
+impl IdCodec for RecordingTelemetry<tracing::Id, u128> {
+    type TraceId = u128;
+    type SpanId = tracing::Id;
+
+    fn encode_trace_id(trace_id: &u128) -> u128 {
+        *trace_id
+    }
+
+    fn decode_trace_id(trace_id: u128) -> u128 {
+        trace_id
+    }
+
+    fn encode_span_id(span_id: &tracing::Id) -> u64 {
+        span_id.into_u64()
+    }
+
+    fn decode_span_id(span_id: u64) -> tracing::Id {
+        tracing::Id::from_u64(span_id)
+    }
+}
+
+/// Spans and events recorded by a `RecordingTelemetry`, in the order they were reported. Spans are
+/// reported once they close, so children precede their parents.
+///
+/// Spans are identified by name in assertions, which panic with a description of the recording if
+/// they don't hold. Assertions return `&Self` so that they can be chained.
+#[derive(Clone, Debug)]
+pub struct Recording<SpanId, TraceId> {
+    /// Spans reported so far.
+    pub spans: Vec<Span<RecordingVisitor, SpanId, TraceId>>,
+    /// Events reported so far.
+    pub events: Vec<Event<RecordingVisitor, SpanId, TraceId>>,
+}
+
+impl<SpanId, TraceId> Default for Recording<SpanId, TraceId> {
+    fn default() -> Self {
+        Recording {
+            spans: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+}
+
+impl<SpanId, TraceId> Recording<SpanId, TraceId>
+where
+    SpanId: PartialEq + fmt::Debug,
+    TraceId: PartialEq + fmt::Debug,
+{
+    /// Iterate over all spans named `name`.
+    pub fn spans_named<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a Span<RecordingVisitor, SpanId, TraceId>> {
+        self.spans
+            .iter()
+            .filter(move |span| span.meta.name() == name)
+    }
+
+    /// Get the span named `name`.
+    ///
+    /// Panics unless exactly one such span was recorded.
+    pub fn span(&self, name: &str) -> &Span<RecordingVisitor, SpanId, TraceId> {
+        let mut spans = self.spans.iter().filter(|span| span.meta.name() == name);
+        match (spans.next(), spans.next()) {
+            (Some(span), None) => span,
+            (None, _) => panic!("no span named {:?} was recorded: {:#?}", name, self.spans),
+            (Some(_), Some(_)) => panic!(
+                "more than one span named {:?} was recorded: {:#?}",
+                name, self.spans
+            ),
+        }
+    }
+
+    /// Assert that the span named `child` is a child of the span named `parent`.
+    pub fn assert_child_of(&self, child: &str, parent: &str) -> &Self {
+        let (child_span, parent_span) = (self.span(child), self.span(parent));
+        assert!(
+            child_span.parent_id.as_ref() == Some(&parent_span.id)
+                && child_span.trace_id == parent_span.trace_id,
+            "span {:?} is not a child of span {:?}: {:#?}",
+            child,
+            parent,
+            self.spans
+        );
+        self
+    }
+
+    /// Assert that some event emitted directly within the span named `span` has a field named
+    /// `field` whose captured value is `value` (see `RecordingVisitor`).
+    pub fn assert_event_in_span(&self, span: &str, field: &str, value: &str) -> &Self {
+        let id = &self.span(span).id;
+        assert!(
+            self.events
+                .iter()
+                .any(|event| event.parent_id.as_ref() == Some(id)
+                    && event.values.get(field) == Some(value)),
+            "no event with {}={:?} was recorded in span {:?}: {:#?}",
+            field,
+            value,
+            span,
+            self.events
+        );
+        self
+    }
+
+    /// Assert that all spans and events belong to the trace identified by `trace_id`.
+    pub fn assert_trace_id(&self, trace_id: &TraceId) -> &Self {
+        let spans = self.spans.iter().map(|span| (span.meta, &span.trace_id));
+        let events = self
+            .events
+            .iter()
+            .map(|event| (event.meta, &event.trace_id));
+        for (meta, id) in spans.chain(events) {
+            assert!(
+                id == trace_id,
+                "{:?} belongs to trace {:?}, not {:?}",
+                meta.name(),
+                id,
+                trace_id
+            );
+        }
+        self
+    }
+
+    /// Assert that the parent of every span, other than local roots (whose parent, if any, is
+    /// remote), was recorded.
+    pub fn assert_no_orphan_spans(&self) -> &Self {
+        for span in self.spans.iter().filter(|span| !span.local_root) {
+            assert!(
+                span.parent_id.as_ref().map_or(false, |parent_id| self
+                    .spans
+                    .iter()
+                    .any(|s| &s.id == parent_id)),
+                "the parent of span {:?} was not recorded: {:#?}",
+                span.meta.name(),
+                self.spans
+            );
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{register_dist_tracing_root, TelemetryLayer};
+    use tracing::instrument;
+    use tracing_subscriber::layer::Layer;
+    use tracing_subscriber::registry;
+
+    fn record<F: FnOnce()>(f: F) -> Recording<tracing::Id, u128> {
+        let telemetry = RecordingTelemetry::new();
+        let layer = TelemetryLayer::new("test_svc_name", telemetry.clone(), |x| x);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+        tracing::subscriber::with_default(subscriber, f);
+        telemetry.recording()
+    }
+
+    #[instrument]
+    fn handle_request() {
+        register_dist_tracing_root::<tracing::Id, u128>(135, None).unwrap();
+        query();
+    }
+
+    #[instrument]
+    fn query() {
+        tracing::info!(table = "users", rows = 3, "queried");
+    }
+
+    #[test]
+    fn assert_trace_shape() {
+        let recording = record(handle_request);
+
+        recording
+            .assert_child_of("query", "handle_request")
+            .assert_event_in_span("query", "table", "users")
+            .assert_event_in_span("query", "rows", "3")
+            .assert_event_in_span("query", "message", "queried")
+            .assert_trace_id(&135)
+            .assert_no_orphan_spans();
+        assert!(recording.span("handle_request").local_root);
+    }
+
+    #[test]
+    #[should_panic(expected = "span \"handle_request\" is not a child of span \"query\"")]
+    fn assert_child_of_fails() {
+        record(handle_request).assert_child_of("handle_request", "query");
+    }
+
+    #[test]
+    #[should_panic(expected = "the parent of span \"query\" was not recorded")]
+    fn assert_no_orphan_spans_fails() {
+        let mut recording = record(handle_request);
+        recording.spans.retain(|span| !span.local_root);
+        recording.assert_no_orphan_spans();
+    }
+}
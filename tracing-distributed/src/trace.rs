@@ -1,12 +1,51 @@
-use crate::telemetry_layer::TraceCtxRegistry;
-use std::time::SystemTime;
-use tracing_subscriber::registry::LookupSpan;
+use crate::baggage::Baggage;
+use crate::kind::SpanKind;
+use crate::resource::Resource;
+use crate::sampling::{RootFields, RootSpan};
+use crate::status::SpanStatus;
+use crate::telemetry_layer::{LazyTraceCtx, TraceCtx, TraceCtxRegistry};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::span::Id;
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
+use tracing_subscriber::Registry;
 
 /// Register the current span as the local root of a distributed trace.
 pub fn register_dist_tracing_root<SpanId, TraceId>(
     trace_id: TraceId,
     remote_parent_span: Option<SpanId>,
 ) -> Result<(), TraceCtxError>
+where
+    SpanId: 'static + Clone + Send + Sync,
+    TraceId: 'static + Clone + Send + Sync,
+{
+    register_dist_tracing_root_with_baggage(trace_id, remote_parent_span, Baggage::default())
+}
+
+/// Register the current span as the local root of a distributed trace, along with baggage
+/// received from the remote parent span (if any).
+pub fn register_dist_tracing_root_with_baggage<SpanId, TraceId>(
+    trace_id: TraceId,
+    remote_parent_span: Option<SpanId>,
+    baggage: Baggage,
+) -> Result<(), TraceCtxError>
+where
+    SpanId: 'static + Clone + Send + Sync,
+    TraceId: 'static + Clone + Send + Sync,
+{
+    register_trace_ctx(trace_id, remote_parent_span, None, None, baggage)
+}
+
+/// Register the current span as the local root of a distributed trace, using the sampler of the
+/// registered `TelemetryLayer` to decide whether to sample it given the sampling decision of its
+/// remote parent (if one was propagated).
+pub(crate) fn register_trace_ctx<SpanId, TraceId>(
+    trace_id: TraceId,
+    remote_parent_span: Option<SpanId>,
+    trace_state: Option<String>,
+    parent_sampled: Option<bool>,
+    baggage: Baggage,
+) -> Result<(), TraceCtxError>
 where
     SpanId: 'static + Clone + Send + Sync,
     TraceId: 'static + Clone + Send + Sync,
@@ -16,11 +55,29 @@ where
         if let Some(trace_ctx_registry) =
             dispatch.downcast_ref::<TraceCtxRegistry<SpanId, TraceId>>()
         {
-            trace_ctx_registry.record_trace_ctx(
-                trace_id,
-                remote_parent_span,
-                current_span_id.clone(),
+            let meta = span.metadata().expect("enabled spans have metadata");
+            // fields are only available if the registry is the subscriber's span store
+            let span_ref = dispatch
+                .downcast_ref::<Registry>()
+                .and_then(|registry| registry.span(current_span_id));
+            let extensions = span_ref.as_ref().map(|span_ref| span_ref.extensions());
+            let fields = extensions
+                .as_ref()
+                .and_then(|extensions| extensions.get::<RootFields>());
+            let sampling = trace_ctx_registry.sample(
+                &trace_id,
+                parent_sampled,
+                &RootSpan::with_fields(meta, fields),
             );
+            let trace_ctx = TraceCtx {
+                sampling,
+                trace_id,
+                parent_span: remote_parent_span,
+                trace_state,
+                baggage,
+                service_name: None,
+            };
+            trace_ctx_registry.record_trace_ctx(trace_ctx, current_span_id.clone());
             Ok(())
         } else {
             Err(TraceCtxError::TelemetryLayerNotRegistered)
@@ -37,41 +94,109 @@ where
     SpanId: 'static + Clone + Send + Sync,
     TraceId: 'static + Clone + Send + Sync,
 {
-    let span = tracing::Span::current();
-    span.with_subscriber(|(current_span_id, dispatch)| {
-        let trace_ctx_registry = dispatch
-            .downcast_ref::<TraceCtxRegistry<SpanId, TraceId>>()
-            .ok_or(TraceCtxError::TelemetryLayerNotRegistered)?;
-
-        let registry = dispatch
-            .downcast_ref::<tracing_subscriber::Registry>()
-            .ok_or(TraceCtxError::RegistrySubscriberNotRegistered)?;
+    current_trace_ctx().map(|(trace_ctx, span_id)| (trace_ctx.trace_id, span_id))
+}
 
-        let iter = itertools::unfold(Some(current_span_id.clone()), |st| match st {
-            Some(target_id) => {
-                // failure here indicates a broken parent id span link, panic is valid
-                let res = registry
-                    .span(target_id)
-                    .expect("span data not found during eval_ctx for current_trace_ctx");
-                *st = res.parent().map(|x| x.id());
-                Some(res)
-            }
-            None => None,
-        });
+/// Override the service name reported for the current span and spans subsequently created within it,
+/// eg just after registering a distributed tracing root, so that a process hosting several logical
+/// services can report each of their traces under its own service name.
+pub fn set_service_name<SpanId, TraceId>(
+    service_name: impl Into<Arc<str>>,
+) -> Result<(), TraceCtxError>
+where
+    SpanId: 'static + Clone + Send + Sync,
+    TraceId: 'static + Clone + Send + Sync,
+{
+    let service_name = service_name.into();
+    with_current_trace_ctx::<SpanId, TraceId, _, _>(|trace_ctx| {
+        trace_ctx.service_name = Some(service_name);
+    })
+}
 
+/// Retrieve the full trace ctx associated with the current span along with the `SpanId`
+/// belonging to the current span.
+pub(crate) fn current_trace_ctx<SpanId, TraceId>(
+) -> Result<(TraceCtx<SpanId, TraceId>, SpanId), TraceCtxError>
+where
+    SpanId: 'static + Clone + Send + Sync,
+    TraceId: 'static + Clone + Send + Sync,
+{
+    with_current_span(|trace_ctx_registry, registry, current_span_id| {
         trace_ctx_registry
-            .eval_ctx(iter)
+            .eval_ctx(span_and_ancestors(registry, current_span_id))
             .map(|x| {
                 (
-                    x.trace_id,
+                    x,
                     trace_ctx_registry.promote_span_id(current_span_id.clone()),
                 )
             })
             .ok_or(TraceCtxError::NoParentNodeHasTraceCtx)
     })
+}
+
+/// Apply `f` to the trace ctx associated with the current span. Changes are visible to the current
+/// span and to any spans subsequently created within it.
+pub(crate) fn with_current_trace_ctx<SpanId, TraceId, F, R>(f: F) -> Result<R, TraceCtxError>
+where
+    SpanId: 'static + Clone + Send + Sync,
+    TraceId: 'static + Clone + Send + Sync,
+    F: FnOnce(&mut TraceCtx<SpanId, TraceId>) -> R,
+{
+    with_current_span::<SpanId, TraceId, _, _>(|trace_ctx_registry, registry, current_span_id| {
+        // caches the evaluated trace ctx on the current span, where it can then be modified
+        trace_ctx_registry
+            .eval_ctx(span_and_ancestors(registry, current_span_id))
+            .ok_or(TraceCtxError::NoParentNodeHasTraceCtx)?;
+
+        let span = registry
+            .span(current_span_id)
+            .expect("span data not found during with_current_trace_ctx");
+        let mut extensions_mut = span.extensions_mut();
+        let LazyTraceCtx(trace_ctx) = extensions_mut
+            .get_mut::<LazyTraceCtx<SpanId, TraceId>>()
+            .expect("trace ctx not cached during eval_ctx");
+        Ok(f(trace_ctx))
+    })
+}
+
+fn with_current_span<SpanId, TraceId, F, R>(f: F) -> Result<R, TraceCtxError>
+where
+    SpanId: 'static + Clone + Send + Sync,
+    TraceId: 'static + Clone + Send + Sync,
+    F: FnOnce(&TraceCtxRegistry<SpanId, TraceId>, &Registry, &Id) -> Result<R, TraceCtxError>,
+{
+    let span = tracing::Span::current();
+    span.with_subscriber(|(current_span_id, dispatch)| {
+        let trace_ctx_registry = dispatch
+            .downcast_ref::<TraceCtxRegistry<SpanId, TraceId>>()
+            .ok_or(TraceCtxError::TelemetryLayerNotRegistered)?;
+
+        let registry = dispatch
+            .downcast_ref::<Registry>()
+            .ok_or(TraceCtxError::RegistrySubscriberNotRegistered)?;
+
+        f(trace_ctx_registry, registry, current_span_id)
+    })
     .ok_or(TraceCtxError::NoEnabledSpan)?
 }
 
+fn span_and_ancestors<'a>(
+    registry: &'a Registry,
+    id: &Id,
+) -> impl Iterator<Item = SpanRef<'a, Registry>> {
+    itertools::unfold(Some(id.clone()), move |st| match st {
+        Some(target_id) => {
+            // failure here indicates a broken parent id span link, panic is valid
+            let res = registry
+                .span(target_id)
+                .expect("span data not found during eval_ctx for current_trace_ctx");
+            *st = res.parent().map(|x| x.id());
+            Some(res)
+        }
+        None => None,
+    })
+}
+
 /// Errors that can occur while registering the current span as a distributed trace root or
 /// attempting to retrieve the current trace context.
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
@@ -85,6 +210,8 @@ pub enum TraceCtxError {
     NoEnabledSpan,
     /// Attempted to evaluate the current distributed trace context but none was found. If this occurs, you should check to make sure that `register_dist_tracing_root` is called in some parent of the current span.
     NoParentNodeHasTraceCtx,
+    /// Attempted to register the current span as a distributed trace root using trace context read from a `Carrier`, but the `Propagator` found none.
+    NoPropagatedTraceCtx,
 }
 
 /// A `Span` holds ready-to-publish information gathered during the lifetime of a `tracing::Span`.
@@ -96,14 +223,35 @@ pub struct Span<Visitor, SpanId, TraceId> {
     pub trace_id: TraceId,
     /// optional parent span id
     pub parent_id: Option<SpanId>,
+    /// whether this span is the local root of its trace in this process, ie it was registered as a
+    /// distributed tracing root or it started a new trace. Local roots are reported after all their descendants.
+    pub local_root: bool,
+    /// spans this span follows from (eg the requests fanned into a batch job), which may belong to other traces
+    pub follows_from: Vec<(TraceId, SpanId)>,
     /// UTC time at which this span was initialized
     pub initialized_at: SystemTime,
     /// `chrono::Duration` elapsed between the time this span was initialized and the time it was completed
     pub completed_at: SystemTime,
+    /// total time spent inside this span, ie between entering and exiting it
+    pub busy: Duration,
+    /// total time spent outside this span between its initialization and completion, eg while an async task is parked
+    pub idle: Duration,
+    /// number of times this span was entered, eg the number of times an instrumented future was polled
+    pub enter_count: u64,
+    /// whether the operation represented by this span succeeded
+    pub status: SpanStatus,
+    /// relationship between this span and its remote parent or children, as recorded via the `span.kind` field
+    pub kind: SpanKind,
+    /// baggage associated with this span, empty unless the `TelemetryLayer` was configured to report baggage
+    pub baggage: Baggage,
+    /// number of spans this span stands in for given the sampling decision for its trace, eg 10 if one in 10 traces is sampled
+    pub sample_rate: u64,
     /// `tracing::Metadata` for this span
     pub meta: &'static tracing::Metadata<'static>,
     /// name of the service on which this span occured
-    pub service_name: &'static str,
+    pub service_name: Arc<str>,
+    /// attributes describing the process on which this span occured, as configured via `TelemetryLayer::with_resource`
+    pub resource: Resource,
     /// values accumulated by visiting fields observed by the `tracing::Span` this span was derived from
     pub values: Visitor,
 }
@@ -113,14 +261,18 @@ pub struct Span<Visitor, SpanId, TraceId> {
 pub struct Event<Visitor, SpanId, TraceId> {
     /// `TraceId` identifying the trace to which this event belongs
     pub trace_id: TraceId,
-    /// optional parent span id
+    /// optional parent span id, `None` for events not part of any trace (see `TelemetryLayer::with_orphan_events`)
     pub parent_id: Option<SpanId>,
     /// UTC time at which this event was initialized
     pub initialized_at: SystemTime,
+    /// number of events this event stands in for given the sampling decision for its trace, eg 10 if one in 10 traces is sampled
+    pub sample_rate: u64,
     /// `tracing::Metadata` for this event
     pub meta: &'static tracing::Metadata<'static>,
     /// name of the service on which this event occured
-    pub service_name: &'static str,
+    pub service_name: Arc<str>,
+    /// attributes describing the process on which this event occured, as configured via `TelemetryLayer::with_resource`
+    pub resource: Resource,
     /// values accumulated by visiting the fields of the `tracing::Event` this event was derived from
     pub values: Visitor,
 }
@@ -0,0 +1,333 @@
+use crate::baggage::{Baggage, BAGGAGE_HEADER};
+use crate::propagation::{Carrier, IdCodec, Propagator, RemoteTraceCtx};
+use crate::trace::{current_trace_ctx, register_trace_ctx, TraceCtxError};
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+/// Name of the header carrying a W3C `traceparent` value.
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Name of the header carrying a W3C `tracestate` value.
+pub const TRACESTATE_HEADER: &str = "tracestate";
+
+/// `trace-flags` bit indicating that the caller may have recorded trace data.
+pub const TRACE_FLAG_SAMPLED: u8 = 0x01;
+
+const SUPPORTED_VERSION: u8 = 0x00;
+const INVALID_VERSION: u8 = 0xff;
+
+/// A parsed [W3C Trace Context](https://www.w3.org/TR/trace-context/) `traceparent` header value.
+///
+/// `Display` always produces a version `00` header value. `Display` and `FromStr` are guaranteed to round-trip for version `00` header values.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub struct TraceParent {
+    /// 128-bit id of the trace, never zero
+    pub trace_id: u128,
+    /// 64-bit id of the remote parent span, never zero
+    pub parent_id: u64,
+    /// trace flags, only `TRACE_FLAG_SAMPLED` is currently defined
+    pub trace_flags: u8,
+}
+
+impl TraceParent {
+    /// Returns true if the `sampled` trace flag is set.
+    pub fn is_sampled(&self) -> bool {
+        self.trace_flags & TRACE_FLAG_SAMPLED == TRACE_FLAG_SAMPLED
+    }
+}
+
+/// Errors that can occur while parsing a `traceparent` header value.
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+#[non_exhaustive]
+pub enum ParseTraceParentError {
+    /// The header value is not made up of dash-separated lowercase hex fields of the expected lengths.
+    InvalidFormat,
+    /// The header value uses the version reserved as invalid (`ff`).
+    UnsupportedVersion,
+    /// The trace id field is all zeroes.
+    InvalidTraceId,
+    /// The parent id field is all zeroes.
+    InvalidParentId,
+}
+
+impl FromStr for TraceParent {
+    type Err = ParseTraceParentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut iter = s.trim().split('-');
+        let mut next_field = |len| {
+            iter.next()
+                .and_then(|field| parse_lower_hex(field, len))
+                .ok_or(ParseTraceParentError::InvalidFormat)
+        };
+
+        let version = next_field(2)? as u8;
+        if version == INVALID_VERSION {
+            return Err(ParseTraceParentError::UnsupportedVersion);
+        }
+        let trace_id = next_field(32)?;
+        let parent_id = next_field(16)? as u64;
+        let trace_flags = next_field(2)? as u8;
+
+        // later versions may append fields, which must be ignored
+        if version == SUPPORTED_VERSION && iter.next().is_some() {
+            return Err(ParseTraceParentError::InvalidFormat);
+        }
+        if trace_id == 0 {
+            return Err(ParseTraceParentError::InvalidTraceId);
+        }
+        if parent_id == 0 {
+            return Err(ParseTraceParentError::InvalidParentId);
+        }
+
+        Ok(TraceParent {
+            trace_id,
+            parent_id,
+            trace_flags,
+        })
+    }
+}
+
+impl std::fmt::Display for TraceParent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:02x}-{:032x}-{:016x}-{:02x}",
+            SUPPORTED_VERSION, self.trace_id, self.parent_id, self.trace_flags
+        )
+    }
+}
+
+// the w3c spec only allows lowercase hex digits, which `from_str_radix` does not enforce
+fn parse_lower_hex(s: &str, len: usize) -> Option<u128> {
+    if s.len() == len && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        u128::from_str_radix(s, 16).ok()
+    } else {
+        None
+    }
+}
+
+/// Register the current span as the local root of a distributed trace continued from a remote
+/// parent, as described by the `traceparent` and (optional) `tracestate` headers of an incoming request.
+///
+/// The `tracestate` value is opaque to this crate and is forwarded as-is by `current_w3c_trace_ctx`.
+/// By default, spans and events in the trace are not reported if the `sampled` trace flag is not set.
+pub fn register_w3c_tracing_root<C>(
+    traceparent: &TraceParent,
+    tracestate: Option<String>,
+) -> Result<(), TraceCtxError>
+where
+    C: IdCodec,
+    C::SpanId: 'static + Clone + Send + Sync,
+    C::TraceId: 'static + Clone + Send + Sync,
+{
+    register_trace_ctx(
+        C::decode_trace_id(traceparent.trace_id),
+        Some(C::decode_span_id(traceparent.parent_id)),
+        tracestate.filter(|s| !s.trim().is_empty()),
+        Some(traceparent.is_sampled()),
+        Baggage::default(),
+    )
+}
+
+/// Retrieve the distributed trace context associated with the current span as the values of the
+/// `traceparent` and (optional) `tracestate` headers to attach to an outgoing request.
+pub fn current_w3c_trace_ctx<C>() -> Result<(TraceParent, Option<String>), TraceCtxError>
+where
+    C: IdCodec,
+    C::SpanId: 'static + Clone + Send + Sync,
+    C::TraceId: 'static + Clone + Send + Sync,
+{
+    let (trace_ctx, span_id) = current_trace_ctx::<C::SpanId, C::TraceId>()?;
+    let traceparent = encode_traceparent::<C>(
+        &trace_ctx.trace_id,
+        &span_id,
+        trace_ctx.sampling.is_sampled(),
+    );
+
+    Ok((traceparent, trace_ctx.trace_state))
+}
+
+fn encode_traceparent<C: IdCodec>(
+    trace_id: &C::TraceId,
+    span_id: &C::SpanId,
+    sampled: bool,
+) -> TraceParent {
+    TraceParent {
+        trace_id: C::encode_trace_id(trace_id),
+        parent_id: C::encode_span_id(span_id),
+        trace_flags: if sampled { TRACE_FLAG_SAMPLED } else { 0 },
+    }
+}
+
+/// `Propagator` using the W3C `traceparent`, `tracestate` and `baggage` headers, with ids converted using `C`.
+pub struct W3CPropagator<C>(PhantomData<fn() -> C>);
+
+impl<C> Default for W3CPropagator<C> {
+    fn default() -> Self {
+        W3CPropagator(PhantomData)
+    }
+}
+
+impl<C> std::fmt::Debug for W3CPropagator<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("W3CPropagator")
+    }
+}
+
+impl<C: IdCodec> Propagator<C::SpanId, C::TraceId> for W3CPropagator<C> {
+    fn inject(&self, ctx: &RemoteTraceCtx<C::SpanId, C::TraceId>, carrier: &mut dyn Carrier) {
+        // w3c has no way to defer the sampling decision, so treat that as sampled
+        let sampled = ctx.sampled.unwrap_or(true);
+        let traceparent = encode_traceparent::<C>(&ctx.trace_id, &ctx.span_id, sampled);
+        carrier.set(TRACEPARENT_HEADER, traceparent.to_string());
+        if let Some(trace_state) = &ctx.trace_state {
+            carrier.set(TRACESTATE_HEADER, trace_state.clone());
+        }
+        if !ctx.baggage.is_empty() {
+            carrier.set(BAGGAGE_HEADER, ctx.baggage.to_w3c_header());
+        }
+    }
+
+    fn extract(&self, carrier: &dyn Carrier) -> Option<RemoteTraceCtx<C::SpanId, C::TraceId>> {
+        let traceparent = TraceParent::from_str(carrier.get(TRACEPARENT_HEADER)?).ok()?;
+        let trace_state = carrier
+            .get(TRACESTATE_HEADER)
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| s.to_string());
+        let baggage = carrier
+            .get(BAGGAGE_HEADER)
+            .map(Baggage::from_w3c_header)
+            .unwrap_or_default();
+
+        Some(RemoteTraceCtx {
+            trace_id: C::decode_trace_id(traceparent.trace_id),
+            span_id: C::decode_span_id(traceparent.parent_id),
+            trace_state,
+            sampled: Some(traceparent.is_sampled()),
+            baggage,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::test::TestTelemetry;
+    use crate::TelemetryLayer;
+    use std::sync::{Arc, Mutex};
+    use tracing::instrument;
+    use tracing_subscriber::layer::Layer;
+    use tracing_subscriber::registry;
+
+    const EXAMPLE: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn parse_example_header() {
+        let traceparent = TraceParent::from_str(EXAMPLE).unwrap();
+        assert_eq!(
+            traceparent.trace_id,
+            0x4bf9_2f35_77b3_4da6_a3ce_929d_0e0e_4736
+        );
+        assert_eq!(traceparent.parent_id, 0x00f0_67aa_0ba9_02b7);
+        assert!(traceparent.is_sampled());
+        assert_eq!(traceparent.to_string(), EXAMPLE);
+    }
+
+    #[test]
+    fn reject_invalid_headers() {
+        let cases = [
+            (
+                "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+                ParseTraceParentError::InvalidFormat,
+            ),
+            (
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+                ParseTraceParentError::InvalidFormat,
+            ),
+            (
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-00",
+                ParseTraceParentError::InvalidFormat,
+            ),
+            (
+                "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+                ParseTraceParentError::UnsupportedVersion,
+            ),
+            (
+                "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+                ParseTraceParentError::InvalidTraceId,
+            ),
+            (
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+                ParseTraceParentError::InvalidParentId,
+            ),
+        ];
+
+        for (header, err) in cases.iter() {
+            assert_eq!(
+                TraceParent::from_str(header),
+                Err(err.clone()),
+                "{}",
+                header
+            );
+        }
+    }
+
+    #[test]
+    fn accept_later_versions() {
+        let header = "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-future";
+        let traceparent = TraceParent::from_str(header).unwrap();
+        assert!(!traceparent.is_sampled());
+        assert_eq!(
+            traceparent.to_string(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00"
+        );
+    }
+
+    #[test]
+    fn propagate_baggage() {
+        let ctx = RemoteTraceCtx {
+            baggage: vec![("tenant", "acme")].into_iter().collect(),
+            ..RemoteTraceCtx::new(135, tracing::Id::from_u64(246))
+        };
+        let mut carrier = std::collections::HashMap::new();
+        W3CPropagator::<TestTelemetry>::default().inject(&ctx, &mut carrier);
+        assert_eq!(
+            carrier.get(BAGGAGE_HEADER),
+            Some(&"tenant=acme".to_string())
+        );
+
+        let extracted = W3CPropagator::<TestTelemetry>::default()
+            .extract(&carrier)
+            .unwrap();
+        assert_eq!(extracted.baggage, ctx.baggage);
+    }
+
+    #[test]
+    fn register_and_propagate() {
+        let cap = TestTelemetry::new(Arc::new(Mutex::new(Vec::new())), Arc::default());
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        #[instrument]
+        fn f() {
+            let traceparent = TraceParent::from_str(EXAMPLE).unwrap();
+            register_w3c_tracing_root::<TestTelemetry>(&traceparent, Some("vendor=value".into()))
+                .unwrap();
+            g();
+        }
+
+        #[instrument]
+        fn g() {
+            let (traceparent, tracestate) = current_w3c_trace_ctx::<TestTelemetry>().unwrap();
+            let current_span_id = tracing::Span::current().id().unwrap().into_u64();
+
+            assert_eq!(traceparent.trace_id, 0xa3ce_929d_0e0e_4736);
+            assert_eq!(traceparent.parent_id, current_span_id);
+            assert!(traceparent.is_sampled());
+            assert_eq!(tracestate, Some("vendor=value".to_string()));
+        }
+
+        tracing::subscriber::with_default(subscriber, f);
+    }
+}
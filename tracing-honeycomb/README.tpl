@@ -18,11 +18,37 @@ This crate provides two functions for out of band interaction with the `Telemetr
 - `register_dist_tracing_root` registers the current span as the local root of a distributed trace.
 - `current_dist_trace_ctx` fetches the `TraceId` and `SpanId` associated with the current span.
 
+To interoperate with services written in other languages, `register_w3c_tracing_root` and `current_w3c_trace_ctx` do the same using the [W3C Trace Context](https://www.w3.org/TR/trace-context/) `traceparent` and `tracestate` headers. To join traces started by services instrumented with honeycomb beelines, use `BeelinePropagator` with `inject_dist_trace_ctx` and `extract_dist_tracing_root` to propagate trace context via the `X-Honeycomb-Trace` header.
+
+Request-scoped values like a tenant id can be carried across services as baggage: `set_baggage_item` adds an entry visible to the current span and spans created within it, `current_baggage` reads it, and propagators carry it via the W3C `baggage` header or the beeline `context` field. Use `TelemetryLayer::with_baggage_fields` to publish baggage entries as `baggage.*` fields on every span.
+
 Here's an example of how they might be used together:
 1. Some span is registered as the global tracing root using a newly-generated `TraceId`.
 2. A child of that span uses `current_dist_trace_ctx` to fetch the current `TraceId` and `SpanId`. It passes these values along with an RPC request, as metadata.
 3. The RPC service handler uses the `TraceId` and remote parent `SpanId` provided in the request's metadata to register the handler function's span as a local root of the distributed trace initiated in step 1.
 
+By default, spans are only published if they (or some ancestor) were registered as a distributed tracing root. To trace background jobs and CLI tools without registering roots explicitly, use `TelemetryLayer::with_auto_trace_roots(TraceId::generate)` to treat every top-level span without registered trace context as the root of a new trace.
+
+Whether a trace is sampled is decided when its local root is registered or automatically created, using the `Sampler` provided via `TelemetryLayer::with_sampler`. Built-in samplers include `RatioSampler`, `RateLimitedSampler`, `AlwaysSample`, `NeverSample` and `TraceIdSampler`, and `ParentBasedSampler` honors the decision of a remote parent. By default, every trace is sampled unless its remote parent was propagated as not sampled.
+
+Declarative sampling rules can be provided via `RulesSampler`, which applies the first `SamplingRule` matching the name, target, level or field values of a trace's local root span to keep it, drop it or sample it at some rate. With the `rules_config` feature of `tracing-distributed` enabled, rules can be loaded from TOML or JSON via `RulesSampler::from_toml` and `RulesSampler::from_json`.
+
+To keep rare events while heavily sampling common ones, `new_honeycomb_telemetry_layer_with_dynamic_sampling` applies a `DynamicSampler` that chooses a sample rate per key (eg `DynamicSampler::by_fields(100.0, vec!["name", "http.status_code"])`) to target an overall number of events per second. Published events carry a `SampleRate` accounting for both trace-level and dynamic sampling, so that honeycomb.io re-weights counts.
+
+Spans and events are handed to a background worker that sends them to honeycomb.io via a bounded queue, so traced threads never contend on the libhoney client. Use `new_honeycomb_telemetry_layer_with_exporter_config` to configure the queue's capacity and whether to drop new data, drop the oldest queued data or block while it's full. `HoneycombTelemetry::dropped_count` reports the number of spans and events dropped so far, and can be read via `TelemetryLayer::telemetry`.
+
+Call `shutdown_dist_telemetry` before exiting to wait (up to a timeout) for the worker to send all queued spans and events and close the libhoney client, or `flush_dist_telemetry` to wait for queued data to be sent without shutting down.
+
+`dist_telemetry_stats` reports how many spans and events were observed, reported, dropped (by sampling, for lack of a trace context, because a queue was full or after shutdown) or failed to export, along with the number of registered trace roots and queued items, eg to export them to a metrics system.
+
+Spans and events lost because a queue was full or they failed to export can also be reported to honeycomb.io itself: if `ExporterConfig::data_loss_interval` is set (it is `None` by default), then at that interval the exporter publishes a `tracing.dropped_events` record to the same dataset with the number of items lost since the previous one, broken down by reason (`dropped.buffer_full`, `dropped.export_errors`).
+
+Use `TelemetryLayer::with_resource` to attach process-wide attributes (eg `host.name`, `process.pid`, `service.version`, `cloud.region`) to every span and event, where they're published as fields. `Resource::from_env` detects common attributes from the environment, including any listed in the `OTEL_RESOURCE_ATTRIBUTES` environment variable.
+
+Service names may be any `&'static str`, `String` or `Arc<str>`, eg read from a config file. A process hosting several logical services can report each trace under its own service name by calling `set_service_name` just after registering its root.
+
+Events emitted outside of any trace, eg standalone `error!` events in a background worker, are dropped by default. Use `TelemetryLayer::with_orphan_events(TraceId::generate)` to publish each of them as the root span of its own single-event trace instead.
+
 ### Registering a global Subscriber
 
 The following example shows how to create and register a subscriber created by composing `TelemetryLayer` with other layers and the `Registry` subscriber provided by the `tracing_subscriber` crate.
@@ -1,10 +1,12 @@
-use std::{env, str::FromStr, time::Duration};
+use std::collections::HashMap;
+use std::{env, time::Duration};
 use tokio::process::Command;
 use tokio::time::delay_for;
 use tracing::instrument;
 use tracing_honeycomb::{
-    current_dist_trace_ctx, new_honeycomb_telemetry_layer, register_dist_tracing_root, SpanId,
-    TraceId,
+    extract_dist_tracing_root, inject_dist_trace_ctx, new_honeycomb_telemetry_layer,
+    register_dist_tracing_root, shutdown_dist_telemetry, TraceId, W3CPropagator,
+    TRACEPARENT_HEADER,
 };
 use tracing_subscriber::filter::LevelFilter;
 use tracing_subscriber::layer::SubscriberExt;
@@ -21,11 +23,10 @@ async fn spawn_children(n: u32, process_name: String) {
 
 #[instrument]
 async fn spawn_child_process(process_name: &str) {
-    let (trace_id, span_id) = current_dist_trace_ctx().unwrap();
-    let child = Command::new(process_name)
-        .arg(span_id.to_string())
-        .arg(trace_id.to_string())
-        .spawn();
+    // pass trace ctx to the child process via environment variables
+    let mut trace_ctx = HashMap::new();
+    inject_dist_trace_ctx(&W3CPropagator::default(), &mut trace_ctx).unwrap();
+    let child = Command::new(process_name).envs(trace_ctx).spawn();
 
     // Make sure our child succeeded in spawning and process the result
     let future = child.expect("failed to spawn");
@@ -34,9 +35,9 @@ async fn spawn_child_process(process_name: &str) {
     future.await.expect("awaiting process failed");
 }
 
-#[instrument]
-async fn run_in_child_process(trace_id: TraceId, parent_span: SpanId) {
-    register_dist_tracing_root(trace_id, Some(parent_span)).unwrap();
+#[instrument(skip(trace_ctx))]
+async fn run_in_child_process(trace_ctx: HashMap<String, String>) {
+    extract_dist_tracing_root(&W3CPropagator::default(), &trace_ctx).unwrap();
 
     tracing::info!("leaf fn");
     delay_for(Duration::from_millis(50)).await
@@ -44,29 +45,24 @@ async fn run_in_child_process(trace_id: TraceId, parent_span: SpanId) {
 
 #[tokio::main]
 async fn main() {
-    // parse first two args (including 0th arg, to get current process name)
-    let mut iter = env::args();
-    let process_name = iter.next().expect("expected first arg to be process name");
-    let parent_span = iter.next();
-    let trace_id = iter.next();
+    // parse first arg (0th arg, to get current process name)
+    let process_name = env::args()
+        .next()
+        .expect("expected first arg to be process name");
+    let trace_ctx: HashMap<String, String> = env::vars().collect();
 
     register_global_subscriber();
 
-    match (parent_span, trace_id) {
-        (Some(parent_span), Some(trace_id)) => {
-            let parent_span = SpanId::from_str(&parent_span).unwrap();
-            let trace_id = TraceId::from_str(&trace_id).unwrap();
-            // parent trace ctx present, run leaf fn
-            run_in_child_process(trace_id, parent_span).await;
-        }
-        _ => {
-            // no parent trace_ctx, spawn child processes
-            spawn_children(5, process_name).await;
-        }
+    if trace_ctx.contains_key(TRACEPARENT_HEADER) {
+        // parent trace_ctx present, run leaf fn
+        run_in_child_process(trace_ctx).await;
+    } else {
+        // no parent trace_ctx, spawn child processes
+        spawn_children(5, process_name).await;
     }
 
-    // janky, but delay seems to be required to ensure all traces are sent to honeycomb by libhoney
-    delay_for(Duration::from_secs(10)).await
+    // deliver any spans that are still buffered before exiting
+    shutdown_dist_telemetry(Duration::from_secs(10)).unwrap();
 }
 
 fn register_global_subscriber() {
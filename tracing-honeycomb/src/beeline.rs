@@ -0,0 +1,308 @@
+use crate::honeycomb::{SpanId, TraceId};
+use libhoney::Value;
+use std::collections::HashMap;
+use std::str::FromStr;
+use tracing_distributed::{Baggage, Carrier, Propagator, RemoteTraceCtx};
+
+/// Name of the header used by honeycomb beelines to propagate trace context.
+pub const BEELINE_HEADER: &str = "x-honeycomb-trace";
+
+const SUPPORTED_VERSION: &str = "1";
+
+/// A parsed `X-Honeycomb-Trace` header value, as propagated by honeycomb beelines:
+/// `1;trace_id=...,parent_id=...,dataset=...,context=...`.
+///
+/// As with beelines, the dataset is URL-encoded, so that it may contain any character.
+///
+/// `Display` and `FromStr` are guaranteed to round-trip.
+#[derive(PartialEq, Clone, Debug)]
+pub struct BeelineHeader {
+    /// id of the trace
+    pub trace_id: TraceId,
+    /// id of the remote parent span
+    pub parent_id: SpanId,
+    /// dataset the remote parent span was published to, if the sender chose to propagate it
+    pub dataset: Option<String>,
+    /// trace-level fields, which beelines add to every span in the trace
+    pub context: HashMap<String, Value>,
+}
+
+/// Errors that can occur while parsing an `X-Honeycomb-Trace` header value.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+#[non_exhaustive]
+pub enum ParseBeelineHeaderError {
+    /// The header value uses a version other than `1`.
+    UnsupportedVersion,
+    /// The header value does not have a `trace_id` field holding a hex or uuid id.
+    InvalidTraceId,
+    /// The header value does not have a `parent_id` field holding a hex or uuid id.
+    InvalidParentId,
+    /// The `context` field is not a base64-encoded JSON object.
+    InvalidContext,
+    /// The `dataset` field is not a valid URL-encoded string.
+    InvalidDataset,
+}
+
+impl FromStr for BeelineHeader {
+    type Err = ParseBeelineHeaderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut iter = s.trim().splitn(2, ';');
+        if iter.next() != Some(SUPPORTED_VERSION) {
+            return Err(ParseBeelineHeaderError::UnsupportedVersion);
+        }
+
+        let mut trace_id = None;
+        let mut parent_id = None;
+        let mut dataset = None;
+        let mut context = HashMap::new();
+
+        // unknown keys are ignored, as other beelines do
+        let fields = iter.next().unwrap_or_default().split(',');
+        let key_values = fields.filter_map(|field| {
+            let mut key_value = field.splitn(2, '=');
+            Some((key_value.next()?, key_value.next()?))
+        });
+        for (key, value) in key_values {
+            match key {
+                "trace_id" => trace_id = TraceId::from_beeline_id(value),
+                "parent_id" => parent_id = SpanId::from_beeline_id(value),
+                "dataset" => dataset = Some(decode_dataset(value)?),
+                "context" => context = decode_context(value)?,
+                _ => {}
+            }
+        }
+
+        Ok(BeelineHeader {
+            trace_id: trace_id.ok_or(ParseBeelineHeaderError::InvalidTraceId)?,
+            parent_id: parent_id.ok_or(ParseBeelineHeaderError::InvalidParentId)?,
+            dataset,
+            context,
+        })
+    }
+}
+
+impl std::fmt::Display for BeelineHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{};trace_id={},parent_id={}",
+            SUPPORTED_VERSION,
+            self.trace_id.to_beeline_id(),
+            self.parent_id.to_beeline_id()
+        )?;
+        if let Some(dataset) = &self.dataset {
+            write!(f, ",dataset={}", encode_dataset(dataset))?;
+        }
+        if !self.context.is_empty() {
+            write!(f, ",context={}", encode_context(&self.context))?;
+        }
+        Ok(())
+    }
+}
+
+// datasets are encoded as by go's `url.QueryEscape`, as done by the go beeline
+fn encode_dataset(dataset: &str) -> String {
+    let mut res = String::with_capacity(dataset.len());
+    for b in dataset.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                res.push(b as char)
+            }
+            b' ' => res.push('+'),
+            b => res.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    res
+}
+
+fn decode_dataset(s: &str) -> Result<String, ParseBeelineHeaderError> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [iter.next(), iter.next()];
+                let hex = match hex {
+                    [Some(hi), Some(lo)] => std::str::from_utf8(&[hi, lo])
+                        .ok()
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+                    _ => None,
+                };
+                bytes.push(hex.ok_or(ParseBeelineHeaderError::InvalidDataset)?);
+            }
+            b => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| ParseBeelineHeaderError::InvalidDataset)
+}
+
+fn decode_context(s: &str) -> Result<HashMap<String, Value>, ParseBeelineHeaderError> {
+    let json = base64::decode(s).map_err(|_| ParseBeelineHeaderError::InvalidContext)?;
+    serde_json::from_slice(&json).map_err(|_| ParseBeelineHeaderError::InvalidContext)
+}
+
+fn encode_context(context: &HashMap<String, Value>) -> String {
+    // serializing a map of json values can't fail
+    let json = serde_json::to_vec(context).expect("context serialization failed");
+    base64::encode(json)
+}
+
+/// `Propagator` using the `X-Honeycomb-Trace` header, for interoperability with honeycomb beelines.
+///
+/// Beeline `context` fields are propagated as baggage, with non-string values represented as JSON.
+/// The beeline `dataset` field is ignored.
+#[derive(Default, Debug, Clone, Copy)]
+pub struct BeelinePropagator;
+
+impl Propagator<SpanId, TraceId> for BeelinePropagator {
+    fn inject(&self, ctx: &RemoteTraceCtx<SpanId, TraceId>, carrier: &mut dyn Carrier) {
+        let header = BeelineHeader {
+            trace_id: ctx.trace_id,
+            parent_id: ctx.span_id.clone(),
+            dataset: None,
+            context: ctx
+                .baggage
+                .iter()
+                .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+                .collect(),
+        };
+        carrier.set(BEELINE_HEADER, header.to_string());
+    }
+
+    fn extract(&self, carrier: &dyn Carrier) -> Option<RemoteTraceCtx<SpanId, TraceId>> {
+        let header = BeelineHeader::from_str(carrier.get(BEELINE_HEADER)?).ok()?;
+        let baggage: Baggage = header
+            .context
+            .into_iter()
+            .map(|(k, v)| match v {
+                Value::String(s) => (k, s),
+                v => (k, v.to_string()),
+            })
+            .collect();
+
+        Some(RemoteTraceCtx {
+            baggage,
+            ..RemoteTraceCtx::new(header.trace_id, header.parent_id)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use libhoney::json;
+
+    #[test]
+    fn parse_go_beeline_header() {
+        let s = "1;trace_id=4bf92f3577b34da6a3ce929d0e0e4736,parent_id=00f067aa0ba902b7,dataset=my-dataset,context=eyJ1c2VyX2lkIjo0Mn0=";
+        let header = BeelineHeader::from_str(s).unwrap();
+
+        assert_eq!(
+            header.trace_id.to_beeline_id(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert_eq!(header.parent_id.to_beeline_id(), "00f067aa0ba902b7");
+        assert_eq!(header.dataset, Some("my-dataset".to_string()));
+        assert_eq!(header.context.get("user_id"), Some(&json!(42)));
+        assert_eq!(BeelineHeader::from_str(&header.to_string()), Ok(header));
+    }
+
+    #[test]
+    fn dataset_is_url_encoded() {
+        let header = BeelineHeader {
+            trace_id: TraceId::generate(),
+            parent_id: SpanId::new(tracing::Id::from_u64(1), 2),
+            dataset: Some("my dataset,env=prod;100%".to_string()),
+            context: HashMap::new(),
+        };
+        let s = header.to_string();
+        assert!(
+            s.ends_with(",dataset=my+dataset%2Cenv%3Dprod%3B100%25"),
+            "{}",
+            s
+        );
+        assert_eq!(BeelineHeader::from_str(&s).unwrap().dataset, header.dataset);
+    }
+
+    #[test]
+    fn parse_uuid_ids() {
+        let s = "1;parent_id=a3ce929d-0e0e-4736-8e3a-53d0b0a2b1c4,trace_id=4bf92f35-77b3-4da6-a3ce-929d0e0e4736";
+        let header = BeelineHeader::from_str(s).unwrap();
+
+        assert_eq!(
+            header.trace_id.to_beeline_id(),
+            "4bf92f35-77b3-4da6-a3ce-929d0e0e4736"
+        );
+        assert_eq!(
+            header.parent_id.to_beeline_id(),
+            "a3ce929d-0e0e-4736-8e3a-53d0b0a2b1c4"
+        );
+        assert_eq!(header.dataset, None);
+        assert!(header.context.is_empty());
+    }
+
+    #[test]
+    fn reject_invalid_headers() {
+        let cases = [
+            (
+                "2;trace_id=abc,parent_id=def",
+                ParseBeelineHeaderError::UnsupportedVersion,
+            ),
+            ("1;parent_id=def", ParseBeelineHeaderError::InvalidTraceId),
+            (
+                "1;trace_id=xyz,parent_id=def",
+                ParseBeelineHeaderError::InvalidTraceId,
+            ),
+            ("1;trace_id=abc", ParseBeelineHeaderError::InvalidParentId),
+            (
+                "1;trace_id=abc,parent_id=def,context=!!",
+                ParseBeelineHeaderError::InvalidContext,
+            ),
+            (
+                "1;trace_id=abc,parent_id=def,dataset=a%2",
+                ParseBeelineHeaderError::InvalidDataset,
+            ),
+        ];
+
+        for (header, err) in cases.iter() {
+            assert_eq!(BeelineHeader::from_str(header), Err(*err), "{}", header);
+        }
+    }
+
+    #[test]
+    fn propagator_round_trip() {
+        let mut carrier = HashMap::new();
+        let ctx = RemoteTraceCtx {
+            baggage: vec![("tenant", "acme")].into_iter().collect(),
+            ..RemoteTraceCtx::new(
+                TraceId::generate(),
+                SpanId::new(tracing::Id::from_u64(1), 2),
+            )
+        };
+        BeelinePropagator.inject(&ctx, &mut carrier);
+
+        let extracted = BeelinePropagator.extract(&carrier).unwrap();
+        assert_eq!(extracted.trace_id, ctx.trace_id);
+        assert_eq!(
+            extracted.span_id.to_beeline_id(),
+            ctx.span_id.to_beeline_id()
+        );
+        assert_eq!(extracted.baggage, ctx.baggage);
+    }
+
+    #[test]
+    fn context_is_extracted_as_baggage() {
+        let mut carrier = HashMap::new();
+        carrier.insert(
+            BEELINE_HEADER.to_string(),
+            "1;trace_id=abc,parent_id=def,context=eyJ1c2VyX2lkIjo0MiwidGVuYW50IjoiYWNtZSJ9"
+                .to_string(),
+        );
+
+        let extracted = BeelinePropagator.extract(&carrier).unwrap();
+        assert_eq!(extracted.baggage.get("user_id"), Some("42"));
+        assert_eq!(extracted.baggage.get("tenant"), Some("acme"));
+    }
+}
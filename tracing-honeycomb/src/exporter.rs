@@ -0,0 +1,500 @@
+use crate::visitor::data_loss_to_values;
+use crossbeam_channel::{select, Receiver, RecvTimeoutError, Sender, TrySendError};
+use libhoney::{FieldHolder, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing_distributed::{BackendStats, DataLossReporter, FlushError};
+
+/// What `HoneycombTelemetry` does with spans and events reported while its export queue is full.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub enum OverflowPolicy {
+    /// Drop the span or event being reported.
+    DropNewest,
+    /// Drop the oldest queued span or event to make room for the one being reported.
+    DropOldest,
+    /// Block the reporting thread until there is room in the queue.
+    Block,
+}
+
+/// Configuration for the bounded queue through which `HoneycombTelemetry` hands spans and events
+/// to the background worker that sends them to honeycomb.io.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub struct ExporterConfig {
+    /// Maximum number of spans and events queued at once.
+    pub queue_capacity: usize,
+    /// What to do with spans and events reported while the queue is full.
+    pub overflow_policy: OverflowPolicy,
+    /// How often to publish a `tracing.dropped_events` record counting the spans and events lost
+    /// since the previous one, if any were. No such records are published if `None`.
+    pub data_loss_interval: Option<Duration>,
+}
+
+impl Default for ExporterConfig {
+    /// Queue up to 10,000 spans and events, dropping new ones while the queue is full. Data loss is
+    /// not reported.
+    fn default() -> Self {
+        ExporterConfig {
+            queue_capacity: 10_000,
+            overflow_policy: OverflowPolicy::DropNewest,
+            data_loss_interval: None,
+        }
+    }
+}
+
+// fields of a span or event to publish, along with its sample rate
+pub(crate) struct QueuedEvent {
+    data: HashMap<String, Value>,
+    sample_rate: u64,
+}
+
+// requests handled by the worker once all data queued before them has been sent, along with a
+// channel on which to reply once the libhoney client has been flushed or closed
+enum Control {
+    Flush(Sender<libhoney::Result<()>>),
+    Shutdown(Sender<libhoney::Result<()>>),
+}
+
+// hands data off to a worker thread, which owns the libhoney client and is the only thread that
+// builds and sends libhoney events
+pub(crate) struct Exporter {
+    sender: Sender<QueuedEvent>,
+    // used to evict the oldest queued data, only present if the overflow policy is `DropOldest`
+    evict: Option<Receiver<QueuedEvent>>,
+    // unbounded, so that flush and shutdown requests are never dropped by the overflow policy
+    control: Sender<Control>,
+    overflow_policy: OverflowPolicy,
+    shut_down: AtomicBool,
+    // shared with the worker, which counts failures reported by libhoney
+    counters: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    dropped_buffer_full: AtomicU64,
+    dropped_shut_down: AtomicU64,
+    export_errors: AtomicU64,
+}
+
+impl Counters {
+    fn snapshot(&self) -> BackendStats {
+        BackendStats {
+            dropped_buffer_full: self.dropped_buffer_full.load(Ordering::Relaxed),
+            dropped_shut_down: self.dropped_shut_down.load(Ordering::Relaxed),
+            export_errors: self.export_errors.load(Ordering::Relaxed),
+            ..BackendStats::default()
+        }
+    }
+}
+
+// publishes records describing the data lost by the exporter to honeycomb.io, from the worker
+struct DataLossRecords {
+    interval: Duration,
+    reporter: DataLossReporter,
+    service_name: Arc<str>,
+}
+
+impl DataLossRecords {
+    fn new(interval: Duration, service_name: Arc<str>) -> Self {
+        DataLossRecords {
+            interval,
+            reporter: DataLossReporter::new(interval),
+            service_name,
+        }
+    }
+
+    // record to send if data was lost since the previous one, and it's time to send another
+    fn poll(&self, counters: &Counters) -> Option<QueuedEvent> {
+        let loss = self.reporter.poll(|| counters.snapshot())?;
+        Some(QueuedEvent {
+            data: data_loss_to_values(loss, &self.service_name),
+            sample_rate: 1,
+        })
+    }
+}
+
+impl Exporter {
+    pub(crate) fn new<T>(
+        client: libhoney::Client<T>,
+        config: ExporterConfig,
+        service_name: Arc<str>,
+    ) -> Self
+    where
+        T: 'static + libhoney::Sender + Send,
+    {
+        let (exporter, receiver, control) = Exporter::unstarted(config);
+        let counters = exporter.counters.clone();
+        let data_loss = config
+            .data_loss_interval
+            .map(|interval| DataLossRecords::new(interval, service_name));
+        std::thread::Builder::new()
+            .name("honeycomb-exporter".to_string())
+            .spawn(move || {
+                let (client, shutdown) =
+                    run_worker(client, receiver, control, &counters, data_loss.as_ref());
+                let res = client.close();
+                match shutdown {
+                    Some(reply) => {
+                        let _ = reply.send(res);
+                    }
+                    None => {
+                        if let Err(err) = res {
+                            eprintln!("error closing honeycomb client, {:?}", err);
+                        }
+                    }
+                }
+            })
+            .expect("failed to spawn honeycomb exporter thread");
+        exporter
+    }
+
+    // exporter with no worker consuming its queue
+    fn unstarted(config: ExporterConfig) -> (Self, Receiver<QueuedEvent>, Receiver<Control>) {
+        // a zero-capacity channel would block until the worker receives each item
+        let (sender, receiver) = crossbeam_channel::bounded(config.queue_capacity.max(1));
+        let (control, control_receiver) = crossbeam_channel::unbounded();
+        let evict = match config.overflow_policy {
+            OverflowPolicy::DropOldest => Some(receiver.clone()),
+            _ => None,
+        };
+        let exporter = Exporter {
+            sender,
+            evict,
+            control,
+            overflow_policy: config.overflow_policy,
+            shut_down: AtomicBool::new(false),
+            counters: Arc::new(Counters::default()),
+        };
+        (exporter, receiver, control_receiver)
+    }
+
+    pub(crate) fn export(&self, data: HashMap<String, Value>, sample_rate: u64) {
+        if self.shut_down.load(Ordering::Relaxed) {
+            self.counters
+                .dropped_shut_down
+                .fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        let mut queued = QueuedEvent { data, sample_rate };
+        let sent = match self.overflow_policy {
+            // only fails if the worker is gone
+            OverflowPolicy::Block => self.sender.send(queued).is_ok(),
+            OverflowPolicy::DropNewest => self.sender.try_send(queued).is_ok(),
+            OverflowPolicy::DropOldest => loop {
+                match self.sender.try_send(queued) {
+                    Ok(()) => break true,
+                    Err(TrySendError::Full(rejected)) => {
+                        // the worker may have made room in the meantime, in which case nothing is evicted
+                        if let Some(Ok(_)) = self.evict.as_ref().map(Receiver::try_recv) {
+                            self.counters
+                                .dropped_buffer_full
+                                .fetch_add(1, Ordering::Relaxed);
+                        }
+                        queued = rejected;
+                    }
+                    Err(TrySendError::Disconnected(_)) => break false,
+                }
+            },
+        };
+        // the worker is only gone once shut down
+        if !sent {
+            self.counters
+                .dropped_buffer_full
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn dropped_count(&self) -> u64 {
+        let stats = self.stats();
+        stats.dropped_buffer_full + stats.dropped_shut_down
+    }
+
+    pub(crate) fn stats(&self) -> BackendStats {
+        BackendStats {
+            pending: self.sender.len(),
+            ..self.counters.snapshot()
+        }
+    }
+
+    // waits for the worker to send all data queued so far and flush the libhoney client
+    pub(crate) fn flush(&self, timeout: Duration) -> Result<(), FlushError> {
+        self.request(Control::Flush, timeout)
+    }
+
+    // waits for the worker to send all data queued so far and close the libhoney client, after
+    // which any further data is dropped
+    pub(crate) fn shutdown(&self, timeout: Duration) -> Result<(), FlushError> {
+        if self.shut_down.swap(true, Ordering::Relaxed) {
+            return Err(FlushError::ShutDown);
+        }
+        self.request(Control::Shutdown, timeout)
+    }
+
+    fn request<F>(&self, mk_control: F, timeout: Duration) -> Result<(), FlushError>
+    where
+        F: FnOnce(Sender<libhoney::Result<()>>) -> Control,
+    {
+        let (reply, response) = crossbeam_channel::bounded(1);
+        self.control
+            .send(mk_control(reply))
+            .map_err(|_| FlushError::ShutDown)?;
+        match response.recv_timeout(timeout) {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(err)) => Err(FlushError::Backend(err.message)),
+            Err(RecvTimeoutError::Timeout) => Err(FlushError::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(FlushError::ShutDown),
+        }
+    }
+}
+
+impl std::fmt::Debug for Exporter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Exporter")
+            .field("queued", &self.sender.len())
+            .field("capacity", &self.sender.capacity())
+            .field("overflow_policy", &self.overflow_policy)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+// sends queued data until the exporter is dropped or shut down, then returns the client along
+// with the channel on which to reply to the shutdown request, if any. data loss is reported
+// whenever the worker is able to send data, and periodically in case no more data is queued
+fn run_worker<T: libhoney::Sender>(
+    mut client: libhoney::Client<T>,
+    receiver: Receiver<QueuedEvent>,
+    control: Receiver<Control>,
+    counters: &Counters,
+    data_loss: Option<&DataLossRecords>,
+) -> (libhoney::Client<T>, Option<Sender<libhoney::Result<()>>>) {
+    // libhoney blocks once its bounded response channel is full, so responses must be consumed
+    let mut responses = client.responses();
+    let ticks = match data_loss {
+        Some(data_loss) => crossbeam_channel::tick(data_loss.interval),
+        None => crossbeam_channel::never(),
+    };
+    let report_data_loss = |client: &mut libhoney::Client<T>| {
+        if let Some(record) = data_loss.and_then(|data_loss| data_loss.poll(counters)) {
+            send(client, record, counters);
+        }
+    };
+    loop {
+        select! {
+            recv(ticks) -> _ => report_data_loss(&mut client),
+            recv(responses) -> response => match response {
+                Ok(response) => {
+                    let accepted = response.status_code.map_or(true, |status| status.is_success());
+                    count_failure(counters, response.error.as_deref(), accepted);
+                }
+                // no more responses will be sent
+                Err(_) => responses = crossbeam_channel::never(),
+            },
+            recv(receiver) -> queued => match queued {
+                Ok(queued) => {
+                    send(&mut client, queued, counters);
+                    report_data_loss(&mut client);
+                }
+                Err(_) => return (client, None),
+            },
+            recv(control) -> request => {
+                // data queued before the request was made must be sent first
+                for queued in receiver.try_iter() {
+                    send(&mut client, queued, counters);
+                }
+                report_data_loss(&mut client);
+                match request {
+                    Ok(Control::Flush(reply)) => {
+                        let _ = reply.send(client.flush());
+                    }
+                    Ok(Control::Shutdown(reply)) => return (client, Some(reply)),
+                    Err(_) => return (client, None),
+                }
+            }
+        }
+    }
+}
+
+// counts events libhoney failed to deliver, either because its own queue was full or due to
+// some error returned by honeycomb.io
+fn count_failure(counters: &Counters, error: Option<&str>, accepted: bool) {
+    let counter = match error {
+        Some("queue overflow") => &counters.dropped_buffer_full,
+        Some(_) => &counters.export_errors,
+        None if !accepted => &counters.export_errors,
+        None => return,
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+fn send<T: libhoney::Sender>(
+    client: &mut libhoney::Client<T>,
+    queued: QueuedEvent,
+    counters: &Counters,
+) {
+    let QueuedEvent { data, sample_rate } = queued;
+    let mut ev = client.new_event();
+    ev.add(data);
+    // data sampled by this crate sets `SampleRate` itself, bypassing libhoney's own sampling
+    let res = if sample_rate > 1 {
+        ev.set_sample_rate(sample_rate as usize);
+        ev.send_presampled(client)
+    } else {
+        ev.send(client)
+    };
+    // reported to honeycomb.io itself by the next data loss record, if enabled
+    if res.is_err() {
+        counters.export_errors.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libhoney::json;
+
+    fn data(n: u64) -> HashMap<String, Value> {
+        vec![("n".to_string(), json!(n))].into_iter().collect()
+    }
+
+    fn queued(receiver: &Receiver<QueuedEvent>) -> Vec<Value> {
+        receiver.try_iter().map(|q| q.data["n"].clone()).collect()
+    }
+
+    fn config(overflow_policy: OverflowPolicy) -> ExporterConfig {
+        ExporterConfig {
+            queue_capacity: 2,
+            overflow_policy,
+            ..ExporterConfig::default()
+        }
+    }
+
+    #[test]
+    fn overflow_policies() {
+        let (exporter, receiver, _) = Exporter::unstarted(config(OverflowPolicy::DropNewest));
+        for n in 0..5 {
+            exporter.export(data(n), 1);
+        }
+        assert_eq!(queued(&receiver), vec![json!(0), json!(1)]);
+        assert_eq!(exporter.dropped_count(), 3);
+
+        let (exporter, receiver, _) = Exporter::unstarted(config(OverflowPolicy::DropOldest));
+        for n in 0..5 {
+            exporter.export(data(n), 1);
+        }
+        assert_eq!(queued(&receiver), vec![json!(3), json!(4)]);
+        assert_eq!(exporter.dropped_count(), 3);
+
+        let (exporter, receiver, _) = Exporter::unstarted(config(OverflowPolicy::Block));
+        let consumer = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            receiver
+                .iter()
+                .map(|q| q.data["n"].clone())
+                .collect::<Vec<_>>()
+        });
+        for n in 0..5 {
+            exporter.export(data(n), 1);
+        }
+        assert_eq!(exporter.dropped_count(), 0);
+        drop(exporter);
+        assert_eq!(
+            consumer.join().unwrap(),
+            (0..5).map(|n| json!(n)).collect::<Vec<_>>()
+        );
+    }
+
+    fn mock_client() -> libhoney::Client<libhoney::mock::TransmissionMock> {
+        libhoney::test::init(libhoney::Config {
+            options: libhoney::client::Options {
+                api_key: "api_key".to_string(),
+                ..libhoney::client::Options::default()
+            },
+            transmission_options: libhoney::transmission::Options::default(),
+        })
+    }
+
+    #[test]
+    fn worker_flushes_and_shuts_down() {
+        let (exporter, receiver, control) = Exporter::unstarted(ExporterConfig::default());
+        let client = mock_client();
+        let worker = std::thread::spawn(move || {
+            let counters = Counters::default();
+            let (mut client, shutdown) = run_worker(client, receiver, control, &counters, None);
+            let _ = shutdown.expect("worker was shut down").send(Ok(()));
+            client.transmission.events()
+        });
+
+        let timeout = Duration::from_secs(1);
+        exporter.export(data(0), 1);
+        exporter.export(data(1), 10);
+        assert_eq!(exporter.flush(timeout), Ok(()));
+        exporter.export(data(2), 1);
+        assert_eq!(exporter.shutdown(timeout), Ok(()));
+
+        let events = worker.join().unwrap();
+        let sent: Vec<Value> = events.iter().map(|ev| ev.fields()["n"].clone()).collect();
+        assert_eq!(sent, vec![json!(0), json!(1), json!(2)]);
+
+        // data reported after shutdown is dropped
+        exporter.export(data(3), 1);
+        assert_eq!(exporter.dropped_count(), 1);
+        assert_eq!(exporter.flush(timeout), Err(FlushError::ShutDown));
+        assert_eq!(exporter.shutdown(timeout), Err(FlushError::ShutDown));
+    }
+
+    #[test]
+    fn worker_reports_data_loss() {
+        let (exporter, receiver, control) = Exporter::unstarted(ExporterConfig::default());
+        let client = mock_client();
+        let counters = exporter.counters.clone();
+        counters.dropped_buffer_full.store(2, Ordering::Relaxed);
+        let data_loss = DataLossRecords::new(Duration::from_millis(1), "test_svc_name".into());
+        std::thread::sleep(Duration::from_millis(5));
+        let worker = std::thread::spawn(move || {
+            let (mut client, shutdown) =
+                run_worker(client, receiver, control, &counters, Some(&data_loss));
+            let _ = shutdown.expect("worker was shut down").send(Ok(()));
+            client.transmission.events()
+        });
+
+        exporter.export(data(0), 1);
+        assert_eq!(exporter.shutdown(Duration::from_secs(1)), Ok(()));
+
+        let events = worker.join().unwrap();
+        // the record may be sent on a tick before the queued data
+        let (records, sent): (Vec<_>, Vec<_>) = events
+            .iter()
+            .map(|ev| ev.fields())
+            .partition(|fields| fields.contains_key("dropped.total"));
+        assert_eq!(sent.len(), 1);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["name"], json!("tracing.dropped_events"));
+        assert_eq!(records[0]["service_name"], json!("test_svc_name"));
+        assert_eq!(records[0]["dropped.buffer_full"], json!(2));
+        assert_eq!(records[0]["dropped.total"], json!(2));
+    }
+
+    #[test]
+    fn counts_failures() {
+        let counters = Counters::default();
+        count_failure(&counters, None, true);
+        count_failure(&counters, Some("queue overflow"), true);
+        count_failure(&counters, Some("timed out"), true);
+        count_failure(&counters, None, false);
+        assert_eq!(counters.dropped_buffer_full.load(Ordering::Relaxed), 1);
+        assert_eq!(counters.export_errors.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn flush_times_out() {
+        let (exporter, _receiver, _control) = Exporter::unstarted(ExporterConfig::default());
+        exporter.export(data(0), 1);
+        assert_eq!(
+            exporter.flush(Duration::from_millis(10)),
+            Err(FlushError::Timeout)
+        );
+    }
+}
@@ -1,57 +1,66 @@
-use crate::visitor::{event_to_values, span_to_values, HoneycombVisitor};
-use libhoney::FieldHolder;
+use crate::exporter::{Exporter, ExporterConfig};
+use crate::sampling::DynamicSampler;
+use crate::visitor::{event_to_values, span_links_to_values, span_to_values, HoneycombVisitor};
 use std::collections::HashMap;
 use std::str::FromStr;
-use tracing_distributed::{Event, Span, Telemetry};
-
-#[cfg(feature = "use_parking_lot")]
-use parking_lot::Mutex;
-#[cfg(not(feature = "use_parking_lot"))]
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing_distributed::{BackendStats, Event, FlushError, IdCodec, Span, Telemetry};
 
 /// Telemetry capability that publishes events and spans to Honeycomb.io.
+///
+/// Spans and events are handed to a background worker via a bounded queue, so that reporting
+/// threads never contend on the libhoney client. See `ExporterConfig` for what happens when the
+/// queue is full.
 #[derive(Debug)]
 pub struct HoneycombTelemetry {
-    honeycomb_client: Mutex<libhoney::Client<libhoney::transmission::Transmission>>,
-    sample_rate: Option<u128>,
+    exporter: Exporter,
+    dynamic_sampler: Option<DynamicSampler>,
+    // spans, links and events dropped by the dynamic sampler
+    dropped_sampling: AtomicU64,
 }
 
 impl HoneycombTelemetry {
-    pub(crate) fn new(cfg: libhoney::Config, sample_rate: Option<u128>) -> Self {
+    pub(crate) fn new(
+        service_name: Arc<str>,
+        cfg: libhoney::Config,
+        exporter_config: ExporterConfig,
+        dynamic_sampler: Option<DynamicSampler>,
+    ) -> Self {
         let honeycomb_client = libhoney::init(cfg);
 
-        // publishing requires &mut so just mutex-wrap it
-        // FIXME: may not be performant, investigate options (eg mpsc)
-        let honeycomb_client = Mutex::new(honeycomb_client);
-
         HoneycombTelemetry {
-            honeycomb_client,
-            sample_rate,
+            exporter: Exporter::new(honeycomb_client, exporter_config, service_name),
+            dynamic_sampler,
+            dropped_sampling: AtomicU64::new(0),
         }
     }
 
-    fn report_data(&self, data: HashMap<String, ::libhoney::Value>) {
-        // succeed or die. failure is unrecoverable (mutex poisoned)
-        #[cfg(not(feature = "use_parking_lot"))]
-        let mut client = self.honeycomb_client.lock().unwrap();
-        #[cfg(feature = "use_parking_lot")]
-        let mut client = self.honeycomb_client.lock();
+    /// Number of spans and events (including span links) dropped because the export queue was full
+    /// or they were reported after shutdown. See `Telemetry::stats` for a breakdown.
+    pub fn dropped_count(&self) -> u64 {
+        self.exporter.dropped_count()
+    }
 
-        let mut ev = client.new_event();
-        ev.add(data);
-        let res = ev.send(&mut client);
-        if let Err(err) = res {
-            // unable to report telemetry (buffer full) so log msg to stderr
-            // TODO: figure out strategy for handling this (eg report data loss event)
-            eprintln!("error sending event to honeycomb, {:?}", err);
+    // applies dynamic sampling (if configured) on top of the trace-level sample rate, returning
+    // the overall sample rate of the data if it's kept
+    fn sample(
+        &self,
+        trace_id: TraceId,
+        trace_sample_rate: u64,
+        data: &HashMap<String, ::libhoney::Value>,
+    ) -> Option<u64> {
+        match &self.dynamic_sampler {
+            Some(dynamic_sampler) => dynamic_sampler
+                .sample(trace_id, data)
+                .map(|sample_rate| sample_rate.saturating_mul(trace_sample_rate)),
+            None => Some(trace_sample_rate),
         }
     }
 
-    fn should_report(&self, trace_id: TraceId) -> bool {
-        match self.sample_rate {
-            Some(sample_rate) => trace_id.0 % sample_rate == 0,
-            None => true,
-        }
+    fn report_data(&self, data: HashMap<String, ::libhoney::Value>, sample_rate: u64) {
+        self.exporter.export(data, sample_rate);
     }
 }
 
@@ -65,29 +74,89 @@ impl Telemetry for HoneycombTelemetry {
     }
 
     fn report_span(&self, span: Span<Self::Visitor, Self::SpanId, Self::TraceId>) {
-        if self.should_report(span.trace_id) {
-            let data = span_to_values(span);
-            self.report_data(data);
+        let (trace_id, trace_sample_rate) = (span.trace_id, span.sample_rate);
+        let links = span_links_to_values(&span);
+        let data = span_to_values(span);
+        // links share the sampling decision of the span they belong to
+        if let Some(sample_rate) = self.sample(trace_id, trace_sample_rate, &data) {
+            for link in links {
+                self.report_data(link, sample_rate);
+            }
+            self.report_data(data, sample_rate);
+        } else {
+            self.dropped_sampling
+                .fetch_add(1 + links.len() as u64, Ordering::Relaxed);
         }
     }
 
     fn report_event(&self, event: Event<Self::Visitor, Self::SpanId, Self::TraceId>) {
-        if self.should_report(event.trace_id) {
-            let data = event_to_values(event);
-            self.report_data(data);
+        let (trace_id, trace_sample_rate) = (event.trace_id, event.sample_rate);
+        let data = event_to_values(event);
+        if let Some(sample_rate) = self.sample(trace_id, trace_sample_rate, &data) {
+            self.report_data(data, sample_rate);
+        } else {
+            self.dropped_sampling.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn flush(&self, timeout: Duration) -> Result<(), FlushError> {
+        self.exporter.flush(timeout)
+    }
+
+    fn shutdown(&self, timeout: Duration) -> Result<(), FlushError> {
+        self.exporter.shutdown(timeout)
+    }
+
+    fn stats(&self) -> BackendStats {
+        BackendStats {
+            dropped_sampling: self.dropped_sampling.load(Ordering::Relaxed),
+            ..self.exporter.stats()
         }
     }
 }
 
+impl IdCodec for HoneycombTelemetry {
+    type TraceId = TraceId;
+    type SpanId = SpanId;
+
+    fn encode_trace_id(trace_id: &TraceId) -> u128 {
+        trace_id.id
+    }
+
+    fn decode_trace_id(trace_id: u128) -> TraceId {
+        TraceId::from_u128(trace_id)
+    }
+
+    fn encode_span_id(span_id: &SpanId) -> u64 {
+        span_id.to_u64()
+    }
+
+    fn decode_span_id(span_id: u64) -> SpanId {
+        SpanId::remote(format!("{:016x}", span_id))
+    }
+}
+
 /// Unique Span identifier.
 ///
-/// Combines a span's `tracing::Id` with an instance identifier to avoid id collisions in distributed scenarios.
+/// Spans observed by this process combine a span's `tracing::Id` with an instance identifier to avoid
+/// id collisions in distributed scenarios, and are published to honeycomb.io as `span-{tracing id}-{instance id}`.
+/// Span ids received from other processes (eg via W3C trace context or from honeycomb beelines) are kept
+/// verbatim, so that they are published exactly as the sender published them.
 ///
 /// `Display` and `FromStr` are guaranteed to round-trip.
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub struct SpanId {
-    pub(crate) tracing_id: tracing::Id,
-    pub(crate) instance_id: u64,
+    repr: SpanIdRepr,
+}
+
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+enum SpanIdRepr {
+    Local {
+        tracing_id: tracing::Id,
+        instance_id: u64,
+    },
+    // hex string or uuid, as received from some other process
+    Remote(String),
 }
 
 impl SpanId {
@@ -95,6 +164,62 @@ impl SpanId {
     pub fn meta_field_name() -> &'static str {
         "span-id"
     }
+
+    pub(crate) fn new(tracing_id: tracing::Id, instance_id: u64) -> Self {
+        SpanId {
+            repr: SpanIdRepr::Local {
+                tracing_id,
+                instance_id,
+            },
+        }
+    }
+
+    pub(crate) fn remote(id: String) -> Self {
+        SpanId {
+            repr: SpanIdRepr::Remote(id),
+        }
+    }
+
+    /// Parse a span id as published by a honeycomb beeline: a hex string or a uuid.
+    pub fn from_beeline_id(s: &str) -> Option<Self> {
+        if parse_beeline_id(s).is_some() {
+            Some(SpanId::remote(s.to_string()))
+        } else {
+            None
+        }
+    }
+
+    /// This span id as propagated to honeycomb beelines: a 64-bit hex value, or the span id as received
+    /// from some other process.
+    pub fn to_beeline_id(&self) -> String {
+        match &self.repr {
+            SpanIdRepr::Local { .. } => format!("{:016x}", self.to_u64()),
+            SpanIdRepr::Remote(id) => id.clone(),
+        }
+    }
+
+    // representation published to honeycomb.io
+    pub(crate) fn to_published_id(&self) -> String {
+        match &self.repr {
+            SpanIdRepr::Local { .. } => format!("span-{}", self),
+            SpanIdRepr::Remote(id) => id.clone(),
+        }
+    }
+
+    // 64-bit representation, folding the instance id in keeps ids from different processes distinct
+    pub(crate) fn to_u64(&self) -> u64 {
+        match &self.repr {
+            SpanIdRepr::Local {
+                tracing_id,
+                instance_id,
+            } => tracing_id.into_u64() ^ instance_id,
+            // lossy for uuids, which are wider than the span ids used by other propagation formats
+            SpanIdRepr::Remote(id) => {
+                let u = parse_beeline_id(id).unwrap_or_default();
+                (u >> 64) as u64 ^ u as u64
+            }
+        }
+    }
 }
 
 #[derive(PartialEq, Eq, Clone, Debug)]
@@ -107,32 +232,65 @@ impl FromStr for SpanId {
     type Err = ParseSpanIdError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut iter = s.split('-');
-        let s1 = iter.next().ok_or(ParseSpanIdError::FormatError)?;
-        let u1 = u64::from_str_radix(s1, 10).map_err(ParseSpanIdError::ParseIntError)?;
-        let s2 = iter.next().ok_or(ParseSpanIdError::FormatError)?;
-        let u2 = u64::from_str_radix(s2, 10).map_err(ParseSpanIdError::ParseIntError)?;
-
-        Ok(SpanId {
-            tracing_id: tracing::Id::from_u64(u1),
-            instance_id: u2,
-        })
+        parse_local_span_id(s).or_else(|e| SpanId::from_beeline_id(s).ok_or(e))
     }
 }
 
+fn parse_local_span_id(s: &str) -> Result<SpanId, ParseSpanIdError> {
+    let mut iter = s.split('-');
+    let s1 = iter.next().ok_or(ParseSpanIdError::FormatError)?;
+    let u1 = s1.parse::<u64>().map_err(ParseSpanIdError::ParseIntError)?;
+    let s2 = iter.next().ok_or(ParseSpanIdError::FormatError)?;
+    let u2 = s2.parse::<u64>().map_err(ParseSpanIdError::ParseIntError)?;
+    if iter.next().is_some() {
+        return Err(ParseSpanIdError::FormatError);
+    }
+
+    Ok(SpanId::new(tracing::Id::from_u64(u1), u2))
+}
+
 impl std::fmt::Display for SpanId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}-{}", self.tracing_id.into_u64(), self.instance_id)
+        match &self.repr {
+            SpanIdRepr::Local {
+                tracing_id,
+                instance_id,
+            } => write!(f, "{}-{}", tracing_id.into_u64(), instance_id),
+            SpanIdRepr::Remote(id) => write!(f, "{}", id),
+        }
     }
 }
 
 /// A Honeycomb Trace ID.
 ///
 /// Uniquely identifies a single distributed trace.
+/// Published to honeycomb.io in its `Display` representation: a decimal number, or a uuid if received
+/// from a honeycomb beeline that uses uuids.
+///
+/// Trace ids with the same 128-bit value are equal (and hash the same) regardless of whether they
+/// are formatted as uuids, as they identify the same trace.
 ///
 /// `Display` and `FromStr` are guaranteed to round-trip.
-#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
-pub struct TraceId(pub(crate) u128);
+#[derive(Copy, Clone, Debug)]
+pub struct TraceId {
+    id: u128,
+    // set if received from a beeline that uses uuid trace ids, to publish them in the same format
+    uuid: bool,
+}
+
+impl PartialEq for TraceId {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for TraceId {}
+
+impl std::hash::Hash for TraceId {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
 
 impl TraceId {
     /// Metadata field name associated with this `TraceId` values.
@@ -145,7 +303,44 @@ impl TraceId {
         use rand::Rng;
         let u: u128 = rand::thread_rng().gen();
 
-        TraceId(u)
+        TraceId::from_u128(u)
+    }
+
+    pub(crate) fn from_u128(id: u128) -> Self {
+        TraceId { id, uuid: false }
+    }
+
+    pub(crate) fn to_u128(self) -> u128 {
+        self.id
+    }
+
+    /// Parse a trace id as published by a honeycomb beeline: a hex string or a uuid.
+    pub fn from_beeline_id(s: &str) -> Option<Self> {
+        match parse_beeline_id(s) {
+            Some(0) | None => None,
+            Some(id) => Some(TraceId {
+                id,
+                uuid: is_uuid(s),
+            }),
+        }
+    }
+
+    /// This trace id as propagated to honeycomb beelines: a 128-bit hex value, or a uuid if received
+    /// as one.
+    pub fn to_beeline_id(self) -> String {
+        let hex = format!("{:032x}", self.id);
+        if self.uuid {
+            format!(
+                "{}-{}-{}-{}-{}",
+                &hex[0..8],
+                &hex[8..12],
+                &hex[12..16],
+                &hex[16..20],
+                &hex[20..32]
+            )
+        } else {
+            hex
+        }
     }
 }
 
@@ -153,14 +348,42 @@ impl FromStr for TraceId {
     type Err = std::num::ParseIntError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let u = u128::from_str_radix(s, 10)?;
-        Ok(Self(u))
+        if is_uuid(s) {
+            if let Some(trace_id) = TraceId::from_beeline_id(s) {
+                return Ok(trace_id);
+            }
+        }
+        let u = s.parse::<u128>()?;
+        Ok(TraceId::from_u128(u))
     }
 }
 
 impl std::fmt::Display for TraceId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        if self.uuid {
+            write!(f, "{}", self.to_beeline_id())
+        } else {
+            write!(f, "{}", self.id)
+        }
+    }
+}
+
+fn is_uuid(s: &str) -> bool {
+    s.len() == 36
+        && s.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+// beelines publish ids as hex strings of up to 128 bits or as uuids
+fn parse_beeline_id(s: &str) -> Option<u128> {
+    if is_uuid(s) {
+        u128::from_str_radix(&s.replace('-', ""), 16).ok()
+    } else if !s.is_empty() && s.len() <= 32 && s.chars().all(|c| c.is_ascii_hexdigit()) {
+        u128::from_str_radix(s, 16).ok()
+    } else {
+        None
     }
 }
 
@@ -172,21 +395,50 @@ mod test {
         #[test]
         // ua is [1..] and not [0..] because 0 is not a valid tracing::Id (tracing::from_u64 throws on 0)
         fn span_id_round_trip(ua in 1u64.., ub in 1u64..) {
-            let span_id = SpanId {
-                tracing_id: tracing::Id::from_u64(ua),
-                instance_id: ub,
-            };
+            let span_id = SpanId::new(tracing::Id::from_u64(ua), ub);
             let s = span_id.to_string();
             let res = SpanId::from_str(&s);
             assert_eq!(Ok(span_id), res);
         }
 
+        #[test]
+        fn remote_span_id_round_trip(u in 1u64..) {
+            let span_id = HoneycombTelemetry::decode_span_id(u);
+            assert_eq!(HoneycombTelemetry::encode_span_id(&span_id), u);
+            assert_eq!(span_id.to_beeline_id(), format!("{:016x}", u));
+            assert_eq!(Ok(span_id.clone()), SpanId::from_str(&span_id.to_string()));
+        }
+
         #[test]
         fn trace_id_round_trip(u in 1u128..) {
-            let trace_id = TraceId(u);
+            let trace_id = TraceId::from_u128(u);
             let s = trace_id.to_string();
             let res = TraceId::from_str(&s);
             assert_eq!(Ok(trace_id), res);
         }
+
+        #[test]
+        fn uuid_trace_id_round_trip(u in 1u128..) {
+            let hex = format!("{:032x}", u);
+            let uuid = format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32]);
+            let trace_id = TraceId::from_beeline_id(&uuid).unwrap();
+            assert_eq!(trace_id.to_beeline_id(), uuid);
+            assert_eq!(Ok(trace_id), TraceId::from_str(&trace_id.to_string()));
+            // the same trace, regardless of its format
+            assert_eq!(trace_id, TraceId::from_beeline_id(&hex).unwrap());
+        }
+    }
+
+    #[test]
+    fn published_ids() {
+        let span_id = SpanId::new(tracing::Id::from_u64(135), 246);
+        assert_eq!(span_id.to_published_id(), "span-135-246");
+        assert_eq!(
+            SpanId::from_beeline_id("00f067aa0ba902b7")
+                .unwrap()
+                .to_published_id(),
+            "00f067aa0ba902b7"
+        );
+        assert_eq!(TraceId::from_u128(135).to_string(), "135");
     }
 }
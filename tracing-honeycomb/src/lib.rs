@@ -7,18 +7,36 @@
 
 //! This crate provides:
 //! - A tracing layer, `TelemetryLayer`, that can be used to publish trace data to honeycomb.io
-//! - Utilities for implementing distributed tracing against the honeycomb.io backend
+//! - Utilities for implementing distributed tracing against the honeycomb.io backend, including
+//!   propagation of trace context to and from services instrumented with honeycomb beelines
 //!
 //! As a tracing layer, `TelemetryLayer` can be composed with other layers to provide stdout logging, filtering, etc.
 
+mod beeline;
+mod exporter;
 mod honeycomb;
+mod sampling;
 mod visitor;
 
+pub use crate::beeline::{
+    BeelineHeader, BeelinePropagator, ParseBeelineHeaderError, BEELINE_HEADER,
+};
+pub use crate::exporter::{ExporterConfig, OverflowPolicy};
 pub use crate::honeycomb::{HoneycombTelemetry, SpanId, TraceId};
+pub use crate::sampling::{DynamicSampler, TraceIdSampler};
 pub use crate::visitor::HoneycombVisitor;
 use rand::{self, Rng};
+use std::sync::Arc;
+use std::time::Duration;
 #[doc(no_inline)]
-pub use tracing_distributed::{TelemetryLayer, TraceCtxError};
+pub use tracing_distributed::{
+    AlwaysSample, B3Encoding, BackendStats, Baggage, Carrier, CompositePropagator, FanOutTelemetry,
+    FlushError, IdAdapter, IncompleteTracePolicy, NeverSample, ParentBasedSampler, Propagator,
+    RateLimitedSampler, RemoteTraceCtx, Resource, RootSpan, Sampler, SamplingAction,
+    SamplingDecision, SamplingRule, SpanKind, SpanStatus, StatusConvention, TailSamplingConfig,
+    TailSamplingRule, TailSamplingTelemetry, TelemetryLayer, TelemetryStats, TraceCtxError,
+    TraceParent, BAGGAGE_HEADER, SPAN_KIND_FIELD, TRACEPARENT_HEADER, TRACESTATE_HEADER,
+};
 
 /// Register the current span as the local root of a distributed trace.
 ///
@@ -30,6 +48,49 @@ pub fn register_dist_tracing_root(
     tracing_distributed::register_dist_tracing_root(trace_id, remote_parent_span)
 }
 
+/// Register the current span as the local root of a distributed trace, along with baggage
+/// received from the remote parent span (if any).
+///
+/// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+pub fn register_dist_tracing_root_with_baggage(
+    trace_id: TraceId,
+    remote_parent_span: Option<SpanId>,
+    baggage: Baggage,
+) -> Result<(), TraceCtxError> {
+    tracing_distributed::register_dist_tracing_root_with_baggage(
+        trace_id,
+        remote_parent_span,
+        baggage,
+    )
+}
+
+/// Retrieve the baggage associated with the current span.
+///
+/// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+pub fn current_baggage() -> Result<Baggage, TraceCtxError> {
+    tracing_distributed::current_baggage::<SpanId, TraceId>()
+}
+
+/// Set a baggage entry on the current span. The entry is visible to the current span, to spans
+/// subsequently created within it and to any process the trace context is propagated to from them.
+///
+/// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+pub fn set_baggage_item(
+    key: impl Into<String>,
+    value: impl Into<String>,
+) -> Result<(), TraceCtxError> {
+    tracing_distributed::set_baggage_item::<SpanId, TraceId>(key, value)
+}
+
+/// Override the service name reported for the current span and spans subsequently created within it,
+/// eg just after registering a distributed tracing root, so that a process hosting several logical
+/// services can report each of their traces under its own service name.
+///
+/// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+pub fn set_service_name(service_name: impl Into<Arc<str>>) -> Result<(), TraceCtxError> {
+    tracing_distributed::set_service_name::<SpanId, TraceId>(service_name)
+}
+
 /// Retrieve the distributed trace context associated with the current span.
 ///
 /// Returns the `TraceId`, if any, that the current span is associated with along with
@@ -40,6 +101,92 @@ pub fn current_dist_trace_ctx() -> Result<(TraceId, SpanId), TraceCtxError> {
     tracing_distributed::current_dist_trace_ctx()
 }
 
+/// Register the current span as the local root of a distributed trace continued from a remote
+/// parent, as described by the W3C `traceparent` and (optional) `tracestate` headers of an incoming request.
+///
+/// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+pub fn register_w3c_tracing_root(
+    traceparent: &TraceParent,
+    tracestate: Option<String>,
+) -> Result<(), TraceCtxError> {
+    tracing_distributed::register_w3c_tracing_root::<HoneycombTelemetry>(traceparent, tracestate)
+}
+
+/// Retrieve the distributed trace context associated with the current span as the values of the
+/// W3C `traceparent` and (optional) `tracestate` headers to attach to an outgoing request.
+///
+/// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+pub fn current_w3c_trace_ctx() -> Result<(TraceParent, Option<String>), TraceCtxError> {
+    tracing_distributed::current_w3c_trace_ctx::<HoneycombTelemetry>()
+}
+
+/// `Propagator` using the W3C `traceparent` and `tracestate` headers.
+///
+/// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+pub type W3CPropagator = tracing_distributed::W3CPropagator<HoneycombTelemetry>;
+
+/// `Propagator` using the B3 headers used by Zipkin and Envoy.
+///
+/// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+pub type B3Propagator = tracing_distributed::B3Propagator<HoneycombTelemetry>;
+
+/// Sampler that samples the provided ratio of traces, between 0 and 1.
+///
+/// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+pub type RatioSampler = tracing_distributed::RatioSampler<HoneycombTelemetry>;
+
+/// Sampler that applies the action of the first `SamplingRule` matching the local root span of each trace.
+///
+/// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+pub type RulesSampler = tracing_distributed::RulesSampler<HoneycombTelemetry>;
+
+/// Write the distributed trace context associated with the current span to `carrier`, using the
+/// provided `Propagator`.
+///
+/// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+pub fn inject_dist_trace_ctx(
+    propagator: &dyn Propagator<SpanId, TraceId>,
+    carrier: &mut dyn Carrier,
+) -> Result<(), TraceCtxError> {
+    tracing_distributed::inject_dist_trace_ctx(propagator, carrier)
+}
+
+/// Register the current span as the local root of a distributed trace, using trace context read
+/// from `carrier` by the provided `Propagator`.
+///
+/// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+pub fn extract_dist_tracing_root(
+    propagator: &dyn Propagator<SpanId, TraceId>,
+    carrier: &dyn Carrier,
+) -> Result<(), TraceCtxError> {
+    tracing_distributed::extract_dist_tracing_root(propagator, carrier)
+}
+
+/// Deliver all spans and events reported so far to honeycomb.io, waiting up to `timeout` for the
+/// background exporter to send them. See `TelemetryLayer::flush`.
+///
+/// Specialized to the `HoneycombTelemetry` used by the layers constructed by this crate.
+pub fn flush_dist_telemetry(timeout: Duration) -> Result<(), FlushError> {
+    tracing_distributed::flush_dist_telemetry::<HoneycombTelemetry>(timeout)
+}
+
+/// Deliver all spans and events reported so far to honeycomb.io and close the libhoney client,
+/// waiting up to `timeout`. Spans and events reported afterwards are dropped. Call this before the
+/// process exits to avoid losing data. See `TelemetryLayer::shutdown`.
+///
+/// Specialized to the `HoneycombTelemetry` used by the layers constructed by this crate.
+pub fn shutdown_dist_telemetry(timeout: Duration) -> Result<(), FlushError> {
+    tracing_distributed::shutdown_dist_telemetry::<HoneycombTelemetry>(timeout)
+}
+
+/// Retrieve the counters of the `TelemetryLayer` registered with the default subscriber, if any,
+/// including those of the export queue. See `TelemetryLayer::stats`.
+///
+/// Specialized to the `HoneycombTelemetry` used by the layers constructed by this crate.
+pub fn dist_telemetry_stats() -> Option<TelemetryStats> {
+    tracing_distributed::dist_telemetry_stats::<HoneycombTelemetry>()
+}
+
 /// Construct a TelemetryLayer that does not publish telemetry to any backend.
 ///
 /// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
@@ -49,10 +196,7 @@ pub fn new_blackhole_telemetry_layer(
     TelemetryLayer::new(
         "honeycomb_blackhole_tracing_layer",
         tracing_distributed::BlackholeTelemetry::default(),
-        move |tracing_id| SpanId {
-            instance_id,
-            tracing_id,
-        },
+        move |tracing_id| SpanId::new(tracing_id, instance_id),
     )
 }
 
@@ -60,17 +204,43 @@ pub fn new_blackhole_telemetry_layer(
 ///
 /// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
 pub fn new_honeycomb_telemetry_layer(
-    service_name: &'static str,
+    service_name: impl Into<Arc<str>>,
+    honeycomb_config: libhoney::Config,
+) -> TelemetryLayer<HoneycombTelemetry, SpanId, TraceId> {
+    let instance_id: u64 = rand::thread_rng().gen();
+    let service_name = service_name.into();
+    TelemetryLayer::new(
+        service_name.clone(),
+        HoneycombTelemetry::new(
+            service_name.clone(),
+            honeycomb_config,
+            ExporterConfig::default(),
+            None,
+        ),
+        move |tracing_id| SpanId::new(tracing_id, instance_id),
+    )
+}
+
+/// Construct a TelemetryLayer that publishes telemetry to honeycomb.io using the provided honeycomb config,
+/// queueing spans and events for the background exporter as configured by the provided `ExporterConfig`.
+///
+/// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+pub fn new_honeycomb_telemetry_layer_with_exporter_config(
+    service_name: impl Into<Arc<str>>,
     honeycomb_config: libhoney::Config,
+    exporter_config: ExporterConfig,
 ) -> TelemetryLayer<HoneycombTelemetry, SpanId, TraceId> {
     let instance_id: u64 = rand::thread_rng().gen();
+    let service_name = service_name.into();
     TelemetryLayer::new(
-        service_name,
-        HoneycombTelemetry::new(honeycomb_config, None),
-        move |tracing_id| SpanId {
-            instance_id,
-            tracing_id,
-        },
+        service_name.clone(),
+        HoneycombTelemetry::new(
+            service_name.clone(),
+            honeycomb_config,
+            exporter_config,
+            None,
+        ),
+        move |tracing_id| SpanId::new(tracing_id, instance_id),
     )
 }
 
@@ -86,21 +256,57 @@ pub fn new_honeycomb_telemetry_layer(
 /// under it will be sent to honeycomb. If a trace is not sampled, no spans or
 /// events under it will be sent. When using this trace-level sampling, the
 /// `sample_rate` parameter on the `libhoney::Config` should be set to 1, which
-/// is the default.
+/// is the default. Published events carry a `SampleRate` of `sample_rate`, so that
+/// honeycomb.io re-weights counts.
+///
+/// Equivalent to `new_honeycomb_telemetry_layer` combined with `TraceIdSampler`, see
+/// `TelemetryLayer::with_sampler` for other sampling strategies.
 ///
 /// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
 pub fn new_honeycomb_telemetry_layer_with_trace_sampling(
-    service_name: &'static str,
+    service_name: impl Into<Arc<str>>,
     honeycomb_config: libhoney::Config,
     sample_rate: u128,
 ) -> TelemetryLayer<HoneycombTelemetry, SpanId, TraceId> {
     let instance_id: u64 = rand::thread_rng().gen();
+    let service_name = service_name.into();
+    TelemetryLayer::new(
+        service_name.clone(),
+        HoneycombTelemetry::new(
+            service_name.clone(),
+            honeycomb_config,
+            ExporterConfig::default(),
+            None,
+        ),
+        move |tracing_id| SpanId::new(tracing_id, instance_id),
+    )
+    .with_sampler(TraceIdSampler::new(sample_rate))
+}
+
+/// Construct a TelemetryLayer that publishes telemetry to honeycomb.io using the
+/// provided honeycomb config, dynamically sampling published events using the
+/// provided `DynamicSampler`. Dynamic sampling is applied on top of any
+/// trace-level sampling configured via `TelemetryLayer::with_sampler`, and
+/// published events carry a `SampleRate` that accounts for both. The
+/// `sample_rate` parameter on the `libhoney::Config` should be set to 1, which
+/// is the default.
+///
+/// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+pub fn new_honeycomb_telemetry_layer_with_dynamic_sampling(
+    service_name: impl Into<Arc<str>>,
+    honeycomb_config: libhoney::Config,
+    dynamic_sampler: DynamicSampler,
+) -> TelemetryLayer<HoneycombTelemetry, SpanId, TraceId> {
+    let instance_id: u64 = rand::thread_rng().gen();
+    let service_name = service_name.into();
     TelemetryLayer::new(
-        service_name,
-        HoneycombTelemetry::new(honeycomb_config, Some(sample_rate)),
-        move |tracing_id| SpanId {
-            instance_id,
-            tracing_id,
-        },
+        service_name.clone(),
+        HoneycombTelemetry::new(
+            service_name.clone(),
+            honeycomb_config,
+            ExporterConfig::default(),
+            Some(dynamic_sampler),
+        ),
+        move |tracing_id| SpanId::new(tracing_id, instance_id),
     )
 }
@@ -0,0 +1,308 @@
+use crate::honeycomb::{HoneycombTelemetry, TraceId};
+use libhoney::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+use tracing_distributed::{trace_id_hash, IdCodec, RootSpan, Sampler, SamplingDecision};
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+/// Sampler that samples one in every `sample_rate` traces, based on a modulo of the trace id. All
+/// services using the same sample rate make the same decision for each trace.
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub struct TraceIdSampler {
+    sample_rate: u128,
+}
+
+impl TraceIdSampler {
+    /// Construct a `TraceIdSampler` that samples one in every `sample_rate` traces. A sample rate
+    /// of 0 is treated as 1, ie every trace is sampled.
+    pub fn new(sample_rate: u128) -> Self {
+        TraceIdSampler {
+            sample_rate: sample_rate.max(1),
+        }
+    }
+}
+
+impl Sampler<TraceId> for TraceIdSampler {
+    fn sample(&self, trace_id: &TraceId, _: Option<bool>, _: &RootSpan<'_>) -> SamplingDecision {
+        if trace_id.to_u128() % self.sample_rate == 0 {
+            SamplingDecision::Sample {
+                sample_rate: self.sample_rate.min(u128::from(u64::MAX)) as u64,
+            }
+        } else {
+            SamplingDecision::Drop
+        }
+    }
+}
+
+/// Key-based dynamic sampler, which chooses a sample rate for each key (eg a route and response
+/// status) such that rare keys are always kept and common keys are heavily sampled, targeting an
+/// overall number of events per second.
+///
+/// Sample rates are recomputed at the end of each window (30 seconds by default) from the number of
+/// events observed for each key during that window. Events with keys not observed during the
+/// previous window are kept. Keys should have low cardinality, as state is kept for each key.
+///
+/// Decisions are derived from the trace id, so events in the same trace that have the same sample
+/// rate are either all kept or all dropped, and an event kept at some sample rate implies that events
+/// in the same trace with lower sample rates are kept too (eg a rare error span's common parent is
+/// dropped, but a common span's rare parent is kept). Decisions are independent of those of trace-level
+/// samplers, so kept events are published with a `SampleRate` of the product of the dynamic and
+/// trace-level sample rates, so that honeycomb.io re-weights counts.
+pub struct DynamicSampler {
+    key: Box<KeyFn>,
+    goal_events_per_second: f64,
+    window: Duration,
+    state: Mutex<DynamicSamplerState>,
+}
+
+// derives the key of an event from its fields
+type KeyFn = dyn 'static + Send + Sync + Fn(&HashMap<String, Value>) -> String;
+
+struct DynamicSamplerState {
+    window_started_at: Instant,
+    // events observed for each key during the current window
+    counts: HashMap<String, u64>,
+    // sample rates computed at the end of the previous window
+    sample_rates: HashMap<String, u64>,
+}
+
+impl DynamicSampler {
+    /// Construct a `DynamicSampler` that targets the provided number of events per second, using
+    /// the provided function, `K`, to derive a key from the fields of each published event.
+    pub fn new<K>(goal_events_per_second: f64, key: K) -> Self
+    where
+        K: 'static + Send + Sync + Fn(&HashMap<String, Value>) -> String,
+    {
+        DynamicSampler {
+            key: Box::new(key),
+            goal_events_per_second: goal_events_per_second.max(0.0),
+            window: Duration::from_secs(30),
+            state: Mutex::new(DynamicSamplerState {
+                window_started_at: Instant::now(),
+                counts: HashMap::new(),
+                sample_rates: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Construct a `DynamicSampler` that targets the provided number of events per second, using
+    /// the values of the provided fields (eg `name` and `http.status_code`) as keys.
+    pub fn by_fields<I, F>(goal_events_per_second: f64, fields: I) -> Self
+    where
+        I: IntoIterator<Item = F>,
+        F: Into<String>,
+    {
+        let fields: Vec<String> = fields.into_iter().map(Into::into).collect();
+        DynamicSampler::new(goal_events_per_second, move |values| {
+            fields
+                .iter()
+                .map(|field| match values.get(field) {
+                    Some(Value::String(s)) => s.clone(),
+                    Some(value) => value.to_string(),
+                    None => String::new(),
+                })
+                .collect::<Vec<_>>()
+                .join("|")
+        })
+    }
+
+    /// Recompute sample rates at the end of each window of the provided duration instead of every 30 seconds.
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    // returns the sample rate of the event with the provided fields if it's kept
+    pub(crate) fn sample(&self, trace_id: TraceId, values: &HashMap<String, Value>) -> Option<u64> {
+        let key = (self.key)(values);
+
+        let sample_rate = {
+            // succeed or die. failure is unrecoverable (mutex poisoned)
+            #[cfg(not(feature = "use_parking_lot"))]
+            let mut state = self.state.lock().unwrap();
+            #[cfg(feature = "use_parking_lot")]
+            let mut state = self.state.lock();
+
+            let now = Instant::now();
+            if now.saturating_duration_since(state.window_started_at) >= self.window {
+                let goal_count = self.goal_events_per_second * self.window.as_secs_f64();
+                state.sample_rates = sample_rates(&state.counts, goal_count);
+                state.counts.clear();
+                state.window_started_at = now;
+            }
+
+            let sample_rate = state.sample_rates.get(&key).copied().unwrap_or(1);
+            *state.counts.entry(key).or_default() += 1;
+            sample_rate
+        };
+
+        if keep(trace_id, sample_rate) {
+            Some(sample_rate)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Debug for DynamicSampler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DynamicSampler")
+            .field("goal_events_per_second", &self.goal_events_per_second)
+            .field("window", &self.window)
+            .finish()
+    }
+}
+
+// distributes the goal count evenly across keys, starting with the rarest, such that keys observed
+// less often than their share of the goal are always kept and the leftover goal is split among the rest
+fn sample_rates(counts: &HashMap<String, u64>, goal_count: f64) -> HashMap<String, u64> {
+    let mut counts: Vec<(&String, u64)> = counts.iter().map(|(k, v)| (k, *v)).collect();
+    counts.sort_by_key(|(_, count)| *count);
+
+    let mut remaining_goal = goal_count;
+    let mut remaining_keys = counts.len();
+    let mut res = HashMap::with_capacity(counts.len());
+    for (key, count) in counts {
+        let key_goal = (remaining_goal / remaining_keys as f64).max(1.0);
+        let sample_rate = (count as f64 / key_goal).ceil().max(1.0) as u64;
+        remaining_goal = (remaining_goal - count as f64 / sample_rate as f64).max(0.0);
+        remaining_keys -= 1;
+        res.insert(key.clone(), sample_rate);
+    }
+    res
+}
+
+// seed of the hash from which dynamic sampling decisions are derived, distinct from that of
+// trace-level samplers such as `RatioSampler` so that the two decisions are independent
+const DYNAMIC_SAMPLING_SEED: u64 = 0x6479_6e61_6d69_6373;
+
+// keeps one in every `sample_rate` traces. Traces kept at some sample rate are kept at every lower
+// sample rate, so that decisions for events of the same trace with different sample rates nest
+fn keep(trace_id: TraceId, sample_rate: u64) -> bool {
+    let hash = trace_id_hash(
+        HoneycombTelemetry::encode_trace_id(&trace_id),
+        DYNAMIC_SAMPLING_SEED,
+    );
+    hash <= u64::MAX / sample_rate.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libhoney::json;
+
+    #[test]
+    fn rare_keys_are_kept() {
+        let counts: HashMap<String, u64> = vec![
+            ("rare".to_string(), 5),
+            ("medium".to_string(), 100),
+            ("common".to_string(), 1000),
+        ]
+        .into_iter()
+        .collect();
+
+        let rates = sample_rates(&counts, 100.0);
+        assert_eq!(rates["rare"], 1);
+        assert_eq!(rates["medium"], 3);
+        assert_eq!(rates["common"], 17);
+
+        let rates = sample_rates(&counts, 10_000.0);
+        assert!(rates.values().all(|rate| *rate == 1));
+    }
+
+    #[test]
+    fn dynamic_sampling() {
+        let sampler = DynamicSampler::by_fields(10.0, vec!["name", "status"])
+            .with_window(Duration::from_millis(100));
+        let event = |name: &str, status: u64| -> HashMap<String, Value> {
+            vec![
+                ("name".to_string(), json!(name)),
+                ("status".to_string(), json!(status)),
+            ]
+            .into_iter()
+            .collect()
+        };
+        let kept = |name: &str, status: u64, count: u128| -> Vec<Option<u64>> {
+            (1..=count)
+                .map(|trace_id| sampler.sample(TraceId::from_u128(trace_id), &event(name, status)))
+                .filter(Option::is_some)
+                .collect()
+        };
+
+        // everything is kept during the first window
+        assert_eq!(kept("get", 200, 1_000).len(), 1_000);
+        assert_eq!(kept("get", 500, 1).len(), 1);
+        std::thread::sleep(Duration::from_millis(100));
+
+        // about one in 1_000 traces is kept
+        let common = kept("get", 200, 10_000);
+        assert!(
+            common.len() > 1 && common.len() < 30,
+            "kept {}",
+            common.len()
+        );
+        assert!(common.iter().all(|rate| *rate == Some(1_000)));
+        assert_eq!(kept("get", 500, 1), vec![Some(1)]);
+
+        // decisions are consistent for each trace id
+        let trace_id = TraceId::from_u128(135);
+        let decision = sampler.sample(trace_id, &event("get", 200));
+        assert_eq!(sampler.sample(trace_id, &event("get", 200)), decision);
+    }
+
+    #[test]
+    fn decisions_nest_across_sample_rates() {
+        let rates = [1, 2, 3, 4, 10, 100];
+        for trace_id in (1..=10_000).map(TraceId::from_u128) {
+            for (idx, higher) in rates.iter().enumerate() {
+                if keep(trace_id, *higher) {
+                    assert!(rates[..idx].iter().all(|lower| keep(trace_id, *lower)));
+                }
+            }
+        }
+
+        let kept = (1..=10_000)
+            .filter(|trace_id| keep(TraceId::from_u128(*trace_id), 4))
+            .count();
+        assert!(kept > 2_000 && kept < 3_000, "kept {}", kept);
+    }
+
+    #[test]
+    fn dynamic_and_trace_level_sampling_are_independent() {
+        let root =
+            tracing::subscriber::with_default(tracing_subscriber::Registry::default(), || {
+                RootSpan::new(
+                    tracing::info_span!("root")
+                        .metadata()
+                        .expect("span is enabled"),
+                )
+            });
+        let ratio_sampler = crate::RatioSampler::new(0.1);
+
+        // each kept trace stands in for the product of both sample rates, so that re-weighted
+        // counts are unbiased estimates of the total
+        let traces = 100_000;
+        let (mut kept, mut weighted) = (0, 0);
+        for trace_id in (1..=traces).map(TraceId::from_u128) {
+            if let SamplingDecision::Sample { sample_rate } =
+                ratio_sampler.sample(&trace_id, None, &root)
+            {
+                if keep(trace_id, 4) {
+                    kept += 1;
+                    weighted += sample_rate * 4;
+                }
+            }
+        }
+        assert!(kept > 2_000 && kept < 3_000, "kept {}", kept);
+        assert!(
+            weighted > traces as u64 * 9 / 10 && weighted < traces as u64 * 11 / 10,
+            "weighted count {}",
+            weighted
+        );
+    }
+}
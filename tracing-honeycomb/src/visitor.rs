@@ -1,10 +1,12 @@
 use crate::honeycomb::{SpanId, TraceId};
 use ::libhoney::{json, Value};
 use chrono::{DateTime, Utc};
+use rand::Rng;
 use std::collections::HashMap;
 use std::fmt;
+use std::time::SystemTime;
 use tracing::field::{Field, Visit};
-use tracing_distributed::{Event, Span};
+use tracing_distributed::{DataLoss, Event, Resource, Span, SpanStatus, DATA_LOSS_RECORD_NAME};
 
 // Visitor that builds honeycomb-compatible values from tracing fields.
 #[derive(Default, Debug)]
@@ -12,7 +14,7 @@ use tracing_distributed::{Event, Span};
 pub struct HoneycombVisitor(pub(crate) HashMap<String, Value>);
 
 // reserved field names (TODO: document)
-static RESERVED_WORDS: [&str; 9] = [
+static RESERVED_WORDS: [&str; 14] = [
     "trace.span_id",
     "trace.trace_id",
     "trace.parent_id",
@@ -22,8 +24,16 @@ static RESERVED_WORDS: [&str; 9] = [
     "name",
     "target",
     "duration_ms",
+    "busy_ms",
+    "idle_ms",
+    "enter_count",
+    "error",
+    "meta.span_kind",
 ];
 
+// reserved field name prefixes, under which baggage items are published
+static RESERVED_PREFIXES: [&str; 1] = ["baggage."];
+
 impl Visit for HoneycombVisitor {
     fn record_i64(&mut self, field: &Field, value: i64) {
         self.0
@@ -54,13 +64,22 @@ impl Visit for HoneycombVisitor {
 
 fn mk_field_name(s: String) -> String {
     // TODO: do another pass, optimize for efficiency (lazy static set?)
-    if RESERVED_WORDS.contains(&&s[..]) {
+    if RESERVED_WORDS.contains(&&s[..]) || RESERVED_PREFIXES.iter().any(|p| s.starts_with(p)) {
         format!("tracing.{}", s)
     } else {
         s
     }
 }
 
+// resource attributes are published as fields, unless a field of the same name was recorded
+fn insert_resource(values: &mut HashMap<String, libhoney::Value>, resource: &Resource) {
+    for (key, value) in resource.iter() {
+        values
+            .entry(key.to_string())
+            .or_insert_with(|| json!(value));
+    }
+}
+
 pub(crate) fn event_to_values(
     event: Event<HoneycombVisitor, SpanId, TraceId>,
 ) -> HashMap<String, libhoney::Value> {
@@ -73,17 +92,29 @@ pub(crate) fn event_to_values(
         json!(event.trace_id.to_string()),
     );
 
-    values.insert(
-        // magic honeycomb string (trace.parent_id)
-        "trace.parent_id".to_string(),
-        event
-            .parent_id
-            .map(|pid| json!(format!("span-{}", pid.to_string())))
-            .unwrap_or(json!(null)),
-    );
+    match event.parent_id {
+        Some(parent_id) => {
+            values.insert(
+                // magic honeycomb string (trace.parent_id)
+                "trace.parent_id".to_string(),
+                json!(parent_id.to_published_id()),
+            );
+        }
+        // events that are not part of any trace are reported as the root span of their own trace
+        None => {
+            let span_id: u64 = rand::thread_rng().gen();
+            values.insert(
+                // magic honeycomb string (trace.span_id)
+                "trace.span_id".to_string(),
+                json!(format!("{:016x}", span_id)),
+            );
+        }
+    }
 
     // magic honeycomb string (service_name)
-    values.insert("service_name".to_string(), json!(event.service_name));
+    values.insert("service_name".to_string(), json!(&*event.service_name));
+
+    insert_resource(&mut values, &event.resource);
 
     values.insert(
         "level".to_string(),
@@ -100,6 +131,33 @@ pub(crate) fn event_to_values(
     values
 }
 
+// synthetic record describing data lost by the exporter, which is not part of any trace
+pub(crate) fn data_loss_to_values(
+    loss: DataLoss,
+    service_name: &str,
+) -> HashMap<String, libhoney::Value> {
+    let mut values = HashMap::new();
+
+    values.insert("service_name".to_string(), json!(service_name));
+
+    let now: DateTime<Utc> = SystemTime::now().into();
+    values.insert("Timestamp".to_string(), json!(now.to_rfc3339()));
+
+    values.insert("name".to_string(), json!(DATA_LOSS_RECORD_NAME));
+    values.insert("dropped.total".to_string(), json!(loss.total()));
+    values.insert("dropped.buffer_full".to_string(), json!(loss.buffer_full));
+    values.insert(
+        "dropped.export_errors".to_string(),
+        json!(loss.export_errors),
+    );
+    values.insert(
+        "dropped.period_ms".to_string(),
+        json!(loss.period.as_millis() as u64),
+    );
+
+    values
+}
+
 pub(crate) fn span_to_values(
     span: Span<HoneycombVisitor, SpanId, TraceId>,
 ) -> HashMap<String, libhoney::Value> {
@@ -108,7 +166,7 @@ pub(crate) fn span_to_values(
     values.insert(
         // magic honeycomb string (trace.span_id)
         "trace.span_id".to_string(),
-        json!(format!("span-{}", span.id.to_string())),
+        json!(span.id.to_published_id()),
     );
 
     values.insert(
@@ -122,12 +180,14 @@ pub(crate) fn span_to_values(
         // magic honeycomb string (trace.parent_id)
         "trace.parent_id".to_string(),
         span.parent_id
-            .map(|pid| json!(format!("span-{}", pid.to_string())))
+            .map(|pid| json!(pid.to_published_id()))
             .unwrap_or(json!(null)),
     );
 
     // magic honeycomb string (service_name)
-    values.insert("service_name".to_string(), json!(span.service_name));
+    values.insert("service_name".to_string(), json!(&*span.service_name));
+
+    insert_resource(&mut values, &span.resource);
 
     values.insert("level".to_string(), json!(format!("{}", span.meta.level())));
 
@@ -148,5 +208,90 @@ pub(crate) fn span_to_values(
         }
     }
 
+    // not honeycomb-special, time spent inside and outside of the span (eg while an async task is parked)
+    values.insert(
+        "busy_ms".to_string(),
+        json!(span.busy.as_secs_f64() * 1000.0),
+    );
+    values.insert(
+        "idle_ms".to_string(),
+        json!(span.idle.as_secs_f64() * 1000.0),
+    );
+    values.insert("enter_count".to_string(), json!(span.enter_count));
+
+    values.insert("meta.span_kind".to_string(), json!(span.kind.to_string()));
+
+    for (key, value) in span.baggage.iter() {
+        values.insert(format!("baggage.{}", key), json!(value));
+    }
+
+    // beeline convention, failed spans have an error field holding the error message
+    if let SpanStatus::Error(message) = span.status {
+        values.insert("error".to_string(), json!(message));
+    }
+
     values
 }
+
+// honeycomb represents links as span annotation events, one per linked span
+pub(crate) fn span_links_to_values(
+    span: &Span<HoneycombVisitor, SpanId, TraceId>,
+) -> Vec<HashMap<String, libhoney::Value>> {
+    let initialized_at: DateTime<Utc> = span.initialized_at.into();
+
+    span.follows_from
+        .iter()
+        .map(|(link_trace_id, link_span_id)| {
+            let mut values = HashMap::new();
+
+            // magic honeycomb strings (annotations share the trace and are parented by the annotated span)
+            values.insert(
+                "trace.trace_id".to_string(),
+                json!(span.trace_id.to_string()),
+            );
+            values.insert(
+                "trace.parent_id".to_string(),
+                json!(span.id.to_published_id()),
+            );
+            values.insert("meta.annotation_type".to_string(), json!("link"));
+            values.insert(
+                "trace.link.trace_id".to_string(),
+                json!(link_trace_id.to_string()),
+            );
+            values.insert(
+                "trace.link.span_id".to_string(),
+                json!(link_span_id.to_published_id()),
+            );
+
+            values.insert("service_name".to_string(), json!(&*span.service_name));
+            values.insert("Timestamp".to_string(), json!(initialized_at.to_rfc3339()));
+
+            values
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reserved_field_names() {
+        assert_eq!(mk_field_name("user_id".to_string()), "user_id");
+        assert_eq!(
+            mk_field_name("trace.trace_id".to_string()),
+            "tracing.trace.trace_id"
+        );
+        // recorded fields can't overwrite the span kind published for each span
+        assert_eq!(
+            mk_field_name("meta.span_kind".to_string()),
+            "tracing.meta.span_kind"
+        );
+        // nor the baggage items published alongside it
+        assert_eq!(
+            mk_field_name("baggage.user_id".to_string()),
+            "tracing.baggage.user_id"
+        );
+        assert_eq!(mk_field_name("baggage".to_string()), "baggage");
+    }
+}
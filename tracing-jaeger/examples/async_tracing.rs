@@ -4,7 +4,8 @@ use tokio::process::Command;
 use tokio::time::delay_for;
 use tracing::instrument;
 use tracing_jaeger::{
-    current_dist_trace_ctx, new_opentelemetry_layer, register_dist_tracing_root, SpanId, TraceId,
+    current_dist_trace_ctx, new_opentelemetry_layer, register_dist_tracing_root,
+    shutdown_dist_telemetry, SpanId, TraceId,
 };
 use tracing_subscriber::filter::LevelFilter;
 use tracing_subscriber::layer::SubscriberExt;
@@ -66,8 +67,8 @@ async fn main() {
         }
     }
 
-    // janky, but delay seems to be required to ensure all traces are sent to honeycomb by libhoney
-    delay_for(Duration::from_secs(10)).await
+    // deliver any spans that are still buffered before exiting
+    shutdown_dist_telemetry(Duration::from_secs(10)).unwrap();
 }
 
 fn register_global_subscriber() {
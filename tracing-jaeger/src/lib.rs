@@ -21,8 +21,19 @@ use ::opentelemetry::exporter::trace::SpanExporter;
 use ::opentelemetry::sdk::Config;
 use rand::Rng;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing_distributed::DataLossReporter;
 #[doc(no_inline)]
-pub use tracing_distributed::{TelemetryLayer, TraceCtxError};
+pub use tracing_distributed::{
+    AlwaysSample, B3Encoding, BackendStats, Baggage, Carrier, CompositePropagator, FanOutTelemetry,
+    FlushError, IdAdapter, IncompleteTracePolicy, NeverSample, ParentBasedSampler, Propagator,
+    RateLimitedSampler, RemoteTraceCtx, Resource, RootSpan, Sampler, SamplingAction, SamplingRule,
+    SpanKind, SpanStatus, StatusConvention, TailSamplingConfig, TailSamplingRule,
+    TailSamplingTelemetry, TelemetryLayer, TelemetryStats, TraceCtxError, TraceParent,
+    BAGGAGE_HEADER, SPAN_KIND_FIELD, TRACEPARENT_HEADER, TRACESTATE_HEADER,
+};
 
 #[cfg(feature = "use_parking_lot")]
 use parking_lot::Mutex;
@@ -39,6 +50,49 @@ pub fn register_dist_tracing_root(
     tracing_distributed::register_dist_tracing_root(trace_id, remote_parent_span)
 }
 
+/// Register the current span as the local root of a distributed trace, along with baggage
+/// received from the remote parent span (if any).
+///
+/// Specialized to the opentelemetry-specific SpanId and TraceId provided by this crate.
+pub fn register_dist_tracing_root_with_baggage(
+    trace_id: TraceId,
+    remote_parent_span: Option<SpanId>,
+    baggage: Baggage,
+) -> Result<(), TraceCtxError> {
+    tracing_distributed::register_dist_tracing_root_with_baggage(
+        trace_id,
+        remote_parent_span,
+        baggage,
+    )
+}
+
+/// Retrieve the baggage associated with the current span.
+///
+/// Specialized to the opentelemetry-specific SpanId and TraceId provided by this crate.
+pub fn current_baggage() -> Result<Baggage, TraceCtxError> {
+    tracing_distributed::current_baggage::<SpanId, TraceId>()
+}
+
+/// Set a baggage entry on the current span. The entry is visible to the current span, to spans
+/// subsequently created within it and to any process the trace context is propagated to from them.
+///
+/// Specialized to the opentelemetry-specific SpanId and TraceId provided by this crate.
+pub fn set_baggage_item(
+    key: impl Into<String>,
+    value: impl Into<String>,
+) -> Result<(), TraceCtxError> {
+    tracing_distributed::set_baggage_item::<SpanId, TraceId>(key, value)
+}
+
+/// Override the service name reported for the current span and spans subsequently created within it,
+/// eg just after registering a distributed tracing root, so that a process hosting several logical
+/// services can report each of their traces under its own service name.
+///
+/// Specialized to the opentelemetry-specific SpanId and TraceId provided by this crate.
+pub fn set_service_name(service_name: impl Into<Arc<str>>) -> Result<(), TraceCtxError> {
+    tracing_distributed::set_service_name::<SpanId, TraceId>(service_name)
+}
+
 /// Retrieve the distributed trace context associated with the current span.
 ///
 /// Returns the `TraceId`, if any, that the current span is associated with along with
@@ -49,6 +103,92 @@ pub fn current_dist_trace_ctx() -> Result<(TraceId, SpanId), TraceCtxError> {
     tracing_distributed::current_dist_trace_ctx()
 }
 
+/// Register the current span as the local root of a distributed trace continued from a remote
+/// parent, as described by the W3C `traceparent` and (optional) `tracestate` headers of an incoming request.
+///
+/// Specialized to the opentelemetry-specific SpanId and TraceId provided by this crate.
+pub fn register_w3c_tracing_root(
+    traceparent: &TraceParent,
+    tracestate: Option<String>,
+) -> Result<(), TraceCtxError> {
+    tracing_distributed::register_w3c_tracing_root::<OpenTelemetry>(traceparent, tracestate)
+}
+
+/// Retrieve the distributed trace context associated with the current span as the values of the
+/// W3C `traceparent` and (optional) `tracestate` headers to attach to an outgoing request.
+///
+/// Specialized to the opentelemetry-specific SpanId and TraceId provided by this crate.
+pub fn current_w3c_trace_ctx() -> Result<(TraceParent, Option<String>), TraceCtxError> {
+    tracing_distributed::current_w3c_trace_ctx::<OpenTelemetry>()
+}
+
+/// `Propagator` using the W3C `traceparent` and `tracestate` headers.
+///
+/// Specialized to the opentelemetry-specific SpanId and TraceId provided by this crate.
+pub type W3CPropagator = tracing_distributed::W3CPropagator<OpenTelemetry>;
+
+/// `Propagator` using the B3 headers used by Zipkin and Envoy.
+///
+/// Specialized to the opentelemetry-specific SpanId and TraceId provided by this crate.
+pub type B3Propagator = tracing_distributed::B3Propagator<OpenTelemetry>;
+
+/// Sampler that samples the provided ratio of traces, between 0 and 1.
+///
+/// Specialized to the opentelemetry-specific SpanId and TraceId provided by this crate.
+pub type RatioSampler = tracing_distributed::RatioSampler<OpenTelemetry>;
+
+/// Sampler that applies the action of the first `SamplingRule` matching the local root span of each trace.
+///
+/// Specialized to the opentelemetry-specific SpanId and TraceId provided by this crate.
+pub type RulesSampler = tracing_distributed::RulesSampler<OpenTelemetry>;
+
+/// Write the distributed trace context associated with the current span to `carrier`, using the
+/// provided `Propagator`.
+///
+/// Specialized to the opentelemetry-specific SpanId and TraceId provided by this crate.
+pub fn inject_dist_trace_ctx(
+    propagator: &dyn Propagator<SpanId, TraceId>,
+    carrier: &mut dyn Carrier,
+) -> Result<(), TraceCtxError> {
+    tracing_distributed::inject_dist_trace_ctx(propagator, carrier)
+}
+
+/// Register the current span as the local root of a distributed trace, using trace context read
+/// from `carrier` by the provided `Propagator`.
+///
+/// Specialized to the opentelemetry-specific SpanId and TraceId provided by this crate.
+pub fn extract_dist_tracing_root(
+    propagator: &dyn Propagator<SpanId, TraceId>,
+    carrier: &dyn Carrier,
+) -> Result<(), TraceCtxError> {
+    tracing_distributed::extract_dist_tracing_root(propagator, carrier)
+}
+
+/// Deliver all spans reported so far to the OpenTelemetry exporter. Spans are exported as soon as
+/// they complete, so this only fails if the exporter was shut down. See `TelemetryLayer::flush`.
+///
+/// Specialized to the `OpenTelemetry` telemetry used by the layers constructed by this crate.
+pub fn flush_dist_telemetry(timeout: Duration) -> Result<(), FlushError> {
+    tracing_distributed::flush_dist_telemetry::<OpenTelemetry>(timeout)
+}
+
+/// Shut down the OpenTelemetry exporter. Spans reported afterwards are dropped. See
+/// `TelemetryLayer::shutdown`, although `timeout` is not enforced as exporters block until
+/// shut down.
+///
+/// Specialized to the `OpenTelemetry` telemetry used by the layers constructed by this crate.
+pub fn shutdown_dist_telemetry(timeout: Duration) -> Result<(), FlushError> {
+    tracing_distributed::shutdown_dist_telemetry::<OpenTelemetry>(timeout)
+}
+
+/// Retrieve the counters of the `TelemetryLayer` registered with the default subscriber, if any.
+/// Pending items are spans with events awaiting the span's completion. See `TelemetryLayer::stats`.
+///
+/// Specialized to the `OpenTelemetry` telemetry used by the layers constructed by this crate.
+pub fn dist_telemetry_stats() -> Option<TelemetryStats> {
+    tracing_distributed::dist_telemetry_stats::<OpenTelemetry>()
+}
+
 /// Construct a TelemetryLayer that does not publish telemetry to any backend.
 ///
 /// Specialized to the opentelemetry-specific SpanId and TraceId provided by this crate.
@@ -65,18 +205,39 @@ pub fn new_blackhole_telemetry_layer(
 ///
 /// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
 pub fn new_opentelemetry_layer(
-    service_name: &'static str,
+    service_name: impl Into<Arc<str>>,
+    exporter: Box<dyn SpanExporter>,
+    config: Config,
+) -> TelemetryLayer<OpenTelemetry, SpanId, TraceId> {
+    new_opentelemetry_layer_with_data_loss_interval(service_name, exporter, config, None)
+}
+
+/// Construct a TelemetryLayer that publishes telemetry using the provided exporter and config, which
+/// also exports a `tracing.dropped_events` span every `data_loss_interval` (if provided) counting the
+/// spans that failed to export since the previous one, if any did.
+///
+/// Specialized to the opentelemetry-specific SpanId and TraceId provided by this crate.
+pub fn new_opentelemetry_layer_with_data_loss_interval(
+    service_name: impl Into<Arc<str>>,
     exporter: Box<dyn SpanExporter>,
     config: Config,
+    data_loss_interval: Option<Duration>,
 ) -> TelemetryLayer<OpenTelemetry, SpanId, TraceId> {
     // used to keep nodes in a multiprocess scenario from generating the same sequence of span ids
     let r: u64 = rand::thread_rng().gen();
+    let service_name = service_name.into();
     TelemetryLayer::new(
-        service_name,
+        service_name.clone(),
         OpenTelemetry {
             exporter,
             events: Mutex::new(HashMap::new()),
+            pending: AtomicUsize::new(0),
             config,
+            shut_down: AtomicBool::new(false),
+            dropped_shut_down: AtomicU64::new(0),
+            export_errors: AtomicU64::new(0),
+            data_loss: data_loss_interval.map(DataLossReporter::new),
+            service_name,
         },
         move |tracing_id| SpanId::from_u64(tracing_id.into_u64() ^ r),
     )
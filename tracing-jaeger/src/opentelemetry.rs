@@ -1,11 +1,19 @@
-use crate::visitor::{event_to_values, span_to_values, OpenTelemetryVisitor};
+use crate::visitor::{
+    data_loss_to_values, event_to_values, orphan_event_to_values, span_to_values,
+    OpenTelemetryVisitor,
+};
 use opentelemetry::api::trace::{self, span_context::SpanId, span_context::TraceId};
+use opentelemetry::exporter::trace::{ExportResult, SpanData};
 use opentelemetry::sdk::trace::config::Config;
 use opentelemetry::sdk::trace::evicted_hash_map::EvictedHashMap;
 use opentelemetry::sdk::EvictedQueue;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use tracing_distributed::{Event, Span, Telemetry};
+use std::time::Duration;
+use tracing_distributed::{
+    BackendStats, DataLossReporter, Event, FlushError, IdCodec, Span, Telemetry,
+};
 
 #[cfg(feature = "use_parking_lot")]
 use parking_lot::Mutex;
@@ -18,7 +26,47 @@ pub struct OpenTelemetry {
     pub(crate) exporter: Box<dyn opentelemetry::exporter::trace::SpanExporter>,
     // TODO: should have some eviction strategy so this doesn't grow forever
     pub(crate) events: Mutex<HashMap<SpanId, EvictedQueue<trace::event::Event>>>,
+    // number of spans in `events`, tracked separately so that reading stats doesn't contend with exports
+    pub(crate) pending: AtomicUsize,
     pub(crate) config: Config,
+    // exporters must not be used once shut down
+    pub(crate) shut_down: AtomicBool,
+    // spans and events reported after shutdown, along with events of spans that never completed
+    pub(crate) dropped_shut_down: AtomicU64,
+    // spans the exporter failed to export
+    pub(crate) export_errors: AtomicU64,
+    // if present, export errors are reported to the backend itself once exports succeed again
+    pub(crate) data_loss: Option<DataLossReporter>,
+    pub(crate) service_name: Arc<str>,
+}
+
+impl OpenTelemetry {
+    fn export(&self, data: SpanData) {
+        match self.exporter.export(vec![Arc::new(data)]) {
+            ExportResult::Success => self.report_data_loss(),
+            ExportResult::FailedRetryable | ExportResult::FailedNotRetryable => {
+                self.export_errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    // exports a synthetic span describing spans lost since the previous one, if it's time to do so
+    fn report_data_loss(&self) {
+        let loss = self
+            .data_loss
+            .as_ref()
+            .and_then(|data_loss| data_loss.poll(|| self.stats()));
+        if let Some(loss) = loss {
+            let data = data_loss_to_values(
+                loss,
+                &self.service_name,
+                self.config.max_attributes_per_span,
+            );
+            if self.exporter.export(vec![Arc::new(data)]) != ExportResult::Success {
+                self.export_errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
 }
 
 impl Telemetry for OpenTelemetry {
@@ -32,34 +80,295 @@ impl Telemetry for OpenTelemetry {
     }
 
     fn report_span(&self, span: Span<Self::Visitor, Self::SpanId, Self::TraceId>) {
-        // succeed or die. failure is unrecoverable (mutex poisoned)
-        #[cfg(not(feature = "use_parking_lot"))]
-        let mut events = self.events.lock().unwrap();
-        #[cfg(feature = "use_parking_lot")]
-        let mut events = self.events.lock();
+        // the lock must be released before exporting, which may report data loss
+        let events = {
+            // succeed or die. failure is unrecoverable (mutex poisoned)
+            #[cfg(not(feature = "use_parking_lot"))]
+            let mut events = self.events.lock().unwrap();
+            #[cfg(feature = "use_parking_lot")]
+            let mut events = self.events.lock();
+
+            // checked under the lock `shutdown` takes, so it can't race with the final clear
+            if self.shut_down.load(Ordering::Relaxed) {
+                self.dropped_shut_down.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
 
-        let events = events
-            .remove(&span.id)
-            .unwrap_or_else(|| EvictedQueue::new(0));
-        let data = span_to_values(span, events);
-        self.exporter.export(vec![Arc::new(data)]); // TODO: batch
+            match events.remove(&span.id) {
+                Some(q) => {
+                    self.pending.fetch_sub(1, Ordering::Relaxed);
+                    q
+                }
+                None => EvictedQueue::new(0),
+            }
+        };
+        let data = span_to_values(span, events, self.config.max_links_per_span);
+        // TODO: batch
+        self.export(data);
     }
 
     fn report_event(&self, event: Event<Self::Visitor, Self::SpanId, Self::TraceId>) {
-        // events are reported as part of spandata, event must have a parent to be recorded
-        if let Some(id) = event.parent_id {
+        // events are reported as part of spandata, events without a parent are their own span
+        let orphan = {
+            // succeed or die. failure is unrecoverable (mutex poisoned)
+            #[cfg(not(feature = "use_parking_lot"))]
+            let mut events = self.events.lock().unwrap();
+            #[cfg(feature = "use_parking_lot")]
+            let mut events = self.events.lock();
+
+            // checked under the lock `shutdown` takes, so nothing is buffered after its final clear
+            if self.shut_down.load(Ordering::Relaxed) {
+                self.dropped_shut_down.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+
+            match event.parent_id {
+                Some(id) => {
+                    if let Some(q) = events.get_mut(&id) {
+                        q.append_vec(&mut vec![event_to_values(event)]);
+                    } else {
+                        let mut q = EvictedQueue::new(self.config.max_events_per_span);
+                        q.append_vec(&mut vec![event_to_values(event)]);
+                        events.insert(id, q);
+                        self.pending.fetch_add(1, Ordering::Relaxed);
+                    }
+                    None
+                }
+                None => Some(event),
+            }
+        };
+        if let Some(event) = orphan {
+            self.export(orphan_event_to_values(event));
+        }
+    }
+
+    // spans are exported synchronously, so there is nothing to flush
+    fn flush(&self, _timeout: Duration) -> Result<(), FlushError> {
+        if self.shut_down.load(Ordering::Relaxed) {
+            Err(FlushError::ShutDown)
+        } else {
+            Ok(())
+        }
+    }
+
+    // `SpanExporter::shutdown` takes no timeout, so this blocks until the exporter is shut down
+    fn shutdown(&self, _timeout: Duration) -> Result<(), FlushError> {
+        {
+            // succeed or die. failure is unrecoverable (mutex poisoned)
             #[cfg(not(feature = "use_parking_lot"))]
             let mut events = self.events.lock().unwrap();
             #[cfg(feature = "use_parking_lot")]
             let mut events = self.events.lock();
 
-            if let Some(q) = events.get_mut(&id) {
-                q.append_vec(&mut vec![event_to_values(event)]);
-            } else {
-                let mut q = EvictedQueue::new(self.config.max_events_per_span);
-                q.append_vec(&mut vec![event_to_values(event)]);
-                events.insert(id, q);
+            // set under the lock, so spans and events reported concurrently either make it into
+            // `events` before it is cleared or see the flag and are dropped
+            if self.shut_down.swap(true, Ordering::Relaxed) {
+                return Err(FlushError::ShutDown);
+            }
+
+            // events of spans that never completed can no longer be exported
+            let dropped: usize = events.values().map(|q| q.len()).sum();
+            self.dropped_shut_down
+                .fetch_add(dropped as u64, Ordering::Relaxed);
+            events.clear();
+            self.pending.store(0, Ordering::Relaxed);
+        }
+        self.exporter.shutdown();
+        Ok(())
+    }
+
+    fn stats(&self) -> BackendStats {
+        BackendStats {
+            dropped_shut_down: self.dropped_shut_down.load(Ordering::Relaxed),
+            export_errors: self.export_errors.load(Ordering::Relaxed),
+            pending: self.pending.load(Ordering::Relaxed),
+            ..BackendStats::default()
+        }
+    }
+}
+
+impl IdCodec for OpenTelemetry {
+    type TraceId = TraceId;
+    type SpanId = SpanId;
+
+    fn encode_trace_id(trace_id: &TraceId) -> u128 {
+        trace_id.to_u128()
+    }
+
+    fn decode_trace_id(trace_id: u128) -> TraceId {
+        TraceId::from_u128(trace_id)
+    }
+
+    fn encode_span_id(span_id: &SpanId) -> u64 {
+        span_id.to_u64()
+    }
+
+    fn decode_span_id(span_id: u64) -> SpanId {
+        SpanId::from_u64(span_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{new_opentelemetry_layer, register_dist_tracing_root};
+    use opentelemetry::sdk::Config;
+    use tracing::instrument;
+    use tracing_distributed::DATA_LOSS_RECORD_NAME;
+    use tracing_subscriber::layer::Layer;
+    use tracing_subscriber::registry;
+
+    // exporter that records exported spans, after failing the provided number of exports
+    #[derive(Debug, Default)]
+    struct MockExporter {
+        exported: Arc<std::sync::Mutex<Vec<Arc<SpanData>>>>,
+        failures: AtomicUsize,
+    }
+
+    impl opentelemetry::exporter::trace::SpanExporter for MockExporter {
+        fn export(&self, mut batch: Vec<Arc<SpanData>>) -> ExportResult {
+            let failures = self.failures.load(Ordering::Relaxed);
+            if failures > 0 {
+                self.failures.store(failures - 1, Ordering::Relaxed);
+                return ExportResult::FailedRetryable;
             }
+            self.exported.lock().unwrap().append(&mut batch);
+            ExportResult::Success
+        }
+
+        fn shutdown(&self) {}
+    }
+
+    #[instrument]
+    fn root() {
+        register_dist_tracing_root(TraceId::from_u128(135), None).unwrap();
+        tracing::info!("event");
+        child();
+    }
+
+    #[instrument]
+    fn child() {
+        tracing::info!("child event");
+    }
+
+    // runs `f` on another thread, failing instead of hanging forever if it deadlocks
+    fn with_timeout<F: 'static + Send + FnOnce()>(f: F) {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            f();
+            sender.send(()).unwrap();
+        });
+        receiver
+            .recv_timeout(Duration::from_secs(10))
+            .expect("deadlocked or panicked");
+    }
+
+    #[test]
+    fn exports_spans_with_their_events() {
+        let exporter = MockExporter::default();
+        let exported = exporter.exported.clone();
+        let layer = new_opentelemetry_layer("test_svc_name", Box::new(exporter), Config::default());
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        with_timeout(move || tracing::subscriber::with_default(subscriber, root));
+
+        let exported = exported.lock().unwrap();
+        let names: Vec<_> = exported.iter().map(|span| span.name.as_str()).collect();
+        assert_eq!(names, vec!["child", "root"]);
+        assert!(exported
+            .iter()
+            .all(|span| span.span_context.trace_id() == TraceId::from_u128(135)));
+        assert_eq!(
+            exported[0].parent_span_id,
+            exported[1].span_context.span_id()
+        );
+        assert_eq!(exported[0].message_events.len(), 1);
+        assert_eq!(exported[1].message_events.len(), 1);
+    }
+
+    #[test]
+    fn stats_count_spans_with_pending_events() {
+        let layer = new_opentelemetry_layer(
+            "test_svc_name",
+            Box::new(MockExporter::default()),
+            Config::default(),
+        );
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        #[instrument]
+        fn pending() {
+            register_dist_tracing_root(TraceId::from_u128(135), None).unwrap();
+            tracing::info!("event");
+            let stats = crate::dist_telemetry_stats().unwrap();
+            assert_eq!(stats.backend.pending, 1);
         }
+
+        with_timeout(move || {
+            tracing::subscriber::with_default(subscriber, || {
+                pending();
+                let stats = crate::dist_telemetry_stats().unwrap();
+                assert_eq!(stats.backend.pending, 0);
+                assert_eq!(stats.spans_reported, 1);
+            })
+        });
+    }
+
+    // spans exported by `root` when the first export fails
+    fn exported_after_export_error(data_loss_interval: Option<Duration>) -> Vec<Arc<SpanData>> {
+        let exporter = MockExporter {
+            failures: AtomicUsize::new(1),
+            ..MockExporter::default()
+        };
+        let exported = exporter.exported.clone();
+        let layer = crate::new_opentelemetry_layer_with_data_loss_interval(
+            "test_svc_name",
+            Box::new(exporter),
+            Config::default(),
+            data_loss_interval,
+        );
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        with_timeout(move || tracing::subscriber::with_default(subscriber, root));
+
+        let exported = exported.lock().unwrap();
+        exported.clone()
+    }
+
+    #[test]
+    fn reports_data_loss_if_configured() {
+        let exported = exported_after_export_error(None);
+        let names: Vec<_> = exported.iter().map(|span| span.name.as_str()).collect();
+        assert_eq!(names, vec!["root"]);
+
+        let exported = exported_after_export_error(Some(Duration::from_millis(0)));
+        let names: Vec<_> = exported.iter().map(|span| span.name.as_str()).collect();
+        assert_eq!(names, vec!["root", DATA_LOSS_RECORD_NAME]);
+        // reported under the layer's service
+        assert!(exported[1]
+            .resource
+            .iter()
+            .any(|(key, value)| key.as_str() == "service.name"
+                && *value == opentelemetry::api::Value::String("test_svc_name".to_string())));
+    }
+
+    #[test]
+    fn drops_events_after_shutdown() {
+        let layer = new_opentelemetry_layer(
+            "test_svc_name",
+            Box::new(MockExporter::default()),
+            Config::default(),
+        );
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        with_timeout(move || {
+            tracing::subscriber::with_default(subscriber, || {
+                crate::shutdown_dist_telemetry(Duration::from_secs(1)).unwrap();
+                root();
+                let stats = crate::dist_telemetry_stats().unwrap();
+                assert_eq!(stats.backend.pending, 0);
+                // two spans, each with an event
+                assert_eq!(stats.backend.dropped_shut_down, 4);
+            })
+        });
     }
 }
@@ -4,17 +4,18 @@ use opentelemetry::api::trace::{
     self,
     span_context::{SpanContext, SpanId, TraceId},
 };
-use opentelemetry::api::SpanKind;
 use opentelemetry::exporter::trace::SpanData;
 use opentelemetry::sdk::trace::evicted_hash_map::EvictedHashMap;
 use opentelemetry::sdk::trace::evicted_queue::EvictedQueue;
 use opentelemetry::sdk::Resource;
+use rand::Rng;
 use std::fmt;
 use std::sync::Arc;
+use std::time::SystemTime;
 use tracing::field::{Field, Visit};
-use tracing_distributed::{Event, Span};
+use tracing_distributed::{DataLoss, Event, Span, SpanKind, SpanStatus, DATA_LOSS_RECORD_NAME};
 
-/// PROBLEM: need 'opentelemetry::sdk::trace::config::Config' for 'max_events_per_span' value
+// PROBLEM: need 'opentelemetry::sdk::trace::config::Config' for 'max_events_per_span' value
 
 /// Visitor that builds honeycomb-compatible values from tracing fields.
 #[derive(Debug)]
@@ -71,33 +72,156 @@ pub(crate) fn event_to_values(
     }
 }
 
+// synthetic span describing data lost by the exporter, which is the root of its own trace
+pub(crate) fn data_loss_to_values(
+    loss: DataLoss,
+    service_name: &str,
+    max_attributes_per_span: u32,
+) -> SpanData {
+    let mut attributes = EvictedHashMap::new(max_attributes_per_span);
+    attributes.insert(KeyValue::new("span.service_name", service_name.to_string()));
+    attributes.insert(KeyValue::new("dropped.total", loss.total()));
+    attributes.insert(KeyValue::new("dropped.buffer_full", loss.buffer_full));
+    attributes.insert(KeyValue::new("dropped.export_errors", loss.export_errors));
+
+    let mut rng = rand::thread_rng();
+    let end_time = SystemTime::now();
+    SpanData {
+        span_context: SpanContext::new(
+            TraceId::from_u128(rng.gen()),
+            SpanId::from_u64(rng.gen()),
+            0,
+            false,
+        ),
+        parent_span_id: SpanId::invalid(),
+        span_kind: opentelemetry::api::SpanKind::Internal,
+        name: DATA_LOSS_RECORD_NAME.to_string(),
+        // covers the period during which the data was lost
+        start_time: end_time - loss.period,
+        end_time,
+        attributes,
+        message_events: EvictedQueue::new(0),
+        links: EvictedQueue::new(0),
+        status_code: trace::span::StatusCode::OK,
+        status_message: String::new(),
+        resource: to_resource(&tracing_distributed::Resource::new(), service_name),
+    }
+}
+
+// events that are not part of any trace are exported as zero-duration root spans of their own trace
+pub(crate) fn orphan_event_to_values(
+    event: Event<OpenTelemetryVisitor, SpanId, TraceId>,
+) -> SpanData {
+    let mut attributes = event.values.0;
+
+    attributes.insert(KeyValue::new("span.level", event.meta.level().to_string()));
+    attributes.insert(KeyValue::new(
+        "span.service_name",
+        event.service_name.to_string(),
+    ));
+    attributes.insert(KeyValue::new("span.target", event.meta.target()));
+
+    let resource = to_resource(&event.resource, &event.service_name);
+    let span_id = SpanId::from_u64(rand::thread_rng().gen());
+    SpanData {
+        span_context: SpanContext::new(event.trace_id, span_id, 0, false),
+        parent_span_id: SpanId::invalid(),
+        span_kind: opentelemetry::api::SpanKind::Internal,
+        name: event.meta.name().to_string(),
+        start_time: event.initialized_at,
+        end_time: event.initialized_at,
+        attributes,
+        message_events: EvictedQueue::new(0),
+        links: EvictedQueue::new(0),
+        status_code: trace::span::StatusCode::OK,
+        status_message: String::new(),
+        resource,
+    }
+}
+
+// the resource of a span, which always includes its service name
+fn to_resource(resource: &tracing_distributed::Resource, service_name: &str) -> Arc<Resource> {
+    Arc::new(Resource::new(
+        resource
+            .iter()
+            .map(|(key, value)| KeyValue::new(key.to_string(), value.to_string()))
+            .chain(std::iter::once(KeyValue::new(
+                "service.name",
+                service_name.to_string(),
+            ))),
+    ))
+}
+
 pub(crate) fn span_to_values(
     span: Span<OpenTelemetryVisitor, SpanId, TraceId>,
     events: EvictedQueue<trace::event::Event>,
+    max_links_per_span: u32,
 ) -> SpanData {
     let mut attributes = span.values.0;
 
     attributes.insert(KeyValue::new("span.level", span.meta.level().to_string()));
 
-    attributes.insert(KeyValue::new("span.service_name", span.service_name));
+    attributes.insert(KeyValue::new(
+        "span.service_name",
+        span.service_name.to_string(),
+    ));
 
     attributes.insert(KeyValue::new("span.target", span.meta.target()));
 
+    attributes.insert(KeyValue::new(
+        "span.busy_ms",
+        span.busy.as_secs_f64() * 1000.0,
+    ));
+    attributes.insert(KeyValue::new(
+        "span.idle_ms",
+        span.idle.as_secs_f64() * 1000.0,
+    ));
+    attributes.insert(KeyValue::new("span.enter_count", span.enter_count));
+
+    for (key, value) in span.baggage.iter() {
+        attributes.insert(KeyValue::new(format!("baggage.{}", key), value.to_string()));
+    }
+
+    let span_kind = match span.kind {
+        SpanKind::Internal => opentelemetry::api::SpanKind::Internal,
+        SpanKind::Server => opentelemetry::api::SpanKind::Server,
+        SpanKind::Client => opentelemetry::api::SpanKind::Client,
+        SpanKind::Producer => opentelemetry::api::SpanKind::Producer,
+        SpanKind::Consumer => opentelemetry::api::SpanKind::Consumer,
+    };
+
+    let (status_code, status_message) = match span.status {
+        SpanStatus::Ok => (trace::span::StatusCode::OK, String::new()),
+        SpanStatus::Error(message) => (trace::span::StatusCode::Unknown, message),
+    };
+
+    let mut links = EvictedQueue::new(max_links_per_span);
+    links.append_vec(
+        &mut span
+            .follows_from
+            .into_iter()
+            .map(|(trace_id, span_id)| {
+                trace::link::Link::new(SpanContext::new(trace_id, span_id, 0, false), Vec::new())
+            })
+            .collect(),
+    );
+
+    let resource = to_resource(&span.resource, &span.service_name);
+
     // TODO: traceflags 0? Is that no flags? hope so
     // TODO: examine use of is_remote
     SpanData {
         span_context: SpanContext::new(span.trace_id, span.id, 0, false),
         parent_span_id: span.parent_id.unwrap_or_else(SpanId::invalid), // idea: invalid == no parent, for root span
-        span_kind: SpanKind::Internal,
+        span_kind,
         name: span.meta.name().to_string(),
         start_time: span.initialized_at,
         end_time: span.completed_at,
         attributes,
         message_events: events,
-        links: EvictedQueue::new(0),
-        status_code: opentelemetry::api::trace::span::StatusCode::OK, // TODO: not sure how to get this from tracing
-        status_message: "".to_string(), // FIXME/TODO: put something useful here
-        // TODO/FIXME: figure out a way to get global info (eg service name) in shared resource
-        resource: Arc::new(Resource::new(std::iter::empty())),
+        links,
+        status_code,
+        status_message,
+        resource,
     }
 }